@@ -7,37 +7,91 @@ pub use iterator::BlockIterator;
 
 pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
 
+/// The largest value an entry can hold, as its length is encoded in a u16.
+pub const MAX_VALUE_SIZE: usize = u16::MAX as usize;
+
+/// Set in the flags of a block when the expiry timestamps (u64, 0 if the entry does not expire) of its entries sit
+/// between the entries and the offsets.
+pub(crate) const BLOCK_EXPIRES: u8 = 1;
+
+/// How a block is encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// Entries, offsets and number of entries, as written before SSTs recorded their format.
+    Legacy,
+    /// Like `Legacy`, followed by a flags byte.
+    Flagged,
+}
+
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
 pub struct Block {
     pub(crate) data: Vec<u8>,
     pub(crate) offsets: Vec<u16>,
+    /// The expiry timestamps of the entries, or empty if none of them expires.
+    pub(crate) expiries: Vec<u64>,
+    pub(crate) format: BlockFormat,
 }
 
 impl Block {
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
+        for expire_at in &self.expiries {
+            buf.put_u64(*expire_at);
+        }
         let offsets_len = self.offsets.len();
         for offset in &self.offsets {
             buf.put_u16(*offset);
         }
         // Adds number of elements at the end of the block
         buf.put_u16(offsets_len as u16);
+        if self.format == BlockFormat::Flagged {
+            buf.put_u8(if self.expiries.is_empty() {
+                0
+            } else {
+                BLOCK_EXPIRES
+            });
+        }
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
+        Self::decode_with_format(data, BlockFormat::Flagged)
+    }
+
+    /// Decode a block whose entries are encoded in `format`.
+    pub fn decode_with_format(data: &[u8], format: BlockFormat) -> Self {
+        let (data, flags) = match format {
+            BlockFormat::Legacy => (data, 0),
+            BlockFormat::Flagged => (&data[..data.len() - 1], data[data.len() - 1]),
+        };
         // get number of elements in the block
         let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
+        let offsets_begin = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
+        let offsets_raw = &data[offsets_begin..data.len() - SIZEOF_U16];
         // get offset array
         let offsets = offsets_raw
             .chunks(SIZEOF_U16)
             .map(|mut x| x.get_u16())
             .collect();
+        // get expiry timestamps
+        let (data_end, expiries) = if flags & BLOCK_EXPIRES != 0 {
+            let data_end = offsets_begin - entry_offsets_len * std::mem::size_of::<u64>();
+            let expiries = data[data_end..offsets_begin]
+                .chunks(std::mem::size_of::<u64>())
+                .map(|mut x| x.get_u64())
+                .collect();
+            (data_end, expiries)
+        } else {
+            (offsets_begin, Vec::new())
+        };
         // retrieve data
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Self {
+            data,
+            offsets,
+            expiries,
+            format,
+        }
     }
 }
@@ -2,7 +2,7 @@ use bytes::BufMut;
 
 use crate::key::{KeySlice, KeyVec};
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, BlockFormat, MAX_VALUE_SIZE, SIZEOF_U16};
 
 /// Builds a block.
 pub struct BlockBuilder {
@@ -10,6 +10,8 @@ pub struct BlockBuilder {
     offsets: Vec<u16>,
     /// All serialized key-value pairs in the block.
     data: Vec<u8>,
+    /// Expiry timestamps of each key-value entries, or empty if none of them expires.
+    expiries: Vec<u64>,
     /// The expected block size.
     block_size: usize,
     /// The first key in the block
@@ -36,6 +38,7 @@ impl BlockBuilder {
         Self {
             offsets: Vec::new(),
             data: Vec::new(),
+            expiries: Vec::new(),
             block_size,
             first_key: KeyVec::new(),
         }
@@ -44,13 +47,34 @@ impl BlockBuilder {
     fn estimated_size(&self) -> usize {
         SIZEOF_U16 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U16 /* offsets */ + self.data.len()
         // key-value pairs
+        + self.expiries.len() * std::mem::size_of::<u64>() /* expiry timestamps */ + 1
+        /* flags */
     }
 
     /// Adds a key-value pair to the block. Returns false when the block is full.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_with_expiry(key, value, None)
+    }
+
+    /// Adds a key-value pair that expires at `expire_at` (unix time in milliseconds) to the block.
+    /// Returns false when the block is full.
+    #[must_use]
+    pub fn add_with_expiry(&mut self, key: KeySlice, value: &[u8], expire_at: Option<u64>) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
+        assert!(
+            value.len() <= MAX_VALUE_SIZE,
+            "value must not be larger than {} bytes",
+            MAX_VALUE_SIZE
+        );
+        let expiries_len = if expire_at.is_some() && self.expiries.is_empty() {
+            (self.offsets.len() + 1) * std::mem::size_of::<u64>()
+        } else if !self.expiries.is_empty() {
+            std::mem::size_of::<u64>()
+        } else {
+            0
+        };
+        if self.estimated_size() + key.raw_len() + value.len() + expiries_len + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
             && !self.is_empty()
         {
             return false;
@@ -70,6 +94,12 @@ impl BlockBuilder {
         self.data.put_u16(value.len() as u16);
         // Encode value content.
         self.data.put(value);
+        // Record expiry timestamp.
+        if expire_at.is_some() || !self.expiries.is_empty() {
+            // the first expiring entry gives all entries an expiry timestamp
+            self.expiries.resize(self.offsets.len() - 1, 0);
+            self.expiries.push(expire_at.unwrap_or(0));
+        }
 
         if self.first_key.is_empty() {
             self.first_key = key.to_key_vec();
@@ -91,6 +121,8 @@ impl BlockBuilder {
         Block {
             data: self.data,
             offsets: self.offsets,
+            expiries: self.expiries,
+            format: BlockFormat::Flagged,
         }
     }
 }
@@ -17,6 +17,8 @@ pub struct BlockIterator {
     key: KeyVec,
     /// the current value range in the block.data, corresponds to the current key
     value_range: (usize, usize),
+    /// the expiry timestamp of the current entry, if any
+    expire_at: Option<u64>,
     /// the current index at the iterator position
     idx: usize,
     /// the first key in the block
@@ -41,6 +43,7 @@ impl BlockIterator {
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
+            expire_at: None,
             idx: 0,
         }
     }
@@ -71,6 +74,12 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the expiry timestamp of the current entry.
+    pub fn expire_at(&self) -> Option<u64> {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.expire_at
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -86,11 +95,12 @@ impl BlockIterator {
         if idx >= self.block.offsets.len() {
             self.key.clear();
             self.value_range = (0, 0);
+            self.expire_at = None;
             return;
         }
         let offset = self.block.offsets[idx] as usize;
-        self.seek_to_offset(offset);
         self.idx = idx;
+        self.seek_to_offset(offset);
     }
 
     /// Move to the next key in the block.
@@ -121,6 +131,12 @@ impl BlockIterator {
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
         entry.advance(value_len);
+        self.expire_at = self
+            .block
+            .expiries
+            .get(self.idx)
+            .copied()
+            .filter(|expire_at| *expire_at != 0);
     }
 
     /// Seek to the first key that is >= `key`.
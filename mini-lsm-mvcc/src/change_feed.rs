@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+
+use crate::lsm_storage::{unix_time_millis, WriteBatchRecord};
+use crate::mem_table::MemTable;
+
+/// A committed write batch and its commit timestamp.
+pub type Change = (u64, Vec<WriteBatchRecord<Bytes>>);
+
+struct ChangeFeedInner {
+    /// The retained changes, in commit timestamp order.
+    history: VecDeque<Change>,
+    /// Changes committed at or below this timestamp may be missing from the history.
+    dropped_up_to: u64,
+    subscribers: Vec<Sender<Change>>,
+}
+
+/// Publishes committed write batches to subscribers, keeping the latest `retention` of them so that new subscribers
+/// can start from an earlier commit.
+pub(crate) struct ChangeFeed {
+    inner: Mutex<ChangeFeedInner>,
+    retention: usize,
+}
+
+impl ChangeFeed {
+    /// Create a feed retaining `history`, whose changes all come after `dropped_up_to`.
+    pub(crate) fn new(retention: usize, dropped_up_to: u64, history: Vec<Change>) -> Self {
+        let mut inner = ChangeFeedInner {
+            history: history.into(),
+            dropped_up_to,
+            subscribers: Vec::new(),
+        };
+        Self::trim(&mut inner, retention);
+        Self {
+            inner: Mutex::new(inner),
+            retention,
+        }
+    }
+
+    fn trim(inner: &mut ChangeFeedInner, retention: usize) {
+        while inner.history.len() > retention {
+            let (ts, _) = inner.history.pop_front().unwrap();
+            inner.dropped_up_to = ts;
+        }
+    }
+
+    /// Publish a batch committed at `ts`. Must be called in commit timestamp order.
+    pub(crate) fn publish<T: AsRef<[u8]>>(&self, ts: u64, batch: &[WriteBatchRecord<T>]) {
+        let mut inner = self.inner.lock();
+        if self.retention == 0 && inner.subscribers.is_empty() {
+            inner.dropped_up_to = ts;
+            return;
+        }
+        let records = batch
+            .iter()
+            .map(|record| match record {
+                WriteBatchRecord::Put(key, value) => WriteBatchRecord::Put(
+                    Bytes::copy_from_slice(key.as_ref()),
+                    Bytes::copy_from_slice(value.as_ref()),
+                ),
+                WriteBatchRecord::PutWithTtl(key, value, ttl) => WriteBatchRecord::PutWithTtl(
+                    Bytes::copy_from_slice(key.as_ref()),
+                    Bytes::copy_from_slice(value.as_ref()),
+                    *ttl,
+                ),
+                WriteBatchRecord::Del(key) => {
+                    WriteBatchRecord::Del(Bytes::copy_from_slice(key.as_ref()))
+                }
+            })
+            .collect::<Vec<_>>();
+        // drop the subscribers whose receivers are gone
+        inner
+            .subscribers
+            .retain(|subscriber| subscriber.send((ts, records.clone())).is_ok());
+        inner.history.push_back((ts, records));
+        Self::trim(&mut inner, self.retention);
+    }
+
+    /// Replay the retained changes committed at or after `from_ts`, then follow new commits.
+    pub(crate) fn subscribe(&self, from_ts: u64) -> Result<Receiver<Change>> {
+        let mut inner = self.inner.lock();
+        if inner.dropped_up_to > 0 && from_ts <= inner.dropped_up_to {
+            bail!(
+                "changes from ts {} are no longer retained, the oldest available ts is {}",
+                from_ts,
+                inner.dropped_up_to + 1
+            );
+        }
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for change in inner.history.iter().filter(|(ts, _)| *ts >= from_ts) {
+            tx.send(change.clone()).unwrap();
+        }
+        inner.subscribers.push(tx);
+        Ok(rx)
+    }
+}
+
+/// Rebuild the changes held by `memtables`, e.g., the ones recovered from the WAL. The TTL of a key is what remains
+/// of it now, as the original one is not stored.
+pub(crate) fn changes_from_memtables(memtables: &[Arc<MemTable>]) -> Vec<Change> {
+    let now = unix_time_millis();
+    let mut changes = BTreeMap::<u64, Vec<WriteBatchRecord<Bytes>>>::new();
+    for memtable in memtables {
+        for entry in memtable.map.iter() {
+            let key = Bytes::copy_from_slice(entry.key().key_ref());
+            let (value, expire_at) = entry.value();
+            let record = match expire_at {
+                _ if value.is_empty() => WriteBatchRecord::Del(key),
+                Some(expire_at) => WriteBatchRecord::PutWithTtl(
+                    key,
+                    value.clone(),
+                    Duration::from_millis(expire_at.saturating_sub(now)),
+                ),
+                None => WriteBatchRecord::Put(key, value.clone()),
+            };
+            changes.entry(entry.key().ts()).or_default().push(record);
+        }
+    }
+    changes.into_iter().collect()
+}
@@ -1,26 +1,49 @@
+mod fifo;
+mod lazy_leveled;
 mod leveled;
+mod partitioner;
+mod running;
 mod simple_leveled;
+mod sst_selection;
+mod stats;
 mod tiered;
 
 use std::collections::HashSet;
-use std::sync::Arc;
-use std::time::Duration;
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+pub use fifo::{FifoCompactionController, FifoCompactionOptions, FifoCompactionTask};
+pub use lazy_leveled::{
+    LazyLeveledCompactionController, LazyLeveledCompactionOptions, LazyLeveledCompactionTask,
+};
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+pub use partitioner::{BoundarySstPartitioner, FixedPrefixSstPartitioner, SstPartitioner};
+pub use running::RunningCompactions;
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
+pub use sst_selection::{
+    MinOverlapFirst, MostTombstonesFirst, OldestFirst, RoundRobin, SstSelection, SstSelectionPolicy,
+};
+pub use stats::{CompactionStats, LevelCompactionStats};
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
 
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
-use crate::manifest::ManifestRecord;
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{
+    range_overlap, unix_time_millis, CompactionDecision, LsmStorageInner, LsmStorageState,
+};
+use crate::manifest::{ManifestEdit, ManifestRecord};
+use crate::rate_limiter::IoPriority;
+use crate::scheduler::{BackgroundEvent, JobPriority};
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +51,8 @@ pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
     Simple(SimpleLeveledCompactionTask),
+    Fifo(FifoCompactionTask),
+    LazyLeveled(LazyLeveledCompactionTask),
     ForceFullCompaction {
         l0_sstables: Vec<usize>,
         l1_sstables: Vec<usize>,
@@ -35,35 +60,116 @@ pub enum CompactionTask {
 }
 
 impl CompactionTask {
+    /// The ids of the SSTs compacted by the task.
+    pub fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(task) => task
+                .upper_level_sst_ids
+                .iter()
+                .chain(&task.lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Simple(task) => task
+                .upper_level_sst_ids
+                .iter()
+                .chain(&task.lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, ssts)| ssts)
+                .copied()
+                .collect(),
+            CompactionTask::Fifo(task) => task.sst_ids.clone(),
+            CompactionTask::LazyLeveled(task) => task
+                .runs
+                .iter()
+                .flat_map(|(_, ssts)| ssts)
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// The level the output of the task is written to. Full compaction writes to L1, and tiered and lazy leveled
+    /// compaction to the position of the first merged sorted run in `snapshot`, where the newest run is 1.
+    fn output_level(&self, snapshot: &LsmStorageState) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(TieredCompactionTask { tiers: runs, .. })
+            | CompactionTask::LazyLeveled(LazyLeveledCompactionTask { runs, .. }) => {
+                snapshot
+                    .levels
+                    .iter()
+                    .position(|(tier_id, _)| *tier_id == runs[0].0)
+                    .unwrap()
+                    + 1
+            }
+            CompactionTask::Fifo(_) => 0,
+        }
+    }
+
     fn compact_to_bottom_level(&self) -> bool {
         match self {
             CompactionTask::ForceFullCompaction { .. } => true,
             CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
             CompactionTask::Simple(task) => task.is_lower_level_bottom_level,
             CompactionTask::Tiered(task) => task.bottom_tier_included,
+            CompactionTask::Fifo(_) => false,
+            CompactionTask::LazyLeveled(task) => task.bottom_run_included,
         }
     }
 }
 
+/// Whether the key ranges of `sst_ids` are disjoint, so that they can be put into a level as they are.
+pub(crate) fn is_non_overlapping(snapshot: &LsmStorageState, sst_ids: &[usize]) -> bool {
+    let mut ssts = sst_ids
+        .iter()
+        .map(|id| &snapshot.sstables[id])
+        .collect::<Vec<_>>();
+    ssts.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+    ssts.windows(2)
+        .all(|pair| pair[0].last_key() < pair[1].first_key())
+}
+
 pub(crate) enum CompactionController {
     Leveled(LeveledCompactionController),
     Tiered(TieredCompactionController),
     Simple(SimpleLeveledCompactionController),
+    Fifo(FifoCompactionController),
+    LazyLeveled(LazyLeveledCompactionController),
     NoCompaction,
 }
 
 impl CompactionController {
-    pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
+    /// Generate a task that does not conflict with the `running` ones.
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, running)
                 .map(CompactionTask::Leveled),
             CompactionController::Simple(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, running)
                 .map(CompactionTask::Simple),
             CompactionController::Tiered(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, running)
                 .map(CompactionTask::Tiered),
+            CompactionController::Fifo(ctrl) => ctrl
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(CompactionTask::Fifo),
+            CompactionController::LazyLeveled(ctrl) => ctrl
+                .generate_compaction_task_excluding(snapshot, running)
+                .map(CompactionTask::LazyLeveled),
             CompactionController::NoCompaction => unreachable!(),
         }
     }
@@ -85,21 +191,69 @@ impl CompactionController {
             (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
+            (CompactionController::Fifo(ctrl), CompactionTask::Fifo(task)) => {
+                ctrl.apply_compaction_result(snapshot, task)
+            }
+            (CompactionController::LazyLeveled(ctrl), CompactionTask::LazyLeveled(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
             _ => unreachable!(),
         }
     }
 }
 
 impl CompactionController {
+    /// Whether `task` can be done by moving the upper level SSTs into the lower level as they are, without rewriting
+    /// them.
+    pub fn is_trivial_move(&self, snapshot: &LsmStorageState, task: &CompactionTask) -> bool {
+        match (self, task) {
+            (CompactionController::Leveled(ctrl), CompactionTask::Leveled(task)) => {
+                ctrl.is_trivial_move(snapshot, task)
+            }
+            (CompactionController::Simple(ctrl), CompactionTask::Simple(task)) => {
+                ctrl.is_trivial_move(snapshot, task)
+            }
+            _ => false,
+        }
+    }
+
+    /// The SSTs a trivial move of `task` puts into the lower level, sorted by key like the rest of the level. The upper
+    /// level SSTs of a task from L0 are ordered from the newest to the oldest instead.
+    pub fn trivial_move_output(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Vec<usize> {
+        let mut output = match task {
+            CompactionTask::Leveled(task) => task.upper_level_sst_ids.clone(),
+            CompactionTask::Simple(task) => task.upper_level_sst_ids.clone(),
+            _ => unreachable!(),
+        };
+        output.sort_by(|a, b| {
+            snapshot.sstables[a]
+                .first_key()
+                .cmp(snapshot.sstables[b].first_key())
+        });
+        output
+    }
+
+    /// When the passing time alone may trigger the next compaction.
+    pub fn next_time_triggered_compaction(&self, snapshot: &LsmStorageState) -> Option<SystemTime> {
+        match self {
+            CompactionController::Fifo(ctrl) => ctrl.next_expiration(snapshot),
+            _ => None,
+        }
+    }
+
     pub fn flush_to_l0(&self) -> bool {
         matches!(
             self,
-            Self::Leveled(_) | Self::Simple(_) | Self::NoCompaction
+            Self::Leveled(_) | Self::Simple(_) | Self::Fifo(_) | Self::NoCompaction
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CompactionOptions {
     /// Leveled compaction with partial compaction + dynamic level support (= RocksDB's Leveled
     /// Compaction)
@@ -108,23 +262,128 @@ pub enum CompactionOptions {
     Tiered(TieredCompactionOptions),
     /// Simple leveled compaction
     Simple(SimpleLeveledCompactionOptions),
+    /// FIFO compaction, which drops the oldest L0 SSTs without rewriting any data (= RocksDB's FIFO compaction)
+    Fifo(FifoCompactionOptions),
+    /// Lazy leveling, with tiered upper levels and a single sorted run in the bottom level (= Dostoevsky's lazy
+    /// leveling)
+    LazyLeveled(LazyLeveledCompactionOptions),
     /// In no compaction mode (week 1), always flush to L0
     NoCompaction,
 }
 
+/// A compaction job registered in `LsmStorageInner::running_compactions`, which is released when dropped.
+struct RegisteredCompaction {
+    storage: Arc<LsmStorageInner>,
+    job_id: usize,
+}
+
+impl Drop for RegisteredCompaction {
+    fn drop(&mut self) {
+        self.storage.release_compaction(self.job_id);
+    }
+}
+
+/// A background flush counted in `LsmStorageInner::running_flushes` until dropped.
+struct RunningFlush<'a> {
+    storage: &'a LsmStorageInner,
+}
+
+impl<'a> RunningFlush<'a> {
+    fn new(storage: &'a LsmStorageInner) -> Self {
+        storage.running_flushes.fetch_add(1, Ordering::SeqCst);
+        Self { storage }
+    }
+}
+
+impl Drop for RunningFlush<'_> {
+    fn drop(&mut self) {
+        self.storage.running_flushes.fetch_sub(1, Ordering::SeqCst);
+        // notify while holding the lock, so that a pause cannot miss it between checking and waiting
+        let _running = self.storage.running_compactions.lock();
+        self.storage.background_job_finished.notify_all();
+    }
+}
+
+/// The SSTs written by a compaction that are not part of the state yet. Their files are deleted when dropped, unless
+/// the SSTs are taken out with `finish`.
+struct CompactionOutput<'a> {
+    storage: &'a LsmStorageInner,
+    ssts: Vec<Arc<SsTable>>,
+}
+
+impl CompactionOutput<'_> {
+    /// Build the next SST of the output. Its file is deleted if that fails.
+    fn build(&mut self, builder: SsTableBuilder) -> Result<()> {
+        let sst_id = self.storage.next_sst_id();
+        let path = self.storage.path_of_sst(sst_id);
+        match builder.build_with_rate_limiter(
+            sst_id,
+            Some(self.storage.block_cache.clone()),
+            &path,
+            self.storage.rate_limiter_for(IoPriority::Low),
+        ) {
+            Ok(sst) => {
+                self.ssts.push(Arc::new(sst));
+                Ok(())
+            }
+            Err(err) => {
+                std::fs::remove_file(&path).ok();
+                Err(err)
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<Arc<SsTable>> {
+        std::mem::take(&mut self.ssts)
+    }
+}
+
+impl Drop for CompactionOutput<'_> {
+    fn drop(&mut self) {
+        // a file that cannot be deleted is moved aside as an unknown SST on the next open
+        for sst in &self.ssts {
+            std::fs::remove_file(self.storage.path_of_sst(sst.sst_id())).ok();
+        }
+    }
+}
+
 impl LsmStorageInner {
+    /// Write the entries of `iter` below `upper` into new SSTs. The SSTs written so far are deleted if it fails.
     fn compact_generate_sst_from_iter(
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
         compact_to_bottom_level: bool,
+        output_level: usize,
+        grandparents: &[Arc<SsTable>],
+        upper: Option<&[u8]>,
+        stats: &mut LevelCompactionStats,
     ) -> Result<Vec<Arc<SsTable>>> {
         let mut builder = None;
-        let mut new_sst = Vec::new();
+        let mut new_sst = CompactionOutput {
+            storage: self,
+            ssts: Vec::new(),
+        };
         let watermark = self.mvcc().watermark();
+        let now = unix_time_millis();
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
-        'outer: while iter.is_valid() {
+        let sst_partitioner = self.sst_partitioner.lock().clone();
+        // the last user key written to the output
+        let mut last_output_key = Vec::<u8>::new();
+        // the grandparent SSTs before `grandparent_idx` end before the current key, and the current output SST
+        // overlaps `grandparent_overlap` bytes of them
+        let mut grandparent_idx = 0;
+        let mut grandparent_overlap = 0;
+        while iter.is_valid() {
+            if self.is_background_work_paused() {
+                bail!("compaction cancelled as background work is paused");
+            }
+            if let Some(upper) = upper {
+                if iter.key().key_ref() >= upper {
+                    break;
+                }
+            }
             if builder.is_none() {
                 builder = Some(SsTableBuilder::new(self.options.block_size));
             }
@@ -134,11 +393,18 @@ impl LsmStorageInner {
                 first_key_below_watermark = true;
             }
 
+            // an expired version below the watermark is as good as a delete tombstone
+            let expired = match iter.expire_at() {
+                Some(expire_at) => iter.key().ts() <= watermark && expire_at <= now,
+                None => false,
+            };
+
             if compact_to_bottom_level
                 && !same_as_last_key
                 && iter.key().ts() <= watermark
-                && iter.value().is_empty()
+                && (iter.value().is_empty() || expired)
             {
+                stats.dropped_tombstones += 1;
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
                 iter.next()?;
@@ -146,62 +412,108 @@ impl LsmStorageInner {
                 continue;
             }
 
+            let mut removed = false;
+            let mut new_value = None;
             if iter.key().ts() <= watermark {
                 if same_as_last_key && !first_key_below_watermark {
+                    stats.dropped_versions += 1;
                     iter.next()?;
                     continue;
                 }
 
                 first_key_below_watermark = false;
 
-                if !compaction_filters.is_empty() {
+                if !expired && !iter.value().is_empty() {
                     for filter in &compaction_filters {
-                        match filter {
-                            CompactionFilter::Prefix(x) => {
-                                if iter.key().key_ref().starts_with(x) {
-                                    iter.next()?;
-                                    continue 'outer;
-                                }
+                        let value = new_value.as_deref().unwrap_or(iter.value());
+                        match filter.filter(
+                            iter.key().key_ref(),
+                            iter.key().ts(),
+                            value,
+                            output_level,
+                        ) {
+                            CompactionDecision::Keep => {}
+                            CompactionDecision::Remove => {
+                                removed = true;
+                                break;
                             }
+                            CompactionDecision::ChangeValue(value) => new_value = Some(value),
                         }
                     }
                 }
+
+                if removed && compact_to_bottom_level {
+                    // the older versions are skipped as they are below the watermark
+                    stats.dropped_versions += 1;
+                    last_key.clear();
+                    last_key.extend(iter.key().key_ref());
+                    iter.next()?;
+                    continue;
+                }
             }
 
             let builder_inner = builder.as_mut().unwrap();
 
-            if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
-                let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
-                let sst = Arc::new(old_builder.build(
-                    sst_id,
-                    Some(self.block_cache.clone()),
-                    self.path_of_sst(sst_id),
-                )?);
-                new_sst.push(sst);
+            let mut overlap_exceeded = false;
+            if !same_as_last_key {
+                while let Some(grandparent) = grandparents.get(grandparent_idx) {
+                    if grandparent.last_key().key_ref() >= iter.key().key_ref() {
+                        break;
+                    }
+                    if !builder_inner.is_empty() {
+                        grandparent_overlap += grandparent.table_size();
+                    }
+                    grandparent_idx += 1;
+                }
+                if let Some(max_overlap) = self.options.max_grandparent_overlap_bytes {
+                    overlap_exceeded = grandparent_overlap > max_overlap as u64;
+                }
+            }
+            let partition = match &sst_partitioner {
+                Some(partitioner) if !same_as_last_key && !builder_inner.is_empty() => {
+                    partitioner.should_partition(&last_output_key, iter.key().key_ref())
+                }
+                _ => false,
+            };
+
+            if !same_as_last_key
+                && !builder_inner.is_empty()
+                && (builder_inner.estimated_size() >= self.options.target_sst_size
+                    || overlap_exceeded
+                    || partition)
+            {
+                grandparent_overlap = 0;
+                new_sst.build(builder.take().unwrap())?;
                 builder = Some(SsTableBuilder::new(self.options.block_size));
             }
 
             let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            if expired || removed {
+                // keep a tombstone so that older versions in lower levels stay hidden
+                builder_inner.add(iter.key(), &[]);
+            } else if let Some(value) = &new_value {
+                builder_inner.add_with_expiry(iter.key(), value, iter.expire_at());
+            } else {
+                builder_inner.add_with_expiry(iter.key(), iter.value(), iter.expire_at());
+            }
 
             if !same_as_last_key {
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
             }
+            if last_output_key != iter.key().key_ref() {
+                last_output_key.clear();
+                last_output_key.extend(iter.key().key_ref());
+            }
 
             iter.next()?;
         }
-        if let Some(builder) = builder {
-            let sst_id = self.next_sst_id(); // lock dropped here
-            let sst = Arc::new(builder.build(
-                sst_id,
-                Some(self.block_cache.clone()),
-                self.path_of_sst(sst_id),
-            )?);
-            new_sst.push(sst);
+        match builder {
+            // every key of the range may have been dropped
+            Some(builder) if !builder.is_empty() => new_sst.build(builder)?,
+            _ => {}
         }
-        Ok(new_sst)
+        Ok(new_sst.finish())
     }
 
     fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
@@ -209,26 +521,169 @@ impl LsmStorageInner {
             let state = self.state.read();
             state.clone()
         };
-        match task {
+        let start = Instant::now();
+        let (output, mut stats) = self.compact_subranges(&snapshot, task)?;
+        let input_sst_ids = task.input_sst_ids();
+        stats.num_compactions = 1;
+        stats.bytes_read = input_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum();
+        stats.bytes_written = output.iter().map(|sst| sst.table_size()).sum();
+        stats.input_files = input_sst_ids.len() as u64;
+        stats.output_files = output.len() as u64;
+        stats.compaction_time = start.elapsed();
+        *self
+            .compaction_stats
+            .lock()
+            .levels
+            .entry(task.output_level(&snapshot))
+            .or_default() += &stats;
+        Ok(output)
+    }
+
+    /// Run `task` as one or more subcompactions, returning the new SSTs and the entries they dropped.
+    fn compact_subranges(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Result<(Vec<Arc<SsTable>>, LevelCompactionStats)> {
+        let split_keys = self.subcompaction_split_keys(snapshot, task);
+        if split_keys.is_empty() {
+            return self.compact_subrange(snapshot, task, None, None);
+        }
+
+        let bounds = std::iter::once(None)
+            .chain(split_keys.iter().map(|key| Some(key.as_ref())))
+            .chain(std::iter::once(None))
+            .collect::<Vec<_>>();
+        println!("running {} subcompactions", bounds.len() - 1);
+        let results = std::thread::scope(|scope| {
+            let workers = bounds
+                .windows(2)
+                .map(|range| {
+                    scope.spawn(move || self.compact_subrange(snapshot, task, range[0], range[1]))
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        if results.iter().any(|result| result.is_err()) {
+            // the outputs of the other subcompactions will not be part of the state, and a file that cannot be
+            // deleted is moved aside as an unknown SST on the next open
+            for sst in results
+                .iter()
+                .flat_map(|result| result.iter().flat_map(|(ssts, _)| ssts))
+            {
+                std::fs::remove_file(self.path_of_sst(sst.sst_id())).ok();
+            }
+        }
+        let mut output = Vec::new();
+        let mut stats = LevelCompactionStats::default();
+        for result in results {
+            let (ssts, subrange_stats) = result?;
+            // the ranges are in key order, so are their outputs
+            output.extend(ssts);
+            stats += &subrange_stats;
+        }
+        Ok((output, stats))
+    }
+
+    /// Choose the keys to split `task` at, so that it runs as up to `max_subcompactions` subcompactions of disjoint
+    /// key ranges. Split keys are taken from the first keys of the blocks of the input SSTs, so that the ranges have
+    /// about the same number of blocks.
+    fn subcompaction_split_keys(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Vec<Bytes> {
+        let max_subcompactions = self.options.max_subcompactions;
+        if max_subcompactions <= 1 {
+            return Vec::new();
+        }
+        let mut block_keys = task
+            .input_sst_ids()
+            .iter()
+            .flat_map(|id| &snapshot.sstables[id].block_meta)
+            .map(|meta| Bytes::copy_from_slice(meta.first_key.key_ref()))
+            .collect::<Vec<_>>();
+        block_keys.sort();
+        block_keys.dedup();
+        if block_keys.len() < 2 {
+            return Vec::new();
+        }
+        let num_ranges = max_subcompactions.min(block_keys.len());
+        let mut split_keys = (1..num_ranges)
+            .map(|i| block_keys[i * block_keys.len() / num_ranges].clone())
+            .collect::<Vec<_>>();
+        // all versions of a key stay in one range, as the split keys are user keys
+        split_keys.dedup();
+        split_keys.retain(|key| key != &block_keys[0]);
+        split_keys
+    }
+
+    /// Compact the keys of `task` that are in `[lower, upper)`. A `None` bound is unbounded.
+    fn compact_subrange(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<(Vec<Arc<SsTable>>, LevelCompactionStats)> {
+        let output_level = task.output_level(snapshot);
+        let mut stats = LevelCompactionStats::default();
+        // the SSTs of the level below the output level, which tiered layouts do not have
+        let grandparents = match snapshot.levels.get(output_level) {
+            Some((_, ssts)) if self.compaction_controller.flush_to_l0() => ssts
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        let table_iter = |id: &usize| {
+            let table = snapshot.sstables[id].clone();
+            match lower {
+                Some(key) => SsTableIterator::create_and_seek_to_key(
+                    table,
+                    KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                ),
+                None => SsTableIterator::create_and_seek_to_first(table),
+            }
+            .map(Box::new)
+        };
+        let concat_iter = |ids: &[usize]| {
+            let tables = ids
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>();
+            match lower {
+                Some(key) => SstConcatIterator::create_and_seek_to_key(
+                    tables,
+                    KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                ),
+                None => SstConcatIterator::create_and_seek_to_first(tables),
+            }
+        };
+        let output = match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
                 l1_sstables,
             } => {
-                let mut l0_iters = Vec::with_capacity(l0_sstables.len());
-                for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
-                }
-                let mut l1_iters = Vec::with_capacity(l1_sstables.len());
-                for id in l1_sstables.iter() {
-                    l1_iters.push(snapshot.sstables.get(id).unwrap().clone());
-                }
+                let l0_iters = l0_sstables.iter().map(table_iter).collect::<Result<_>>()?;
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    concat_iter(l1_sstables)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(
+                    iter,
+                    task.compact_to_bottom_level(),
+                    output_level,
+                    &grandparents,
+                    upper,
+                    &mut stats,
+                )
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -244,56 +699,53 @@ impl LsmStorageInner {
                 lower_level_sst_ids,
                 ..
             }) => match upper_level {
-                Some(_) => {
-                    let mut upper_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
-                    let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
-                    self.compact_generate_sst_from_iter(
-                        TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
-                    )
-                }
+                Some(_) => self.compact_generate_sst_from_iter(
+                    TwoMergeIterator::create(
+                        concat_iter(upper_level_sst_ids)?,
+                        concat_iter(lower_level_sst_ids)?,
+                    )?,
+                    task.compact_to_bottom_level(),
+                    output_level,
+                    &grandparents,
+                    upper,
+                    &mut stats,
+                ),
                 None => {
-                    let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
-                    }
-                    let upper_iter = MergeIterator::create(upper_iters);
-                    let mut lower_ssts = Vec::with_capacity(lower_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let upper_iters = upper_level_sst_ids
+                        .iter()
+                        .map(table_iter)
+                        .collect::<Result<_>>()?;
                     self.compact_generate_sst_from_iter(
-                        TwoMergeIterator::create(upper_iter, lower_iter)?,
+                        TwoMergeIterator::create(
+                            MergeIterator::create(upper_iters),
+                            concat_iter(lower_level_sst_ids)?,
+                        )?,
                         task.compact_to_bottom_level(),
+                        output_level,
+                        &grandparents,
+                        upper,
+                        &mut stats,
                     )
                 }
             },
-            CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
-                let mut iters = Vec::with_capacity(tiers.len());
-                for (_, tier_sst_ids) in tiers {
-                    let mut ssts = Vec::with_capacity(tier_sst_ids.len());
-                    for id in tier_sst_ids.iter() {
-                        ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
-                }
+            CompactionTask::Tiered(TieredCompactionTask { tiers: runs, .. })
+            | CompactionTask::LazyLeveled(LazyLeveledCompactionTask { runs, .. }) => {
+                let iters = runs
+                    .iter()
+                    .map(|(_, tier_sst_ids)| concat_iter(tier_sst_ids).map(Box::new))
+                    .collect::<Result<_>>()?;
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
                     task.compact_to_bottom_level(),
+                    output_level,
+                    &grandparents,
+                    upper,
+                    &mut stats,
                 )
             }
-        }
+            CompactionTask::Fifo(_) => unreachable!("FIFO compaction does not rewrite SSTs"),
+        }?;
+        Ok((output, stats))
     }
 
     pub fn force_full_compaction(&self) -> Result<()> {
@@ -320,7 +772,8 @@ impl LsmStorageInner {
 
         {
             let state_lock = self.state_lock.lock();
-            let mut state = self.state.read().as_ref().clone();
+            let old_state = self.state.read().clone();
+            let mut state = old_state.as_ref().clone();
             for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
                 let result = state.sstables.remove(sst);
                 assert!(result.is_some());
@@ -340,12 +793,10 @@ impl LsmStorageInner {
                 .copied()
                 .collect::<Vec<_>>();
             assert!(l0_sstables_map.is_empty());
+            let edit = ManifestEdit::between(&old_state, &state);
             *self.state.write() = Arc::new(state);
             self.sync_dir()?;
-            self.manifest.as_ref().unwrap().add_record(
-                &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
-            )?;
+            self.add_manifest_record(&state_lock, ManifestRecord::Compaction(edit))?;
         }
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
             std::fs::remove_file(self.path_of_sst(*sst))?;
@@ -356,48 +807,257 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
+    /// Compact the keys in the range down to `target_level`, one level at a time starting from L0, so that no level
+    /// above it holds any of them afterwards. The memtables are flushed first. Tombstones and keys removed by the
+    /// compaction filters are dropped when `target_level` is the bottom level. With tiered and lazy leveled compaction,
+    /// the sorted runs from the newest one down to the oldest one holding a key in the range are merged, and
+    /// `target_level` is ignored.
+    pub fn compact_range(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        target_level: usize,
+    ) -> Result<()> {
+        match &self.options.compaction_options {
+            CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
+            | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => {
+                if target_level == 0 || target_level > *max_levels {
+                    bail!(
+                        "target level {} out of range, expected 1 to {}",
+                        target_level,
+                        max_levels
+                    );
+                }
+            }
+            CompactionOptions::Tiered(_) | CompactionOptions::LazyLeveled(_) => {}
+            CompactionOptions::Fifo(_) | CompactionOptions::NoCompaction => {
+                bail!(
+                    "range compaction requires leveled, simple leveled, tiered or lazy leveled compaction"
+                )
+            }
+        }
+
+        if !self.state.read().memtable.is_empty() {
+            self.force_freeze_memtable(&self.state_lock.lock())?;
+        }
+        while !self.state.read().imm_memtables.is_empty() {
+            self.force_flush_next_imm_memtable()?;
+        }
+
+        if !self.compaction_controller.flush_to_l0() {
+            return self.run_manual_compaction(|snapshot| {
+                self.sorted_runs_range_compaction_task(snapshot, lower, upper)
+            });
+        }
+        for level in 0..target_level {
+            self.run_manual_compaction(|snapshot| {
+                self.range_compaction_task(snapshot, level, lower, upper)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The task compacting the SSTs of `level` (0 is L0) that hold keys in the range into the next level.
+    fn range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        level: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<CompactionTask> {
+        let in_range = |id: &&usize| {
+            let sst = &snapshot.sstables[*id];
+            range_overlap(
+                lower,
+                upper,
+                sst.first_key().as_key_slice(),
+                sst.last_key().as_key_slice(),
+            )
+        };
+        let ssts = match level {
+            0 => &snapshot.l0_sstables,
+            _ => &snapshot.levels[level - 1].1,
+        };
+        if !ssts.iter().any(|id| in_range(&id)) {
+            return None;
+        }
+        let upper_level = if level == 0 { None } else { Some(level) };
+        let is_lower_level_bottom_level = level + 1 == snapshot.levels.len();
+        match &self.compaction_controller {
+            CompactionController::Leveled(ctrl) => {
+                // L0 SSTs overlap each other, so they are compacted together
+                let upper_level_sst_ids = match level {
+                    0 => ssts.clone(),
+                    _ => ssts.iter().filter(in_range).copied().collect(),
+                };
+                let lower_level_sst_ids =
+                    ctrl.find_overlapping_ssts(snapshot, &upper_level_sst_ids, level + 1);
+                Some(CompactionTask::Leveled(LeveledCompactionTask {
+                    upper_level,
+                    upper_level_sst_ids,
+                    lower_level: level + 1,
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level,
+                }))
+            }
+            // simple leveled compaction always compacts whole levels
+            CompactionController::Simple(_) => {
+                Some(CompactionTask::Simple(SimpleLeveledCompactionTask {
+                    upper_level,
+                    upper_level_sst_ids: ssts.clone(),
+                    lower_level: level + 1,
+                    lower_level_sst_ids: snapshot.levels[level].1.clone(),
+                    is_lower_level_bottom_level,
+                }))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The task merging the newest sorted runs down to the oldest one that holds keys in the range.
+    fn sorted_runs_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<CompactionTask> {
+        let oldest = snapshot.levels.iter().rposition(|(_, ssts)| {
+            ssts.iter().any(|id| {
+                let sst = &snapshot.sstables[id];
+                range_overlap(
+                    lower,
+                    upper,
+                    sst.first_key().as_key_slice(),
+                    sst.last_key().as_key_slice(),
+                )
+            })
+        })?;
+        let bottom_tier_included = oldest + 1 == snapshot.levels.len();
+        // a single tier is already a sorted run, it is only rewritten to drop the tombstones in the bottom tier
+        if oldest == 0 && !bottom_tier_included {
+            return None;
+        }
+        let runs = snapshot.levels[..=oldest].to_vec();
+        match &self.compaction_controller {
+            CompactionController::Tiered(_) => Some(CompactionTask::Tiered(TieredCompactionTask {
+                tiers: runs,
+                bottom_tier_included,
+            })),
+            CompactionController::LazyLeveled(_) => {
+                Some(CompactionTask::LazyLeveled(LazyLeveledCompactionTask {
+                    runs,
+                    bottom_run_included: bottom_tier_included,
+                }))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Run the task generated by `generate` from the latest state once it does not conflict with the running jobs.
+    /// Manual compactions always rewrite their input, so that tombstones and filtered keys are dropped.
+    fn run_manual_compaction(
+        &self,
+        generate: impl Fn(&LsmStorageState) -> Option<CompactionTask>,
+    ) -> Result<()> {
+        loop {
+            let mut running = self.running_compactions.lock();
+            let snapshot = {
+                let state = self.state.read();
+                state.clone()
+            };
+            let Some(task) = generate(&snapshot) else {
+                return Ok(());
+            };
+            if running.is_conflicting(&snapshot, &task) {
+                drop(running);
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            let job_id = running.register(&snapshot, &task);
+            drop(running);
+            let result = self.run_compaction_task(&snapshot, &task, false);
+            self.release_compaction(job_id);
+            return result;
+        }
+    }
+
+    /// Generate a task that does not conflict with the running ones, and register it as running. The caller must
+    /// release the returned job id when the task is done.
+    fn next_compaction_task(&self) -> Option<(usize, Arc<LsmStorageState>, CompactionTask)> {
+        let mut running = self.running_compactions.lock();
+        // checked with the lock held, so that a pause waiting for the running jobs also waits for this one
+        if self.is_background_work_paused() {
+            return None;
+        }
+        if running.len() >= self.options.max_background_compactions.max(1) {
+            return None;
+        }
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
         let task = self
             .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
-            return Ok(());
-        };
+            .generate_compaction_task(&snapshot, &running)?;
+        let job_id = running.register(&snapshot, &task);
+        Some((job_id, snapshot, task))
+    }
+
+    /// Run `task`, generated from `snapshot`. If `allow_trivial_move` is not set, SSTs are always rewritten.
+    fn run_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        allow_trivial_move: bool,
+    ) -> Result<()> {
         self.dump_structure();
         println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
-        let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
+        let trivial_move =
+            allow_trivial_move && self.compaction_controller.is_trivial_move(snapshot, task);
+        let (sstables, output) = if trivial_move {
+            // the upper level SSTs are kept as they are, only the manifest is updated
+            (
+                Vec::new(),
+                self.compaction_controller
+                    .trivial_move_output(snapshot, task),
+            )
+        } else if let CompactionTask::Fifo(_) = task {
+            // the oldest SSTs are dropped without writing anything
+            (Vec::new(), Vec::new())
+        } else {
+            let sstables = self.compact(task)?;
+            let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
+            (sstables, output)
+        };
         let ssts_to_remove = {
             let state_lock = self.state_lock.lock();
             let mut snapshot = self.state.read().as_ref().clone();
-            let mut new_sst_ids = Vec::new();
             for file_to_add in sstables {
-                new_sst_ids.push(file_to_add.sst_id());
                 let result = snapshot.sstables.insert(file_to_add.sst_id(), file_to_add);
                 assert!(result.is_none());
             }
             let (mut snapshot, files_to_remove) = self
                 .compaction_controller
-                .apply_compaction_result(&snapshot, &task, &output, false);
+                .apply_compaction_result(&snapshot, task, &output, false);
 
             let mut ssts_to_remove = Vec::with_capacity(files_to_remove.len());
-            for file_to_remove in &files_to_remove {
+            for file_to_remove in files_to_remove.iter().filter(|id| !output.contains(id)) {
                 let result = snapshot.sstables.remove(file_to_remove);
                 assert!(result.is_some(), "cannot remove {}.sst", file_to_remove);
                 ssts_to_remove.push(result.unwrap());
             }
             let mut state = self.state.write();
+            let edit = ManifestEdit::between(&state, &snapshot);
             *state = Arc::new(snapshot);
             drop(state);
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+            self.add_manifest_record(&state_lock, ManifestRecord::Compaction(edit))?;
             ssts_to_remove
         };
+        if trivial_move {
+            println!("compaction finished: {} files moved", output.len());
+            return Ok(());
+        }
         println!(
             "compaction finished: {} files removed, {} files added, output={:?}",
             ssts_to_remove.len(),
@@ -412,32 +1072,165 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    pub(crate) fn spawn_compaction_thread(
-        self: &Arc<Self>,
-        rx: crossbeam_channel::Receiver<()>,
-    ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        if let CompactionOptions::Leveled(_)
-        | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
-        {
+    fn background_compaction_enabled(&self) -> bool {
+        matches!(
+            self.options.compaction_options,
+            CompactionOptions::Leveled(_)
+                | CompactionOptions::Simple(_)
+                | CompactionOptions::Tiered(_)
+                | CompactionOptions::Fifo(_)
+                | CompactionOptions::LazyLeveled(_)
+        )
+    }
+
+    /// Start the background flushes, and the background compactions unless compaction is disabled.
+    pub(crate) fn start_background_work(self: &Arc<Self>) {
+        self.weak_self.get_or_init(|| Arc::downgrade(self));
+        let compaction_threads = if self.background_compaction_enabled() {
+            self.options.max_background_compactions.max(1)
+        } else {
+            0
+        };
+        self.scheduler.start(
+            self.options.max_background_flushes.max(1),
+            compaction_threads,
+        );
+        // the recovered state may already need a flush or compaction
+        self.schedule_background_work(BackgroundEvent::Started);
+    }
+
+    /// Schedule the flushes and compactions `event` may have made necessary.
+    pub(crate) fn schedule_background_work(&self, event: BackgroundEvent) {
+        if self.is_background_work_paused() {
+            return;
+        }
+        let Some(this) = self.weak_self.get().and_then(Weak::upgrade) else {
+            return;
+        };
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.tune(self.background_backlog());
+        }
+        if !matches!(
+            event,
+            BackgroundEvent::CompactionFinished | BackgroundEvent::TimerExpired
+        ) {
+            this.schedule_flushes();
+        }
+        if event != BackgroundEvent::MemtableFrozen {
+            this.schedule_compactions();
+        }
+    }
+
+    /// Schedule a flush job for each immutable memtable over the limit that no scheduled job flushes yet.
+    fn schedule_flushes(self: &Arc<Self>) {
+        let excess_memtables = {
+            let state = self.state.read();
+            (state.imm_memtables.len() + 1).saturating_sub(self.options.num_memtable_limit)
+        };
+        while self.scheduled_flushes.load(Ordering::SeqCst) < excess_memtables {
+            self.scheduled_flushes.fetch_add(1, Ordering::SeqCst);
             let this = self.clone();
-            let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
-                    }
+            let scheduled = self.scheduler.schedule(JobPriority::High, move || {
+                // a flush finished from now on may schedule the next one
+                this.scheduled_flushes.fetch_sub(1, Ordering::SeqCst);
+                if let Err(e) = this.trigger_flush() {
+                    eprintln!("flush failed: {}", e);
+                }
+            });
+            if !scheduled {
+                self.scheduled_flushes.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    /// Schedule as many non-conflicting compaction jobs as the limit allows, and a timer for the next compaction
+    /// triggered by the time.
+    fn schedule_compactions(self: &Arc<Self>) {
+        if !self.scheduler.is_running(JobPriority::Low) {
+            return;
+        }
+        while let Some((job_id, snapshot, task)) = self.next_compaction_task() {
+            let job = RegisteredCompaction {
+                storage: self.clone(),
+                job_id,
+            };
+            // the job is released when dropped, also if it is dropped from the queue by a shutdown
+            let scheduled = self.scheduler.schedule(JobPriority::Low, move || {
+                let this = job.storage.clone();
+                if let Err(e) = this.run_compaction_task(&snapshot, &task, true) {
+                    eprintln!("compaction failed: {}", e);
                 }
+                drop(job);
+                this.schedule_background_work(BackgroundEvent::CompactionFinished);
             });
-            return Ok(Some(handle));
+            if !scheduled {
+                return;
+            }
+        }
+
+        let snapshot = self.state.read().clone();
+        let Some(next) = self
+            .compaction_controller
+            .next_time_triggered_compaction(&snapshot)
+        else {
+            return;
+        };
+        let mut compaction_timer = self.compaction_timer.lock();
+        if compaction_timer.is_some_and(|timer| timer <= next) {
+            return;
         }
-        Ok(None)
+        let delay = next.duration_since(SystemTime::now()).unwrap_or_default();
+        let this = self.clone();
+        let scheduled =
+            self.scheduler
+                .schedule_at(Instant::now() + delay, JobPriority::Low, move || {
+                    this.compaction_timer.lock().take();
+                    this.schedule_background_work(BackgroundEvent::TimerExpired);
+                });
+        if scheduled {
+            *compaction_timer = Some(next);
+        }
+    }
+
+    /// The pending flush and compaction work, relative to what triggers a flush or compaction.
+    fn background_backlog(&self) -> f64 {
+        let state = self.state.read();
+        let flush_backlog =
+            state.imm_memtables.len() as f64 / self.options.num_memtable_limit as f64;
+        let compaction_backlog = match &self.options.compaction_options {
+            CompactionOptions::Leveled(options) => {
+                state.l0_sstables.len() as f64 / options.level0_file_num_compaction_trigger as f64
+            }
+            CompactionOptions::Simple(options) => {
+                state.l0_sstables.len() as f64 / options.level0_file_num_compaction_trigger as f64
+            }
+            CompactionOptions::Tiered(options) => {
+                state.levels.len() as f64 / options.num_tiers as f64
+            }
+            CompactionOptions::LazyLeveled(options) => {
+                state.levels.len() as f64
+                    / (options.level_size_multiplier * options.max_levels).max(1) as f64
+            }
+            // dropping SSTs writes nothing
+            CompactionOptions::Fifo(_) | CompactionOptions::NoCompaction => 0.0,
+        };
+        flush_backlog.max(compaction_backlog)
+    }
+
+    /// Release the compaction job `job_id`, waking up a pause waiting for it.
+    fn release_compaction(&self, job_id: usize) {
+        let mut running = self.running_compactions.lock();
+        running.release(job_id);
+        self.background_job_finished.notify_all();
     }
 
     fn trigger_flush(&self) -> Result<()> {
+        // counted before checking the pause, so that a pause either stops the flush or waits for it
+        let _flush = RunningFlush::new(self);
+        if self.is_background_work_paused() {
+            return Ok(());
+        }
         let res = {
             let state = self.state.read();
             state.imm_memtables.len() >= self.options.num_memtable_limit
@@ -449,22 +1242,27 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    pub(crate) fn spawn_flush_thread(
-        self: &Arc<Self>,
-        rx: crossbeam_channel::Receiver<()>,
-    ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        let this = self.clone();
-        let handle = std::thread::spawn(move || {
-            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-            loop {
-                crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("flush failed: {}", e);
-                    },
-                    recv(rx) -> _ => return
-                }
-            }
-        });
-        Ok(Some(handle))
+    pub(crate) fn is_background_work_paused(&self) -> bool {
+        self.background_work_paused.load(Ordering::SeqCst) > 0
+    }
+
+    /// Stop scheduling background flushes and compactions, and cancel the running compactions, including manual ones.
+    /// Returns once no compaction job or background flush runs.
+    pub fn pause_background_work(&self) {
+        self.background_work_paused.fetch_add(1, Ordering::SeqCst);
+        let mut running = self.running_compactions.lock();
+        while !running.is_empty() || self.running_flushes.load(Ordering::SeqCst) > 0 {
+            self.background_job_finished.wait(&mut running);
+        }
+    }
+
+    pub fn continue_background_work(&self) -> Result<()> {
+        self.background_work_paused
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |paused| {
+                paused.checked_sub(1)
+            })
+            .map_err(|_| anyhow::anyhow!("background work is not paused"))?;
+        self.schedule_background_work(BackgroundEvent::Started);
+        Ok(())
     }
 }
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::RunningCompactions;
+use crate::lsm_storage::LsmStorageState;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FifoCompactionTask {
+    /// The L0 SSTs to drop, oldest first.
+    pub sst_ids: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FifoCompactionOptions {
+    /// Drop the oldest SSTs while the total size of the SSTs exceeds this limit.
+    pub max_total_size_mb: usize,
+    /// Drop SSTs written longer ago than this if set.
+    pub ttl_secs: Option<u64>,
+}
+
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<FifoCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generate a task if no job is `running`. Tasks always drop the oldest SSTs, so they cannot run concurrently.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<FifoCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
+        assert!(
+            snapshot.levels.is_empty(),
+            "should not have levels in FIFO compaction"
+        );
+        let max_total_size = self.options.max_total_size_mb as u64 * 1024 * 1024;
+        let mut total_size = snapshot
+            .l0_sstables
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        let now = SystemTime::now();
+        let is_expired = |id: &usize| {
+            let (Some(ttl_secs), Some(creation_time)) =
+                (self.options.ttl_secs, snapshot.sstables[id].creation_time())
+            else {
+                return false;
+            };
+            now.duration_since(creation_time).unwrap_or_default() >= Duration::from_secs(ttl_secs)
+        };
+        let mut sst_ids = Vec::new();
+        // L0 SSTs are ordered from the newest to the oldest
+        for id in snapshot.l0_sstables.iter().rev() {
+            if total_size <= max_total_size && !is_expired(id) {
+                break;
+            }
+            total_size -= snapshot.sstables[id].table_size();
+            sst_ids.push(*id);
+        }
+        if sst_ids.is_empty() {
+            return None;
+        }
+        println!(
+            "FIFO compaction drops {} SSTs, {:.3}MB remain",
+            sst_ids.len(),
+            total_size as f64 / 1024.0 / 1024.0
+        );
+        Some(FifoCompactionTask { sst_ids })
+    }
+
+    /// When the next SST expires, if SSTs expire at all. No compaction would be triggered by the time before.
+    pub fn next_expiration(&self, snapshot: &LsmStorageState) -> Option<SystemTime> {
+        let ttl = Duration::from_secs(self.options.ttl_secs?);
+        snapshot
+            .l0_sstables
+            .iter()
+            .filter_map(|id| snapshot.sstables[id].creation_time())
+            .min()
+            .map(|creation_time| creation_time + ttl)
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &FifoCompactionTask,
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let mut sst_ids_set = task.sst_ids.iter().copied().collect::<HashSet<_>>();
+        snapshot.l0_sstables.retain(|id| !sst_ids_set.remove(id));
+        assert!(sst_ids_set.is_empty());
+        (snapshot, task.sst_ids.clone())
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+use super::tiered::replace_tiers;
+use super::{RunningCompactions, TieredCompactionController};
+use crate::lsm_storage::LsmStorageState;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LazyLeveledCompactionTask {
+    /// The adjacent sorted runs to merge, from the newest to the oldest.
+    pub runs: Vec<(usize, Vec<usize>)>,
+    pub bottom_run_included: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LazyLeveledCompactionOptions {
+    /// The size ratio between adjacent levels, which is also the number of sorted runs an upper level collects before
+    /// they are merged into one run of the next level.
+    pub level_size_multiplier: usize,
+    /// The number of levels, including the bottom level.
+    pub max_levels: usize,
+    /// The size of the sorted runs of L1. The runs of each following level are `level_size_multiplier` times larger.
+    pub base_level_size_mb: usize,
+}
+
+/// Lazy leveling (as in Dostoevsky): the upper levels are tiered and the bottom level is a single sorted run.
+///
+/// Like in tiered compaction, `LsmStorageState::levels` holds the sorted runs from the newest to the oldest, where
+/// the oldest one is the bottom level. The level of an upper run follows from its size.
+pub struct LazyLeveledCompactionController {
+    options: LazyLeveledCompactionOptions,
+}
+
+impl LazyLeveledCompactionController {
+    pub fn new(options: LazyLeveledCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LazyLeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    fn size_ratio(&self) -> u64 {
+        self.options.level_size_multiplier.max(2) as u64
+    }
+
+    /// The upper level a sorted run of `size` bytes belongs to. Runs larger than the runs of every upper level are
+    /// in the last upper level.
+    fn run_level(&self, size: u64) -> usize {
+        let last_upper_level = self.options.max_levels.saturating_sub(1).max(1);
+        let mut level = 1;
+        let mut run_size = self.options.base_level_size_mb as u64 * 1024 * 1024;
+        while size > run_size && level < last_upper_level {
+            level += 1;
+            run_size *= self.size_ratio();
+        }
+        level
+    }
+
+    fn merge_runs(
+        snapshot: &LsmStorageState,
+        begin: usize,
+        end: usize,
+    ) -> LazyLeveledCompactionTask {
+        LazyLeveledCompactionTask {
+            runs: snapshot.levels[begin..end].to_vec(),
+            bottom_run_included: end == snapshot.levels.len(),
+        }
+    }
+
+    /// Generate a task if no job is `running`, as tasks renumber the sorted runs like tiered compaction does.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<LazyLeveledCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
+        assert!(
+            snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in lazy leveled compaction"
+        );
+        let num_runs = snapshot.levels.len();
+        if num_runs < 2 {
+            return None;
+        }
+        let run_sizes = TieredCompactionController::tier_sizes(snapshot);
+        let size_ratio = self.size_ratio();
+
+        // the upper levels may hold up to 1 / (T - 1) of the bottom level, as they would if each of them was T times
+        // smaller than the next one
+        let upper_size = run_sizes[..num_runs - 1].iter().sum::<u64>();
+        let bottom_size = run_sizes[num_runs - 1];
+        if upper_size * (size_ratio - 1) >= bottom_size {
+            println!(
+                "compaction triggered by bottom level size: upper levels {} bytes, bottom level {} bytes",
+                upper_size, bottom_size
+            );
+            return Some(Self::merge_runs(snapshot, 0, num_runs));
+        }
+
+        // an upper level with T sorted runs is merged into one run of the next level, or into the bottom level if it
+        // is the last upper level
+        let levels = run_sizes[..num_runs - 1]
+            .iter()
+            .map(|size| self.run_level(*size))
+            .collect::<Vec<_>>();
+        let last_upper_level = self.options.max_levels.saturating_sub(1).max(1);
+        let mut begin = 0;
+        while begin < levels.len() {
+            let level = levels[begin];
+            let end = begin + levels[begin..].iter().take_while(|l| **l == level).count();
+            if end - begin >= size_ratio as usize {
+                println!(
+                    "compaction triggered by {} sorted runs in L{}, merging runs {}..{}",
+                    end - begin,
+                    level,
+                    begin,
+                    end
+                );
+                if level == last_upper_level {
+                    return Some(Self::merge_runs(snapshot, begin, num_runs));
+                }
+                return Some(Self::merge_runs(snapshot, begin, end));
+            }
+            begin = end;
+        }
+        None
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &LazyLeveledCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        replace_tiers(snapshot, &task.runs, output)
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use super::{is_non_overlapping, RunningCompactions, SstSelection, SstSelectionPolicy};
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -14,7 +15,7 @@ pub struct LeveledCompactionTask {
     pub is_lower_level_bottom_level: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LeveledCompactionOptions {
     pub level_size_multiplier: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -24,14 +25,27 @@ pub struct LeveledCompactionOptions {
 
 pub struct LeveledCompactionController {
     options: LeveledCompactionOptions,
+    sst_selection: Box<dyn SstSelectionPolicy>,
 }
 
 impl LeveledCompactionController {
     pub fn new(options: LeveledCompactionOptions) -> Self {
-        Self { options }
+        Self::with_sst_selection(options, SstSelection::Oldest.build())
     }
 
-    fn find_overlapping_ssts(
+    /// Create a controller that picks the SST compacted out of a level with `sst_selection`.
+    pub fn with_sst_selection(
+        options: LeveledCompactionOptions,
+        sst_selection: Box<dyn SstSelectionPolicy>,
+    ) -> Self {
+        Self {
+            options,
+            sst_selection,
+        }
+    }
+
+    /// The SSTs of `in_level` overlapping the key range of `sst_ids`.
+    pub(crate) fn find_overlapping_ssts(
         &self,
         snapshot: &LsmStorageState,
         sst_ids: &[usize],
@@ -64,6 +78,36 @@ impl LeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Whether the key range of `sst_ids` overlaps the output of a running job writing to `level`.
+    fn is_output_range_taken(
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+        sst_ids: &[usize],
+        level: usize,
+    ) -> bool {
+        let first_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key())
+            .min()
+            .unwrap();
+        let last_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].last_key())
+            .max()
+            .unwrap();
+        running.is_range_taken(level, first_key, last_key)
+    }
+
+    /// Generate a task that does not conflict with the `running` ones: it compacts none of their SSTs, and does not
+    /// write to a range of a level they write to.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
     ) -> Option<LeveledCompactionTask> {
         // step 1: compute target level size
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
@@ -95,19 +139,24 @@ impl LeveledCompactionController {
         }
 
         // Flush L0 SST is the top priority
-        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
-            println!("flush L0 SST to base level {}", base_level);
-            return Some(LeveledCompactionTask {
-                upper_level: None,
-                upper_level_sst_ids: snapshot.l0_sstables.clone(),
-                lower_level: base_level,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &snapshot.l0_sstables,
-                    base_level,
-                ),
-                is_lower_level_bottom_level: base_level == self.options.max_levels,
-            });
+        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+            && !running.is_level_taken(0)
+        {
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, base_level);
+            let sst_ids = [snapshot.l0_sstables.clone(), lower_level_sst_ids.clone()].concat();
+            if !running.is_any_sst_taken(&lower_level_sst_ids)
+                && !Self::is_output_range_taken(snapshot, running, &sst_ids, base_level)
+            {
+                println!("flush L0 SST to base level {}", base_level);
+                return Some(LeveledCompactionTask {
+                    upper_level: None,
+                    upper_level_sst_ids: snapshot.l0_sstables.clone(),
+                    lower_level: base_level,
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level: base_level == self.options.max_levels,
+                });
+            }
         }
 
         let mut priorities = Vec::with_capacity(self.options.max_levels);
@@ -119,8 +168,7 @@ impl LeveledCompactionController {
         }
         priorities.sort_by(|a, b| a.partial_cmp(b).unwrap().reverse());
 
-        let priority = priorities.first();
-        if let Some((_, level)) = priority {
+        if !priorities.is_empty() {
             println!(
                 "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
                 target_level_size
@@ -133,28 +181,54 @@ impl LeveledCompactionController {
                     .collect::<Vec<_>>(),
                 base_level,
             );
-
+        }
+        for (_, level) in &priorities {
             let level = *level;
-            let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
-            println!(
-                "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
-                priorities
-            );
-            return Some(LeveledCompactionTask {
-                upper_level: Some(level),
-                upper_level_sst_ids: vec![selected_sst],
-                lower_level: level + 1,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &[selected_sst],
-                    level + 1,
-                ),
-                is_lower_level_bottom_level: level + 1 == self.options.max_levels,
-            });
+            let mut candidates = snapshot.levels[level - 1].1.clone();
+            self.sst_selection
+                .prioritize(snapshot, level, &mut candidates);
+            for selected_sst in candidates {
+                if running.is_any_sst_taken(&[selected_sst]) {
+                    continue;
+                }
+                let lower_level_sst_ids =
+                    self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
+                let sst_ids = [vec![selected_sst], lower_level_sst_ids.clone()].concat();
+                if running.is_any_sst_taken(&lower_level_sst_ids)
+                    || Self::is_output_range_taken(snapshot, running, &sst_ids, level + 1)
+                {
+                    continue;
+                }
+                println!(
+                    "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
+                    priorities
+                );
+                self.sst_selection
+                    .on_selected(snapshot, level, selected_sst);
+                return Some(LeveledCompactionTask {
+                    upper_level: Some(level),
+                    upper_level_sst_ids: vec![selected_sst],
+                    lower_level: level + 1,
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+                });
+            }
         }
         None
     }
 
+    /// Whether `task` moves SSTs into a lower level without overlapping any SST there, in which case they are
+    /// relinked into the lower level instead of being rewritten.
+    pub fn is_trivial_move(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &LeveledCompactionTask,
+    ) -> bool {
+        !task.upper_level_sst_ids.is_empty()
+            && task.lower_level_sst_ids.is_empty()
+            && is_non_overlapping(snapshot, &task.upper_level_sst_ids)
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
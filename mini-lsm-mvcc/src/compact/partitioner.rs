@@ -0,0 +1,42 @@
+use bytes::Bytes;
+
+/// Decides where compaction output is split into SSTs, on top of the size limits, so that an SST never holds keys of
+/// two partitions, e.g., two tenants.
+pub trait SstPartitioner: Send + Sync {
+    /// Whether `key` must start a new SST when the previous key written to the output is `previous_key`. Only called
+    /// between different user keys.
+    fn should_partition(&self, previous_key: &[u8], key: &[u8]) -> bool;
+}
+
+/// Splits the output where the first `prefix_len` bytes of the keys change.
+pub struct FixedPrefixSstPartitioner(pub usize);
+
+impl SstPartitioner for FixedPrefixSstPartitioner {
+    fn should_partition(&self, previous_key: &[u8], key: &[u8]) -> bool {
+        previous_key[..previous_key.len().min(self.0)] != key[..key.len().min(self.0)]
+    }
+}
+
+/// Splits the output before the first key at or after each boundary.
+pub struct BoundarySstPartitioner {
+    boundaries: Vec<Bytes>,
+}
+
+impl BoundarySstPartitioner {
+    pub fn new(mut boundaries: Vec<Bytes>) -> Self {
+        boundaries.sort();
+        Self { boundaries }
+    }
+}
+
+impl SstPartitioner for BoundarySstPartitioner {
+    fn should_partition(&self, previous_key: &[u8], key: &[u8]) -> bool {
+        // the first boundary after the previous key is the only one that can be at or before the key
+        let next = self
+            .boundaries
+            .partition_point(|boundary| boundary.as_ref() <= previous_key);
+        self.boundaries
+            .get(next)
+            .is_some_and(|boundary| boundary.as_ref() <= key)
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+
+use super::CompactionTask;
+use crate::key::KeyBytes;
+use crate::lsm_storage::LsmStorageState;
+
+struct RunningCompaction {
+    ssts: HashSet<usize>,
+    /// The levels owned by the job as a whole, where 0 is L0.
+    levels: Vec<usize>,
+    /// The level the job writes to and the key range of its output.
+    output: Option<(usize, KeyBytes, KeyBytes)>,
+}
+
+/// The compaction jobs being executed, which new tasks must not conflict with.
+#[derive(Default)]
+pub struct RunningCompactions {
+    jobs: HashMap<usize, RunningCompaction>,
+    next_job_id: usize,
+}
+
+impl RunningCompactions {
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Whether any of `sst_ids` is compacted by a running job.
+    pub fn is_any_sst_taken(&self, sst_ids: &[usize]) -> bool {
+        self.jobs
+            .values()
+            .any(|job| sst_ids.iter().any(|id| job.ssts.contains(id)))
+    }
+
+    /// Whether `level` (0 is L0) is owned by a running job.
+    pub fn is_level_taken(&self, level: usize) -> bool {
+        self.jobs.values().any(|job| job.levels.contains(&level))
+    }
+
+    /// Whether a running job writes keys in `[first_key, last_key]` to `level`.
+    pub fn is_range_taken(&self, level: usize, first_key: &KeyBytes, last_key: &KeyBytes) -> bool {
+        self.jobs.values().any(|job| {
+            job.output
+                .as_ref()
+                .is_some_and(|(output_level, first, last)| {
+                    *output_level == level && !(last < first_key || first > last_key)
+                })
+        })
+    }
+
+    /// The SSTs, levels and output range `task`, generated from `snapshot`, claims while it runs.
+    fn claim(snapshot: &LsmStorageState, task: &CompactionTask) -> RunningCompaction {
+        let ssts = task.input_sst_ids();
+        let (levels, output) = match task {
+            CompactionTask::Leveled(task) => {
+                let first_key = ssts
+                    .iter()
+                    .map(|id| snapshot.sstables[id].first_key())
+                    .min()
+                    .cloned()
+                    .unwrap();
+                let last_key = ssts
+                    .iter()
+                    .map(|id| snapshot.sstables[id].last_key())
+                    .max()
+                    .cloned()
+                    .unwrap();
+                // L0 SSTs overlap each other, so only one job compacts L0 at a time
+                let levels = match task.upper_level {
+                    Some(_) => Vec::new(),
+                    None => vec![0],
+                };
+                (levels, Some((task.lower_level, first_key, last_key)))
+            }
+            CompactionTask::Simple(task) => {
+                (vec![task.upper_level.unwrap_or(0), task.lower_level], None)
+            }
+            CompactionTask::Tiered(_)
+            | CompactionTask::LazyLeveled(_)
+            | CompactionTask::Fifo(_)
+            | CompactionTask::ForceFullCompaction { .. } => (Vec::new(), None),
+        };
+        RunningCompaction {
+            ssts: ssts.into_iter().collect(),
+            levels,
+            output,
+        }
+    }
+
+    /// Whether `task`, generated from `snapshot`, conflicts with a running job, i.e., it compacts SSTs, owns levels
+    /// or writes to a range that a running job does. Tasks of the other layouts cannot run with any other job.
+    pub fn is_conflicting(&self, snapshot: &LsmStorageState, task: &CompactionTask) -> bool {
+        let claim = Self::claim(snapshot, task);
+        match task {
+            CompactionTask::Leveled(_) | CompactionTask::Simple(_) => {
+                self.jobs
+                    .values()
+                    .any(|job| !job.ssts.is_disjoint(&claim.ssts))
+                    || claim.levels.iter().any(|level| self.is_level_taken(*level))
+                    || claim.output.as_ref().is_some_and(|(level, first, last)| {
+                        self.is_range_taken(*level, first, last)
+                    })
+            }
+            _ => !self.is_empty(),
+        }
+    }
+
+    /// Register `task`, generated from `snapshot`, as running. Returns the id to release it with.
+    pub fn register(&mut self, snapshot: &LsmStorageState, task: &CompactionTask) -> usize {
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+        self.jobs.insert(id, Self::claim(snapshot, task));
+        id
+    }
+
+    /// Release the job `id` once it is applied or has failed.
+    pub fn release(&mut self, id: usize) {
+        self.jobs.remove(&id);
+    }
+}
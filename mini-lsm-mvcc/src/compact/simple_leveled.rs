@@ -2,9 +2,10 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 
+use super::{is_non_overlapping, RunningCompactions};
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionOptions {
     pub size_ratio_percent: usize,
     pub level0_file_num_compaction_trigger: usize,
@@ -36,6 +37,15 @@ impl SimpleLeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// Generate a task that does not conflict with the `running` ones, i.e., it compacts none of their levels.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
     ) -> Option<SimpleLeveledCompactionTask> {
         let mut level_sizes = Vec::new();
         level_sizes.push(snapshot.l0_sstables.len());
@@ -51,6 +61,9 @@ impl SimpleLeveledCompactionController {
             }
 
             let lower_level = i + 1;
+            if running.is_level_taken(i) || running.is_level_taken(lower_level) {
+                continue;
+            }
             let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
             if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
                 println!(
@@ -73,6 +86,18 @@ impl SimpleLeveledCompactionController {
         None
     }
 
+    /// Whether `task` moves SSTs into a lower level without overlapping any SST there, in which case they are
+    /// relinked into the lower level instead of being rewritten.
+    pub fn is_trivial_move(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &SimpleLeveledCompactionTask,
+    ) -> bool {
+        !task.upper_level_sst_ids.is_empty()
+            && task.lower_level_sst_ids.is_empty()
+            && is_non_overlapping(snapshot, &task.upper_level_sst_ids)
+    }
+
     /// Apply the compaction result.
     ///
     /// The compactor will call this function with the compaction task and the list of SST ids generated. This function applies the
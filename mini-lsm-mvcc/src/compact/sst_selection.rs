@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::key::KeyBytes;
+use crate::lsm_storage::LsmStorageState;
+
+/// Decides which SST of a level is compacted into the next level when leveled compaction picks the level.
+pub trait SstSelectionPolicy: Send + Sync {
+    /// Order `sst_ids`, the SSTs of `level`, by preference. The first one that does not conflict with a running
+    /// compaction is picked.
+    fn prioritize(&self, snapshot: &LsmStorageState, level: usize, sst_ids: &mut [usize]);
+
+    /// Called once `sst_id` of `level` is picked for compaction.
+    fn on_selected(&self, _snapshot: &LsmStorageState, _level: usize, _sst_id: usize) {}
+}
+
+/// The built-in SST selection policies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SstSelection {
+    /// The oldest SST, i.e., the one with the smallest id.
+    #[default]
+    Oldest,
+    /// The SST with the highest ratio of delete tombstones to entries, which removes the most garbage.
+    MostTombstones,
+    /// The SST overlapping the fewest bytes in the next level relative to its own size, which writes the least.
+    MinOverlap,
+    /// The SST following the one picked last from the level, which cycles through the key space.
+    RoundRobin,
+}
+
+impl SstSelection {
+    pub fn build(self) -> Box<dyn SstSelectionPolicy> {
+        match self {
+            SstSelection::Oldest => Box::new(OldestFirst),
+            SstSelection::MostTombstones => Box::new(MostTombstonesFirst),
+            SstSelection::MinOverlap => Box::new(MinOverlapFirst),
+            SstSelection::RoundRobin => Box::new(RoundRobin::default()),
+        }
+    }
+}
+
+pub struct OldestFirst;
+
+impl SstSelectionPolicy for OldestFirst {
+    fn prioritize(&self, _snapshot: &LsmStorageState, _level: usize, sst_ids: &mut [usize]) {
+        sst_ids.sort();
+    }
+}
+
+pub struct MostTombstonesFirst;
+
+impl SstSelectionPolicy for MostTombstonesFirst {
+    fn prioritize(&self, snapshot: &LsmStorageState, _level: usize, sst_ids: &mut [usize]) {
+        let tombstone_ratio = |id: &usize| {
+            let properties = snapshot.sstables[id].properties();
+            if properties.num_entries == 0 {
+                return 0.0;
+            }
+            properties.num_tombstones as f64 / properties.num_entries as f64
+        };
+        // ties go to the oldest SST
+        sst_ids.sort_by(|a, b| {
+            tombstone_ratio(b)
+                .total_cmp(&tombstone_ratio(a))
+                .then(a.cmp(b))
+        });
+    }
+}
+
+pub struct MinOverlapFirst;
+
+impl SstSelectionPolicy for MinOverlapFirst {
+    fn prioritize(&self, snapshot: &LsmStorageState, level: usize, sst_ids: &mut [usize]) {
+        let Some((_, next_level)) = snapshot.levels.get(level) else {
+            sst_ids.sort();
+            return;
+        };
+        let overlap_ratio = |id: &usize| {
+            let sst = &snapshot.sstables[id];
+            let overlapping_bytes = next_level
+                .iter()
+                .map(|id| &snapshot.sstables[id])
+                .filter(|next| {
+                    !(next.last_key() < sst.first_key() || next.first_key() > sst.last_key())
+                })
+                .map(|next| next.table_size())
+                .sum::<u64>();
+            overlapping_bytes as f64 / sst.table_size().max(1) as f64
+        };
+        // ties go to the oldest SST
+        sst_ids.sort_by(|a, b| overlap_ratio(a).total_cmp(&overlap_ratio(b)).then(a.cmp(b)));
+    }
+}
+
+/// Picks the SSTs of each level in key order, starting after the last key of the SST picked last. The cursors are
+/// kept in memory, so they start over from the smallest key when the storage is reopened.
+#[derive(Default)]
+pub struct RoundRobin {
+    cursors: Mutex<HashMap<usize, KeyBytes>>,
+}
+
+impl SstSelectionPolicy for RoundRobin {
+    fn prioritize(&self, snapshot: &LsmStorageState, level: usize, sst_ids: &mut [usize]) {
+        sst_ids.sort_by(|a, b| {
+            snapshot.sstables[a]
+                .first_key()
+                .cmp(snapshot.sstables[b].first_key())
+        });
+        if let Some(cursor) = self.cursors.lock().get(&level) {
+            let start = sst_ids.partition_point(|id| snapshot.sstables[id].first_key() <= cursor);
+            sst_ids.rotate_left(start);
+        }
+    }
+
+    fn on_selected(&self, snapshot: &LsmStorageState, level: usize, sst_id: usize) {
+        self.cursors
+            .lock()
+            .insert(level, snapshot.sstables[&sst_id].last_key().clone());
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// Cumulative statistics of the compactions writing to a level.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LevelCompactionStats {
+    pub num_compactions: u64,
+    /// Total size of the input SSTs.
+    pub bytes_read: u64,
+    /// Total size of the output SSTs.
+    pub bytes_written: u64,
+    pub input_files: u64,
+    pub output_files: u64,
+    /// Delete tombstones and expired keys dropped in the bottom level.
+    pub dropped_tombstones: u64,
+    /// Versions hidden by a newer version below the watermark, or removed by a compaction filter.
+    pub dropped_versions: u64,
+    pub compaction_time: Duration,
+}
+
+impl AddAssign<&LevelCompactionStats> for LevelCompactionStats {
+    fn add_assign(&mut self, other: &LevelCompactionStats) {
+        self.num_compactions += other.num_compactions;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.input_files += other.input_files;
+        self.output_files += other.output_files;
+        self.dropped_tombstones += other.dropped_tombstones;
+        self.dropped_versions += other.dropped_versions;
+        self.compaction_time += other.compaction_time;
+    }
+}
+
+/// Cumulative statistics of flushes and compactions since the storage was opened.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub num_flushes: u64,
+    /// Total size of the SSTs written by flushes.
+    pub flush_bytes: u64,
+    /// The compactions, by the level they write to. Tiered compaction writes to the position of the merged tier.
+    pub levels: BTreeMap<usize, LevelCompactionStats>,
+}
+
+impl CompactionStats {
+    /// The stats of all levels together.
+    pub fn total(&self) -> LevelCompactionStats {
+        let mut total = LevelCompactionStats::default();
+        for stats in self.levels.values() {
+            total += stats;
+        }
+        total
+    }
+
+    /// The bytes written by flushes and compactions per byte flushed.
+    pub fn write_amplification(&self) -> f64 {
+        if self.flush_bytes == 0 {
+            return 0.0;
+        }
+        (self.flush_bytes + self.total().bytes_written) as f64 / self.flush_bytes as f64
+    }
+}
+
+impl fmt::Display for CompactionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MB: f64 = 1024.0 * 1024.0;
+        writeln!(
+            f,
+            "Flush: {} flushes, {:.3}MB written",
+            self.num_flushes,
+            self.flush_bytes as f64 / MB
+        )?;
+        writeln!(
+            f,
+            "Level  Comps  Read(MB)  Write(MB)  Files(in/out)  Tombstones  Versions  Time(s)"
+        )?;
+        let total = self.total();
+        let rows = self
+            .levels
+            .iter()
+            .map(|(level, stats)| (format!("L{}", level), stats))
+            .chain(std::iter::once(("Sum".to_string(), &total)));
+        for (name, stats) in rows {
+            writeln!(
+                f,
+                "{:<5}  {:>5}  {:>8.3}  {:>9.3}  {:>13}  {:>10}  {:>8}  {:>7.3}",
+                name,
+                stats.num_compactions,
+                stats.bytes_read as f64 / MB,
+                stats.bytes_written as f64 / MB,
+                format!("{}/{}", stats.input_files, stats.output_files),
+                stats.dropped_tombstones,
+                stats.dropped_versions,
+                stats.compaction_time.as_secs_f64()
+            )?;
+        }
+        write!(f, "Write amplification: {:.3}x", self.write_amplification())
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
 
+use super::RunningCompactions;
 use crate::lsm_storage::LsmStorageState;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,7 +11,7 @@ pub struct TieredCompactionTask {
     pub bottom_tier_included: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TieredCompactionOptions {
     pub num_tiers: usize,
     pub max_size_amplification_percent: usize,
@@ -32,69 +33,108 @@ impl TieredCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &RunningCompactions::default())
+    }
+
+    /// The total size in bytes of each tier, from the newest to the oldest.
+    pub(super) fn tier_sizes(snapshot: &LsmStorageState) -> Vec<u64> {
+        snapshot
+            .levels
+            .iter()
+            .map(|(_, ssts)| {
+                ssts.iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum::<u64>()
+            })
+            .collect()
+    }
+
+    /// The task merging the adjacent tiers `[begin, end)`.
+    fn merge_tiers(snapshot: &LsmStorageState, begin: usize, end: usize) -> TieredCompactionTask {
+        TieredCompactionTask {
+            tiers: snapshot.levels[begin..end].to_vec(),
+            bottom_tier_included: end == snapshot.levels.len(),
+        }
+    }
+
+    /// Generate a task if no job is `running`. Tasks merge adjacent tiers, and the tiers are renumbered when they are
+    /// applied, so they cannot run concurrently. Tier sizes are the total bytes of their SSTs.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        running: &RunningCompactions,
+    ) -> Option<TieredCompactionTask> {
+        if !running.is_empty() {
+            return None;
+        }
         assert!(
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
         );
-        if snapshot.levels.len() < self.options.num_tiers {
+        let num_tiers = snapshot.levels.len();
+        // a single tier has nothing to be merged with, whatever `num_tiers` is configured to
+        if num_tiers < self.options.num_tiers.max(2) {
             return None;
         }
+        let tier_sizes = Self::tier_sizes(snapshot);
+        let max_merge_width = self
+            .options
+            .max_merge_width
+            .unwrap_or(usize::MAX)
+            .clamp(2, num_tiers);
+
         // compaction triggered by space amplification ratio
-        let mut size = 0;
-        for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-        }
+        let upper_tiers_size = tier_sizes[..num_tiers - 1].iter().sum::<u64>();
         let space_amp_ratio =
-            (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
+            upper_tiers_size as f64 / tier_sizes[num_tiers - 1].max(1) as f64 * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
             println!(
                 "compaction triggered by space amplification ratio: {}",
                 space_amp_ratio
             );
-            return Some(TieredCompactionTask {
-                tiers: snapshot.levels.clone(),
-                bottom_tier_included: true,
-            });
+            return Some(Self::merge_tiers(snapshot, 0, num_tiers));
         }
+
+        // compaction triggered by size ratio: merge a run of adjacent tiers that is much smaller than the tier below
+        // it, starting from the newest tiers
         let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
-        // compaction triggered by size ratio
-        let mut size = 0;
-        for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-            let next_level_size = snapshot.levels[id + 1].1.len();
-            let current_size_ratio = next_level_size as f64 / size as f64;
-            if current_size_ratio > size_ratio_trigger && id + 1 >= self.options.min_merge_width {
-                println!(
-                    "compaction triggered by size ratio: {} > {}",
-                    current_size_ratio * 100.0,
-                    size_ratio_trigger * 100.0
-                );
-                return Some(TieredCompactionTask {
-                    tiers: snapshot
-                        .levels
-                        .iter()
-                        .take(id + 1)
-                        .cloned()
-                        .collect::<Vec<_>>(),
-                    bottom_tier_included: id + 1 >= snapshot.levels.len(),
-                });
+        for begin in 0..num_tiers - 1 {
+            let mut size = 0;
+            for end in begin + 1..num_tiers.min(begin + max_merge_width + 1) {
+                size += tier_sizes[end - 1];
+                let current_size_ratio = tier_sizes[end] as f64 / size.max(1) as f64;
+                if current_size_ratio > size_ratio_trigger
+                    && end - begin >= self.options.min_merge_width
+                {
+                    println!(
+                        "compaction triggered by size ratio: {} > {}, merging tiers {}..{}",
+                        current_size_ratio * 100.0,
+                        size_ratio_trigger * 100.0,
+                        begin,
+                        end
+                    );
+                    return Some(Self::merge_tiers(snapshot, begin, end));
+                }
             }
         }
-        // trying to reduce sorted runs without respecting size ratio
-        let num_tiers_to_take = snapshot
-            .levels
-            .len()
-            .min(self.options.max_merge_width.unwrap_or(usize::MAX));
-        println!("compaction triggered by reducing sorted runs");
-        Some(TieredCompactionTask {
-            tiers: snapshot
-                .levels
-                .iter()
-                .take(num_tiers_to_take)
-                .cloned()
-                .collect::<Vec<_>>(),
-            bottom_tier_included: snapshot.levels.len() >= num_tiers_to_take,
-        })
+
+        // trying to reduce sorted runs without respecting size ratio, by merging the adjacent tiers that are the
+        // cheapest to rewrite
+        let (begin, _) = (0..=num_tiers - max_merge_width)
+            .map(|begin| {
+                let size = tier_sizes[begin..begin + max_merge_width]
+                    .iter()
+                    .sum::<u64>();
+                (begin, size)
+            })
+            .min_by_key(|(_, size)| *size)
+            .unwrap();
+        println!(
+            "compaction triggered by reducing sorted runs, merging tiers {}..{}",
+            begin,
+            begin + max_merge_width
+        );
+        Some(Self::merge_tiers(snapshot, begin, begin + max_merge_width))
     }
 
     pub fn apply_compaction_result(
@@ -103,38 +143,49 @@ impl TieredCompactionController {
         task: &TieredCompactionTask,
         output: &[usize],
     ) -> (LsmStorageState, Vec<usize>) {
-        assert!(
-            snapshot.l0_sstables.is_empty(),
-            "should not add l0 ssts in tiered compaction"
-        );
-        let mut snapshot = snapshot.clone();
-        let mut tier_to_remove = task
-            .tiers
-            .iter()
-            .map(|(x, y)| (*x, y))
-            .collect::<HashMap<_, _>>();
-        let mut levels = Vec::new();
-        let mut new_tier_added = false;
-        let mut files_to_remove = Vec::new();
-        for (tier_id, files) in &snapshot.levels {
-            if let Some(ffiles) = tier_to_remove.remove(tier_id) {
-                // the tier should be removed
-                assert_eq!(ffiles, files, "file changed after issuing compaction task");
-                files_to_remove.extend(ffiles.iter().copied());
-            } else {
-                // retain the tier
-                levels.push((*tier_id, files.clone()));
-            }
-            if tier_to_remove.is_empty() && !new_tier_added {
-                // add the compacted tier to the LSM tree
-                new_tier_added = true;
-                levels.push((output[0], output.to_vec()));
-            }
+        replace_tiers(snapshot, &task.tiers, output)
+    }
+}
+
+/// Replace the adjacent `tiers` of `snapshot` with one tier of `output`, returning the new state and the SSTs to
+/// remove.
+pub(super) fn replace_tiers(
+    snapshot: &LsmStorageState,
+    tiers: &[(usize, Vec<usize>)],
+    output: &[usize],
+) -> (LsmStorageState, Vec<usize>) {
+    assert!(
+        snapshot.l0_sstables.is_empty(),
+        "should not add l0 ssts in tiered compaction"
+    );
+    let mut snapshot = snapshot.clone();
+    let mut tier_to_remove = tiers
+        .iter()
+        .map(|(x, y)| (*x, y))
+        .collect::<HashMap<_, _>>();
+    let mut levels = Vec::new();
+    let mut new_tier_added = false;
+    let mut files_to_remove = Vec::new();
+    for (tier_id, files) in &snapshot.levels {
+        if let Some(ffiles) = tier_to_remove.remove(tier_id) {
+            // the tier should be removed
+            assert_eq!(ffiles, files, "file changed after issuing compaction task");
+            files_to_remove.extend(ffiles.iter().copied());
+        } else {
+            // retain the tier
+            levels.push((*tier_id, files.clone()));
         }
-        if !tier_to_remove.is_empty() {
-            unreachable!("some tiers not found??");
+        if tier_to_remove.is_empty() && !new_tier_added {
+            // add the compacted tier to the LSM tree, unless every key was dropped
+            new_tier_added = true;
+            if let Some(tier_id) = output.first() {
+                levels.push((*tier_id, output.to_vec()));
+            }
         }
-        snapshot.levels = levels;
-        (snapshot, files_to_remove)
     }
+    if !tier_to_remove.is_empty() {
+        unreachable!("some tiers not found??");
+    }
+    snapshot.levels = levels;
+    (snapshot, files_to_remove)
 }
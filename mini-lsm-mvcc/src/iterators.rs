@@ -13,6 +13,11 @@ pub trait StorageIterator {
     /// Get the current key.
     fn key(&self) -> Self::KeyType<'_>;
 
+    /// Get the expiry timestamp (unix time in milliseconds) of the current entry, if it has one.
+    fn expire_at(&self) -> Option<u64> {
+        None
+    }
+
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
@@ -102,6 +102,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn expire_at(&self) -> Option<u64> {
+        self.current.as_ref().unwrap().expire_at()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
@@ -88,6 +88,10 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         self.current.as_ref().unwrap().1.value()
     }
 
+    fn expire_at(&self) -> Option<u64> {
+        self.current.as_ref().unwrap().1.expire_at()
+    }
+
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
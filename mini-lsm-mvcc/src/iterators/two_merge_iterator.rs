@@ -69,6 +69,14 @@ impl<
         }
     }
 
+    fn expire_at(&self) -> Option<u64> {
+        if self.choose_a {
+            self.a.expire_at()
+        } else {
+            self.b.expire_at()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -1,4 +1,5 @@
 pub mod block;
+pub mod change_feed;
 pub mod compact;
 pub mod debug;
 pub mod iterators;
@@ -8,6 +9,9 @@ pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
 pub mod mvcc;
+pub mod options;
+pub mod rate_limiter;
+pub mod scheduler;
 pub mod table;
 pub mod wal;
 
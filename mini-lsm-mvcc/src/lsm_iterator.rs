@@ -7,6 +7,7 @@ use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
+use crate::lsm_storage::unix_time_millis;
 use crate::mem_table::MemTableIterator;
 use crate::table::SsTableIterator;
 
@@ -21,6 +22,8 @@ pub struct LsmIterator {
     end_bound: Bound<Bytes>,
     is_valid: bool,
     read_ts: u64,
+    /// Entries expiring at or before this unix time (in milliseconds) are treated as deleted.
+    now: u64,
     prev_key: Vec<u8>,
 }
 
@@ -35,6 +38,7 @@ impl LsmIterator {
             inner: iter,
             end_bound,
             read_ts,
+            now: unix_time_millis(),
             prev_key: Vec::new(),
         };
         iter.move_to_key()?;
@@ -77,12 +81,18 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
+            if !self.inner.value().is_empty() && !self.is_expired() {
                 break;
             }
         }
         Ok(())
     }
+
+    fn is_expired(&self) -> bool {
+        self.inner
+            .expire_at()
+            .is_some_and(|expire_at| expire_at <= self.now)
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -1,18 +1,23 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
-use parking_lot::{Mutex, MutexGuard, RwLock};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
+use serde::{Deserialize, Serialize};
 
-use crate::block::Block;
+use crate::block::{Block, MAX_VALUE_SIZE};
+use crate::change_feed::{changes_from_memtables, Change, ChangeFeed};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionOptions, CompactionStats, FifoCompactionController,
+    LazyLeveledCompactionController, LeveledCompactionController, LeveledCompactionOptions,
+    RunningCompactions, SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+    SstPartitioner, SstSelection, TieredCompactionController,
 };
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
@@ -20,11 +25,15 @@ use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
 use crate::key::{self, KeySlice};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
-use crate::manifest::{Manifest, ManifestRecord};
+use crate::manifest::{Manifest, ManifestRecord, ManifestSnapshot};
 use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
 use crate::mvcc::txn::{Transaction, TxnIterator};
 use crate::mvcc::LsmMvccInner;
+use crate::options::OptionsFile;
+use crate::rate_limiter::{IoPriority, RateLimiter, RateLimiterOptions};
+use crate::scheduler::{BackgroundEvent, BackgroundScheduler, SchedulerState};
 use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::wal::{Wal, WalRecords, WalRecoveryMode, WalRecoveryReport};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
@@ -44,12 +53,24 @@ pub struct LsmStorageState {
     pub sstables: HashMap<usize, Arc<SsTable>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
+    /// Put a key-value pair that is no longer visible once the TTL has elapsed since the commit.
+    PutWithTtl(T, T, Duration),
     Del(T),
 }
 
 impl LsmStorageState {
+    /// The ids of all SSTs in L0 and the levels.
+    fn sst_ids(&self) -> HashSet<usize> {
+        self.l0_sstables
+            .iter()
+            .chain(self.levels.iter().flat_map(|(_, ssts)| ssts))
+            .copied()
+            .collect()
+    }
+
     fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
@@ -57,7 +78,9 @@ impl LsmStorageState {
                 ..=*max_levels)
                 .map(|level| (level, Vec::new()))
                 .collect::<Vec<_>>(),
-            CompactionOptions::Tiered(_) => Vec::new(),
+            CompactionOptions::Tiered(_)
+            | CompactionOptions::LazyLeveled(_)
+            | CompactionOptions::Fifo(_) => Vec::new(),
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
@@ -68,9 +91,55 @@ impl LsmStorageState {
             sstables: Default::default(),
         }
     }
+
+    /// Add or remove levels of a leveled state to match `options`, keeping the existing levels at the bottom.
+    /// Returns whether any level was added or removed. Only empty levels can be removed.
+    pub(crate) fn migrate_levels(&mut self, options: &LsmStorageOptions) -> Result<bool> {
+        let max_levels = match &options.compaction_options {
+            CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
+            | CompactionOptions::Simple(SimpleLeveledCompactionOptions { max_levels, .. }) => {
+                *max_levels
+            }
+            CompactionOptions::NoCompaction => 1,
+            // FIFO compaction keeps all SSTs in L0
+            CompactionOptions::Fifo(_) => 0,
+            // sorted runs are not levels
+            CompactionOptions::Tiered(_) | CompactionOptions::LazyLeveled(_) => return Ok(false),
+        };
+        let num_levels = self.levels.len();
+        if num_levels == max_levels {
+            return Ok(false);
+        }
+        if num_levels > max_levels {
+            let removed = num_levels - max_levels;
+            if let Some((level, _)) = self.levels[..removed]
+                .iter()
+                .find(|(_, ssts)| !ssts.is_empty())
+            {
+                bail!(
+                    "cannot reduce the number of levels from {} to {}, level {} is not empty",
+                    num_levels,
+                    max_levels,
+                    level
+                );
+            }
+            self.levels.drain(..removed);
+        } else {
+            let added = (0..max_levels - num_levels).map(|_| (0, Vec::new()));
+            self.levels.splice(0..0, added);
+        }
+        for (idx, (level, _)) in self.levels.iter_mut().enumerate() {
+            *level = idx + 1;
+        }
+        println!(
+            "number of levels changed from {} to {}",
+            num_levels, max_levels
+        );
+        Ok(true)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LsmStorageOptions {
     // Block size in bytes
     pub block_size: usize,
@@ -80,7 +149,32 @@ pub struct LsmStorageOptions {
     pub num_memtable_limit: usize,
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
+    // Directory of the WAL segments, the DB directory if not set
+    pub wal_dir: Option<PathBuf>,
+    // WAL segment size in bytes, each segment file is preallocated to this size
+    pub wal_segment_size: usize,
+    // How to treat damaged WAL records when recovering
+    pub wal_recovery_mode: WalRecoveryMode,
     pub serializable: bool,
+    // Number of latest commits retained for change feed subscribers starting from an earlier commit
+    pub change_feed_retention: usize,
+    // Manifest size in bytes above which a new manifest file is started with a snapshot of the storage layout
+    pub max_manifest_size: usize,
+    // Number of threads opening SSTs when recovering
+    pub recovery_threads: usize,
+    // Maximum number of key ranges a compaction task is split into, each compacted on its own thread
+    pub max_subcompactions: usize,
+    // Maximum number of compaction jobs running at the same time, which is also the number of low-priority threads
+    pub max_background_compactions: usize,
+    // Number of high-priority threads flushing memtables
+    pub max_background_flushes: usize,
+    // Limit the rate of flush and compaction writes if set
+    pub rate_limiter: Option<RateLimiterOptions>,
+    // How leveled compaction picks the SST compacted out of a level
+    pub leveled_sst_selection: SstSelection,
+    // Start a new compaction output SST once the current one overlaps this many bytes of the level below the output
+    // level, so that compacting it down later stays small
+    pub max_grandparent_overlap_bytes: Option<usize>,
 }
 
 impl LsmStorageOptions {
@@ -90,8 +184,20 @@ impl LsmStorageOptions {
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
+            wal_dir: None,
+            wal_segment_size: 4 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
             num_memtable_limit: 50,
             serializable: false,
+            change_feed_retention: 1024,
+            max_manifest_size: 1 << 20,
+            recovery_threads: 4,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            max_background_flushes: 1,
+            rate_limiter: None,
+            leveled_sst_selection: SstSelection::Oldest,
+            max_grandparent_overlap_bytes: None,
         }
     }
 
@@ -101,8 +207,20 @@ impl LsmStorageOptions {
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
             enable_wal: false,
+            wal_dir: None,
+            wal_segment_size: 4 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
             num_memtable_limit: 2,
             serializable: false,
+            change_feed_retention: 1024,
+            max_manifest_size: 1 << 20,
+            recovery_threads: 4,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            max_background_flushes: 1,
+            rate_limiter: None,
+            leveled_sst_selection: SstSelection::Oldest,
+            max_grandparent_overlap_bytes: None,
         }
     }
 
@@ -112,13 +230,25 @@ impl LsmStorageOptions {
             target_sst_size: 1 << 20, // 1MB
             compaction_options,
             enable_wal: false,
+            wal_dir: None,
+            wal_segment_size: 4 << 20,
+            wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
             num_memtable_limit: 2,
             serializable: false,
+            change_feed_retention: 1024,
+            max_manifest_size: 1 << 20,
+            recovery_threads: 4,
+            max_subcompactions: 1,
+            max_background_compactions: 1,
+            max_background_flushes: 1,
+            rate_limiter: None,
+            leveled_sst_selection: SstSelection::Oldest,
+            max_grandparent_overlap_bytes: None,
         }
     }
 }
 
-fn range_overlap(
+pub(crate) fn range_overlap(
     user_begin: Bound<&[u8]>,
     user_end: Bound<&[u8]>,
     table_begin: KeySlice,
@@ -149,9 +279,49 @@ fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bo
     table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
 }
 
+/// The current unix time in milliseconds, the unit of the expiry timestamps stored with TTL keys.
+pub(crate) fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_millis() as u64
+}
+
+/// What a compaction filter does with a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    /// Drop the key, along with its older versions.
+    Remove,
+    /// Keep the key with another value.
+    ChangeValue(Bytes),
+}
+
+/// Decides what compactions do with the keys they rewrite. Filters only see the latest version of each key at the
+/// watermark, which no snapshot reads an older version of, and neither delete tombstones nor expired keys.
+pub trait CompactionFilter: Send + Sync {
+    /// Decide what to do with `key`, written at `ts` with `value`, as it is compacted into `level`.
+    fn filter(&self, key: &[u8], ts: u64, value: &[u8], level: usize) -> CompactionDecision;
+}
+
+impl<T: CompactionFilter + ?Sized> CompactionFilter for Arc<T> {
+    fn filter(&self, key: &[u8], ts: u64, value: &[u8], level: usize) -> CompactionDecision {
+        self.as_ref().filter(key, ts, value, level)
+    }
+}
+
+/// Removes the keys starting with a prefix.
 #[derive(Clone, Debug)]
-pub enum CompactionFilter {
-    Prefix(Bytes),
+pub struct PrefixCompactionFilter(pub Bytes);
+
+impl CompactionFilter for PrefixCompactionFilter {
+    fn filter(&self, key: &[u8], _ts: u64, _value: &[u8], _level: usize) -> CompactionDecision {
+        if key.starts_with(&self.0) {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
 }
 
 /// The storage interface of the LSM tree.
@@ -163,49 +333,67 @@ pub(crate) struct LsmStorageInner {
     next_sst_id: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
     pub(crate) compaction_controller: CompactionController,
+    /// The compaction jobs being executed by the compaction workers.
+    pub(crate) running_compactions: Mutex<RunningCompactions>,
     pub(crate) manifest: Option<Manifest>,
+    pub(crate) wal: Option<Arc<Wal>>,
+    /// What was dropped when recovering from the WAL.
+    pub(crate) wal_recovery_report: WalRecoveryReport,
     pub(crate) mvcc: Option<LsmMvccInner>,
-    pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) compaction_filters: Arc<Mutex<Vec<Arc<dyn CompactionFilter>>>>,
+    pub(crate) sst_partitioner: Mutex<Option<Arc<dyn SstPartitioner>>>,
+    pub(crate) change_feed: ChangeFeed,
+    /// Shared by flush, compaction, and any other background writes.
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+    /// Cumulative flush and compaction statistics since the storage was opened.
+    pub(crate) compaction_stats: Mutex<CompactionStats>,
+    /// How many times background work has been paused and not continued yet.
+    pub(crate) background_work_paused: AtomicUsize,
+    /// Runs the background flushes and compactions once started by `MiniLsm::open`.
+    pub(crate) scheduler: BackgroundScheduler,
+    /// The flush jobs scheduled and not started yet.
+    pub(crate) scheduled_flushes: AtomicUsize,
+    /// The background flushes being executed.
+    pub(crate) running_flushes: AtomicUsize,
+    /// Notified with `running_compactions` when a compaction job is released or a background flush finishes.
+    pub(crate) background_job_finished: Condvar,
+    /// When the scheduled timer for a compaction triggered by the time fires.
+    pub(crate) compaction_timer: Mutex<Option<SystemTime>>,
+    /// Lets the jobs scheduled on events own the storage.
+    pub(crate) weak_self: OnceLock<Weak<LsmStorageInner>>,
+}
+
+/// Read the version `iter` is positioned at, if it is a version of `key`: `Some(None)` for a delete tombstone or an
+/// entry expired at `now`, which hide the older versions like a value does.
+fn visible_value<'a, I>(iter: &'a I, key: &[u8], now: u64) -> Option<Option<Bytes>>
+where
+    I: StorageIterator<KeyType<'a> = KeySlice<'a>> + 'a,
+{
+    if !iter.is_valid() || iter.key().key_ref() != key {
+        return None;
+    }
+    let expired = iter.expire_at().is_some_and(|expire_at| expire_at <= now);
+    if iter.value().is_empty() || expired {
+        return Some(None);
+    }
+    Some(Some(Bytes::copy_from_slice(iter.value())))
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
 pub struct MiniLsm {
     pub(crate) inner: Arc<LsmStorageInner>,
-    /// Notifies the L0 flush thread to stop working. (In week 1 day 6)
-    flush_notifier: crossbeam_channel::Sender<()>,
-    /// The handle for the flush thread. (In week 1 day 6)
-    flush_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
-    /// Notifies the compaction thread to stop working. (In week 2)
-    compaction_notifier: crossbeam_channel::Sender<()>,
-    /// The handle for the compaction thread. (In week 2)
-    compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl Drop for MiniLsm {
     fn drop(&mut self) {
-        self.compaction_notifier.send(()).ok();
-        self.flush_notifier.send(()).ok();
+        self.inner.scheduler.shutdown();
     }
 }
 
 impl MiniLsm {
     pub fn close(&self) -> Result<()> {
         self.inner.sync_dir()?;
-        self.compaction_notifier.send(()).ok();
-        self.flush_notifier.send(()).ok();
-
-        let mut compaction_thread = self.compaction_thread.lock();
-        if let Some(compaction_thread) = compaction_thread.take() {
-            compaction_thread
-                .join()
-                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-        }
-        let mut flush_thread = self.flush_thread.lock();
-        if let Some(flush_thread) = flush_thread.take() {
-            flush_thread
-                .join()
-                .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-        }
+        self.inner.scheduler.shutdown();
 
         if self.inner.options.enable_wal {
             self.inner.sync()?;
@@ -236,21 +424,34 @@ impl MiniLsm {
     /// not exist.
     pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
         let inner = Arc::new(LsmStorageInner::open(path, options)?);
-        let (tx1, rx) = crossbeam_channel::unbounded();
-        let compaction_thread = inner.spawn_compaction_thread(rx)?;
-        let (tx2, rx) = crossbeam_channel::unbounded();
-        let flush_thread = inner.spawn_flush_thread(rx)?;
-        Ok(Arc::new(Self {
-            inner,
-            flush_notifier: tx2,
-            flush_thread: Mutex::new(flush_thread),
-            compaction_notifier: tx1,
-            compaction_thread: Mutex::new(compaction_thread),
-        }))
+        inner.start_background_work();
+        Ok(Arc::new(Self { inner }))
+    }
+
+    /// What recovering from the WAL dropped when opening the storage.
+    pub fn wal_recovery_report(&self) -> &WalRecoveryReport {
+        &self.inner.wal_recovery_report
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
-        self.inner.add_compaction_filter(compaction_filter)
+    /// The rate limiter of background writes, which other background work such as scrubbing can charge against.
+    pub fn rate_limiter(&self) -> Option<&Arc<RateLimiter>> {
+        self.inner.rate_limiter.as_ref()
+    }
+
+    /// Cumulative flush and compaction statistics since the storage was opened.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.inner.compaction_stats.lock().clone()
+    }
+
+    /// Register a filter applied by all later compactions. Filters run in the order they are added.
+    pub fn add_compaction_filter(&self, compaction_filter: impl CompactionFilter + 'static) {
+        self.inner
+            .add_compaction_filter(Arc::new(compaction_filter))
+    }
+
+    /// Set the partitioner splitting the output of all later compactions, replacing the previous one.
+    pub fn set_sst_partitioner(&self, sst_partitioner: impl SstPartitioner + 'static) {
+        *self.inner.sst_partitioner.lock() = Some(Arc::new(sst_partitioner));
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
@@ -265,10 +466,20 @@ impl MiniLsm {
         self.inner.put(key, value)
     }
 
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.inner.put_with_ttl(key, value, ttl)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
 
+    /// Subscribe to the write batches committed at or after `from_ts`, in commit order. The retained ones are
+    /// replayed first, followed by new commits as they happen. Fails if some of them are no longer retained.
+    pub fn subscribe(&self, from_ts: u64) -> Result<crossbeam_channel::Receiver<Change>> {
+        self.inner.change_feed.subscribe(from_ts)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -296,6 +507,33 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    /// Stop background flushes and compactions, e.g., while taking a backup. A running flush is finished, while the
+    /// running compactions are cancelled and their partial output deleted. Returns once none of them runs anymore.
+    /// Manual compactions by `compact_range` or `force_full_compaction` are cancelled as well, and fail with an error
+    /// while paused. Pauses nest, and each has to be matched by `continue_background_work`.
+    pub fn pause_background_work(&self) {
+        self.inner.pause_background_work()
+    }
+
+    /// Resume the background work stopped by `pause_background_work`.
+    pub fn continue_background_work(&self) -> Result<()> {
+        self.inner.continue_background_work()
+    }
+
+    /// The threads and jobs of the background flush and compaction pools.
+    pub fn background_scheduler_state(&self) -> SchedulerState {
+        self.inner.scheduler.state()
+    }
+
+    pub fn compact_range(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        target_level: usize,
+    ) -> Result<()> {
+        self.inner.compact_range(lower, upper, target_level)
+    }
 }
 
 impl LsmStorageInner {
@@ -315,15 +553,32 @@ impl LsmStorageInner {
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
-        let mut state = LsmStorageState::create(&options);
         let path = path.as_ref();
+        let stored_options = if Manifest::exists(path) {
+            OptionsFile::read(path, &options)?
+        } else {
+            None
+        };
+        if let Some(stored_options) = &stored_options {
+            OptionsFile::check_compatibility(stored_options, &options)?;
+            OptionsFile::migrate_wal_dir(path, stored_options, &options)?;
+        }
+        // the manifest is replayed into the layout of the stored options, and migrated to the new one afterwards
+        let mut state = LsmStorageState::create(stored_options.as_ref().unwrap_or(&options));
+        let mut levels_migrated = false;
         let mut next_sst_id = 1;
         let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache,
         let manifest;
+        let mut wal = None;
+        let mut wal_recovery_report = WalRecoveryReport::default();
+        let wal_dir = options.wal_dir.as_deref().unwrap_or(path).to_path_buf();
 
         let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            CompactionOptions::Leveled(leveled_options) => {
+                CompactionController::Leveled(LeveledCompactionController::with_sst_selection(
+                    leveled_options.clone(),
+                    options.leveled_sst_selection.build(),
+                ))
             }
             CompactionOptions::Tiered(options) => {
                 CompactionController::Tiered(TieredCompactionController::new(options.clone()))
@@ -331,27 +586,44 @@ impl LsmStorageInner {
             CompactionOptions::Simple(options) => CompactionController::Simple(
                 SimpleLeveledCompactionController::new(options.clone()),
             ),
+            CompactionOptions::Fifo(options) => {
+                CompactionController::Fifo(FifoCompactionController::new(options.clone()))
+            }
+            CompactionOptions::LazyLeveled(options) => CompactionController::LazyLeveled(
+                LazyLeveledCompactionController::new(options.clone()),
+            ),
             CompactionOptions::NoCompaction => CompactionController::NoCompaction,
         };
 
         if !path.exists() {
             std::fs::create_dir_all(path).context("failed to create DB dir")?;
         }
-        let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
-        if !manifest_path.exists() {
+        // commits that are not in the memtables have been flushed and cannot be replayed to change feed subscribers
+        let mut flushed_commit_ts = 0;
+        if !Manifest::exists(path) {
             if options.enable_wal {
+                let new_wal = Arc::new(Wal::create(&wal_dir, options.wal_segment_size)?);
                 state.memtable = Arc::new(MemTable::create_with_wal(
                     state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
+                    new_wal.clone(),
+                ));
+                wal = Some(new_wal);
             }
-            manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
+            manifest = Manifest::create(path).context("failed to create manifest")?;
             manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
         } else {
-            let (m, records) = Manifest::recover(&manifest_path)?;
+            let (m, records) = Manifest::recover(path)?;
+            for record in &records {
+                if let ManifestRecord::LegacyCompaction(task, _) = record {
+                    OptionsFile::check_legacy_compaction(task, &options)?;
+                }
+            }
             let mut memtables = BTreeSet::new();
+            // SSTs that are no longer part of the state, whose files may not have been deleted before a crash
+            let mut removed_ssts = HashSet::new();
             for record in records {
+                let sst_ids_before = state.sst_ids();
                 match record {
                     ManifestRecord::Flush(sst_id) => {
                         let res = memtables.remove(&sst_id);
@@ -367,59 +639,106 @@ impl LsmStorageInner {
                         next_sst_id = next_sst_id.max(x);
                         memtables.insert(x);
                     }
-                    ManifestRecord::Compaction(task, output) => {
+                    ManifestRecord::Compaction(edit) => {
+                        edit.apply(&mut state);
+                        let max_added = edit.added.iter().flat_map(|(_, ids)| ids).max();
+                        next_sst_id = next_sst_id.max(max_added.copied().unwrap_or_default());
+                    }
+                    ManifestRecord::LegacyCompaction(task, output) => {
                         let (new_state, _) = compaction_controller
                             .apply_compaction_result(&state, &task, &output, true);
-                        // TODO: apply remove again
                         state = new_state;
                         next_sst_id =
                             next_sst_id.max(output.iter().max().copied().unwrap_or_default());
                     }
+                    ManifestRecord::Snapshot(snapshot) => {
+                        memtables = snapshot.memtables.into_iter().collect();
+                        state.l0_sstables = snapshot.l0_sstables;
+                        state.levels = snapshot.levels;
+                        next_sst_id = next_sst_id.max(snapshot.max_id);
+                    }
                 }
+                let sst_ids_after = state.sst_ids();
+                removed_ssts.extend(sst_ids_before.difference(&sst_ids_after));
             }
 
-            let mut sst_cnt = 0;
+            levels_migrated = state.migrate_levels(&options)?;
+            Self::reconcile_dir(path, &state, &removed_ssts, &memtables)?;
+
             // recover SSTs
-            for table_id in state
+            let table_ids = state
                 .l0_sstables
                 .iter()
                 .chain(state.levels.iter().flat_map(|(_, files)| files))
-            {
-                let table_id = *table_id;
-                let sst = SsTable::open(
-                    table_id,
-                    Some(block_cache.clone()),
-                    FileObject::open(&Self::path_of_sst_static(path, table_id))
-                        .context("failed to open SST")?,
-                )?;
+                .copied()
+                .collect::<Vec<_>>();
+            let ssts = Self::open_ssts(path, &table_ids, &block_cache, options.recovery_threads)?;
+            let sst_cnt = ssts.len();
+            for sst in ssts {
                 last_commit_ts = last_commit_ts.max(sst.max_ts());
-                state.sstables.insert(table_id, Arc::new(sst));
-                sst_cnt += 1;
+                state.sstables.insert(sst.sst_id(), Arc::new(sst));
             }
             println!("{} SSTs opened", sst_cnt);
 
             next_sst_id += 1;
 
-            // Sort SSTs on each level (only for leveled compaction)
-            if let CompactionController::Leveled(_) = &compaction_controller {
-                for (_id, ssts) in &mut state.levels {
-                    ssts.sort_by(|x, y| {
-                        state
-                            .sstables
-                            .get(x)
-                            .unwrap()
-                            .first_key()
-                            .cmp(state.sstables.get(y).unwrap().first_key())
-                    })
-                }
+            // Sort SSTs on each level, as every level (or tier) is a sorted run
+            for (_id, ssts) in &mut state.levels {
+                ssts.sort_by(|x, y| {
+                    state
+                        .sstables
+                        .get(x)
+                        .unwrap()
+                        .first_key()
+                        .cmp(state.sstables.get(y).unwrap().first_key())
+                })
             }
 
+            flushed_commit_ts = last_commit_ts;
+
             // recover memtables
             if options.enable_wal {
+                let (recovered_wal, mut records, mut report) = Wal::recover(
+                    &wal_dir,
+                    options.wal_segment_size,
+                    options.wal_recovery_mode,
+                )?;
+                let recovered_wal = Arc::new(recovered_wal);
+                // the WAL files of memtables from before the WAL was segmented are logged again into the segmented
+                // WAL, and deleted once that is synced
+                let legacy_wals = memtables
+                    .iter()
+                    .map(|id| (*id, Self::path_of_legacy_wal_static(path, *id)))
+                    .filter(|(_, legacy_wal)| legacy_wal.exists())
+                    .collect::<Vec<_>>();
+                let legacy_batches =
+                    Wal::read_legacy(&legacy_wals, options.wal_recovery_mode, &mut report)?;
+                for ((id, _), batches) in legacy_wals.iter().zip(legacy_batches) {
+                    let records = records.entry(*id).or_insert_with(WalRecords::new);
+                    for batch in batches {
+                        let data = batch
+                            .iter()
+                            .map(|(key, value)| (key.as_key_slice(), &value[..]))
+                            .collect::<Vec<_>>();
+                        recovered_wal.put_batch(*id, &data)?;
+                        for (key, value) in batch {
+                            records.insert(key, (value, None));
+                        }
+                    }
+                }
+                for corruption in &report.corruptions {
+                    println!("dropped {}", corruption);
+                }
+                if report.dropped_records > 0 {
+                    println!("dropped {} WAL records", report.dropped_records);
+                }
+                wal_recovery_report = report;
                 let mut wal_cnt = 0;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
+                    let Some(records) = records.remove(id) else {
+                        continue;
+                    };
+                    let memtable = MemTable::recover_from_wal(*id, recovered_wal.clone(), records);
                     let max_ts = memtable
                         .map
                         .iter()
@@ -432,19 +751,57 @@ impl LsmStorageInner {
                         wal_cnt += 1;
                     }
                 }
-                println!("{} WALs recovered", wal_cnt);
+                println!("{} memtables recovered from WAL", wal_cnt);
+                if !legacy_wals.is_empty() {
+                    recovered_wal.sync()?;
+                    for (_, legacy_wal) in &legacy_wals {
+                        std::fs::remove_file(legacy_wal)?;
+                    }
+                    File::open(path)?.sync_all()?;
+                    println!("{} legacy WAL files migrated", legacy_wals.len());
+                }
                 state.memtable = Arc::new(MemTable::create_with_wal(
                     next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
+                    recovered_wal.clone(),
+                ));
+                // segments that only hold flushed memtables can be reused right away
+                recovered_wal.purge(
+                    state
+                        .imm_memtables
+                        .last()
+                        .map_or(next_sst_id, |memtable| memtable.id()),
+                );
+                wal = Some(recovered_wal);
             } else {
+                if let Some(id) = memtables
+                    .iter()
+                    .find(|id| Self::path_of_legacy_wal_static(path, **id).exists())
+                {
+                    bail!(
+                        "memtable {} has a legacy WAL file, open with the WAL enabled to recover it",
+                        id
+                    );
+                }
                 state.memtable = Arc::new(MemTable::create(next_sst_id));
             }
-            m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            // a legacy manifest is migrated below by starting a new one with a snapshot, which records the memtable
+            if !m.is_legacy() {
+                m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
+            }
             next_sst_id += 1;
             manifest = m;
         };
 
+        let change_feed = ChangeFeed::new(
+            options.change_feed_retention,
+            flushed_commit_ts,
+            changes_from_memtables(&state.imm_memtables),
+        );
+
+        let rate_limiter = options
+            .rate_limiter
+            .clone()
+            .map(|options| Arc::new(RateLimiter::new(options)));
         let storage = Self {
             state: Arc::new(RwLock::new(Arc::new(state))),
             state_lock: Mutex::new(()),
@@ -452,21 +809,219 @@ impl LsmStorageInner {
             block_cache,
             next_sst_id: AtomicUsize::new(next_sst_id),
             compaction_controller,
+            running_compactions: Mutex::new(RunningCompactions::default()),
             manifest: Some(manifest),
+            wal,
+            wal_recovery_report,
             options: options.into(),
             mvcc: Some(LsmMvccInner::new(last_commit_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            sst_partitioner: Mutex::new(None),
+            change_feed,
+            rate_limiter,
+            compaction_stats: Mutex::new(CompactionStats::default()),
+            background_work_paused: AtomicUsize::new(0),
+            scheduler: BackgroundScheduler::new(),
+            scheduled_flushes: AtomicUsize::new(0),
+            running_flushes: AtomicUsize::new(0),
+            background_job_finished: Condvar::new(),
+            compaction_timer: Mutex::new(None),
+            weak_self: OnceLock::new(),
         };
         storage.sync_dir()?;
+        if levels_migrated {
+            storage.manifest().rollover(storage.manifest_snapshot())?;
+        } else {
+            storage.maybe_rollover_manifest()?;
+        }
+        // written after the manifest, which has to be in the layout of the options first
+        if stored_options.as_ref() != Some(&*storage.options) {
+            OptionsFile::write(path, &storage.options)?;
+        }
 
         Ok(storage)
     }
 
-    pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
+    /// Compare the files in the DB directory with the recovered `state`. SSTs removed by a recorded compaction are
+    /// deleted. Other unknown SSTs, e.g., the output of a flush or compaction that crashed before it was recorded, and
+    /// WAL files from before the WAL was segmented that do not belong to the unflushed `memtables` are moved to the
+    /// `lost` directory. Fails if any SST of the state is missing.
+    fn reconcile_dir(
+        path: &Path,
+        state: &LsmStorageState,
+        removed_ssts: &HashSet<usize>,
+        memtables: &BTreeSet<usize>,
+    ) -> Result<()> {
+        let live_ssts = state.sst_ids();
+        let mut missing = live_ssts
+            .iter()
+            .map(|id| Self::path_of_sst_static(path, *id))
+            .filter(|sst_path| !sst_path.exists())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            missing.sort();
+            bail!("SSTs referenced by the manifest are missing: {:?}", missing);
+        }
+
+        let mut deleted = 0;
+        let mut lost = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let file_path = entry?.path();
+            let Some(stem) = file_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            match file_path.extension().and_then(|ext| ext.to_str()) {
+                Some("sst") if live_ssts.contains(&stem) => {}
+                Some("sst") if removed_ssts.contains(&stem) => {
+                    std::fs::remove_file(&file_path)?;
+                    deleted += 1;
+                }
+                // migrated into the segmented WAL when the memtables are recovered
+                Some("wal") if memtables.contains(&stem) => {}
+                Some("sst") | Some("wal") => lost.push(file_path),
+                _ => {}
+            }
+        }
+        if deleted > 0 {
+            println!("{} SSTs of finished compactions deleted", deleted);
+        }
+        if !lost.is_empty() {
+            let lost_dir = path.join("lost");
+            std::fs::create_dir_all(&lost_dir)?;
+            for file_path in &lost {
+                std::fs::rename(file_path, lost_dir.join(file_path.file_name().unwrap()))?;
+            }
+            println!(
+                "{} unknown files moved to {}",
+                lost.len(),
+                lost_dir.display()
+            );
+        }
+        if deleted > 0 || !lost.is_empty() {
+            File::open(path)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Open the SSTs of `table_ids` on `threads` threads, returning them in the order of `table_ids`. Every SST is
+    /// tried, and the failures are reported together in table id order.
+    fn open_ssts(
+        path: &Path,
+        table_ids: &[usize],
+        block_cache: &Arc<BlockCache>,
+        threads: usize,
+    ) -> Result<Vec<SsTable>> {
+        let open_sst = |table_id: usize| -> Result<SsTable> {
+            SsTable::open(
+                table_id,
+                Some(block_cache.clone()),
+                FileObject::open(&Self::path_of_sst_static(path, table_id))
+                    .context("failed to open SST")?,
+            )
+        };
+        let next = AtomicUsize::new(0);
+        let mut results = std::thread::scope(|scope| {
+            let workers = (0..threads.clamp(1, table_ids.len().max(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let idx = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            let Some(&table_id) = table_ids.get(idx) else {
+                                break;
+                            };
+                            results.push((idx, open_sst(table_id)));
+                        }
+                        results
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        results.sort_by_key(|(idx, _)| *idx);
+
+        let mut ssts = Vec::with_capacity(results.len());
+        let mut errors = Vec::new();
+        for (idx, result) in results {
+            match result {
+                Ok(sst) => ssts.push(sst),
+                Err(e) => errors.push((table_ids[idx], e)),
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort_by_key(|(table_id, _)| *table_id);
+            let report = errors
+                .iter()
+                .map(|(table_id, e)| format!("SST {}: {:#}", table_id, e))
+                .collect::<Vec<_>>()
+                .join("; ");
+            bail!("failed to open {} SSTs: {}", errors.len(), report);
+        }
+        Ok(ssts)
+    }
+
+    /// The layout of the storage to start a new manifest file with.
+    fn manifest_snapshot(&self) -> ManifestSnapshot {
+        let state = self.state.read();
+        ManifestSnapshot {
+            memtables: state
+                .imm_memtables
+                .iter()
+                .rev()
+                .chain(std::iter::once(&state.memtable))
+                .map(|memtable| memtable.id())
+                .collect(),
+            l0_sstables: state.l0_sstables.clone(),
+            levels: state.levels.clone(),
+            max_id: self
+                .next_sst_id
+                .load(std::sync::atomic::Ordering::SeqCst)
+                .saturating_sub(1),
+        }
+    }
+
+    /// Start a new manifest file with a snapshot of the storage layout if the current one has grown too large or is
+    /// in the legacy format. The caller must hold the state lock, or have exclusive access when opening the storage.
+    fn maybe_rollover_manifest(&self) -> Result<()> {
+        let manifest = self.manifest();
+        if manifest.is_legacy() || manifest.size() > self.options.max_manifest_size as u64 {
+            self.manifest().rollover(self.manifest_snapshot())?;
+        }
+        Ok(())
+    }
+
+    /// Append `record` to the manifest, which must describe the current state.
+    pub(crate) fn add_manifest_record(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        record: ManifestRecord,
+    ) -> Result<()> {
+        self.manifest().add_record(state_lock_observer, record)?;
+        self.maybe_rollover_manifest()
+    }
+
+    pub fn add_compaction_filter(&self, compaction_filter: Arc<dyn CompactionFilter>) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
     }
 
+    /// The rate limiter to charge a background write of `priority` against, if any.
+    pub(crate) fn rate_limiter_for(
+        &self,
+        priority: IoPriority,
+    ) -> Option<(&RateLimiter, IoPriority)> {
+        self.rate_limiter
+            .as_deref()
+            .map(|rate_limiter| (rate_limiter, priority))
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.state.read().memtable.sync_wal()
     }
@@ -477,26 +1032,27 @@ impl LsmStorageInner {
         txn.get(key)
     }
 
+    /// Get the version of `key` visible at `read_ts`. The sources are probed from the newest to the oldest, as the
+    /// versions of a key in a newer memtable, SST or level are all newer than those in an older one, and the lookup
+    /// stops at the first source holding a visible version, including a delete tombstone.
     pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
+        let now = unix_time_millis();
+        // the versions of a key are sorted from the newest, so this is the first one visible at `read_ts`
+        let visible_key = KeySlice::from_slice(key, read_ts);
 
-        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
-            memtable_iters.push(Box::new(memtable.scan(
-                Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            let iter = memtable.scan(
+                Bound::Included(visible_key),
                 Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-            )));
+            );
+            if let Some(value) = visible_value(&iter, key, now) {
+                return Ok(value);
+            }
         }
-        let memtable_iter = MergeIterator::create(memtable_iters);
-
-        let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
 
         let keep_table = |key: &[u8], table: &SsTable| {
             if key_within(
@@ -518,47 +1074,46 @@ impl LsmStorageInner {
         for table in snapshot.l0_sstables.iter() {
             let table = snapshot.sstables[table].clone();
             if keep_table(key, &table) {
-                l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                    table,
-                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                )?));
+                let iter = SsTableIterator::create_and_seek_to_key(table, visible_key)?;
+                if let Some(value) = visible_value(&iter, key, now) {
+                    return Ok(value);
+                }
             }
         }
-        let l0_iter = MergeIterator::create(l0_iters);
-        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
         for (_, level_sst_ids) in &snapshot.levels {
-            let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
-            for table in level_sst_ids {
-                let table = snapshot.sstables[table].clone();
-                if keep_table(key, &table) {
-                    level_ssts.push(table);
-                }
+            let level_ssts = level_sst_ids
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .filter(|table| keep_table(key, table))
+                .collect::<Vec<_>>();
+            if level_ssts.is_empty() {
+                continue;
+            }
+            let iter = SstConcatIterator::create_and_seek_to_key(level_ssts, visible_key)?;
+            if let Some(value) = visible_value(&iter, key, now) {
+                return Ok(value);
             }
-            let level_iter = SstConcatIterator::create_and_seek_to_key(
-                level_ssts,
-                KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-            )?;
-            level_iters.push(Box::new(level_iter));
-        }
-
-        let iter = LsmIterator::new(
-            TwoMergeIterator::create(
-                TwoMergeIterator::create(memtable_iter, l0_iter)?,
-                MergeIterator::create(level_iters),
-            )?,
-            Bound::Unbounded,
-            read_ts,
-        )?;
-
-        if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
         }
         Ok(None)
     }
 
     pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
+        for record in batch {
+            if let WriteBatchRecord::Put(_, value) | WriteBatchRecord::PutWithTtl(_, value, _) =
+                record
+            {
+                if value.as_ref().len() > MAX_VALUE_SIZE {
+                    bail!(
+                        "value of {} bytes is larger than {} bytes",
+                        value.as_ref().len(),
+                        MAX_VALUE_SIZE
+                    );
+                }
+            }
+        }
         let _lck = self.mvcc().write_lock.lock();
         let ts = self.mvcc().latest_commit_ts() + 1;
+        let now = unix_time_millis();
         for record in batch {
             match record {
                 WriteBatchRecord::Del(key) => {
@@ -585,8 +1140,27 @@ impl LsmStorageInner {
                     }
                     self.try_freeze(size)?;
                 }
+                WriteBatchRecord::PutWithTtl(key, value, ttl) => {
+                    let key = key.as_ref();
+                    let value = value.as_ref();
+                    assert!(!key.is_empty(), "key cannot be empty");
+                    assert!(!value.is_empty(), "value cannot be empty");
+                    let expire_at = now.saturating_add(ttl.as_millis() as u64);
+                    let size;
+                    {
+                        let guard = self.state.read();
+                        guard.memtable.put_with_expiry(
+                            KeySlice::from_slice(key, ts),
+                            value,
+                            Some(expire_at),
+                        )?;
+                        size = guard.memtable.approximate_size();
+                    }
+                    self.try_freeze(size)?;
+                }
             }
         }
+        self.change_feed.publish(ts, batch);
         self.mvcc().update_commit_ts(ts);
         Ok(ts)
     }
@@ -607,6 +1181,9 @@ impl LsmStorageInner {
                     WriteBatchRecord::Put(key, value) => {
                         txn.put(key.as_ref(), value.as_ref());
                     }
+                    WriteBatchRecord::PutWithTtl(key, value, ttl) => {
+                        txn.put_with_ttl(key.as_ref(), value.as_ref(), *ttl);
+                    }
                 }
             }
             txn.commit()?;
@@ -626,6 +1203,18 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Put a key-value pair that expires once `ttl` has elapsed.
+    pub fn put_with_ttl(self: &Arc<Self>, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        if !self.options.serializable {
+            self.write_batch_inner(&[WriteBatchRecord::PutWithTtl(key, value, ttl)])?;
+        } else {
+            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
+            txn.put_with_ttl(key, value, ttl);
+            txn.commit()?;
+        }
+        Ok(())
+    }
+
     /// Remove a key from the storage by writing an empty value.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
         if !self.options.serializable {
@@ -659,14 +1248,11 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
-    pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+    /// The WAL file of memtable `id`, from before the WAL was segmented.
+    pub(crate) fn path_of_legacy_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
 
-    pub(crate) fn path_of_wal(&self, id: usize) -> PathBuf {
-        Self::path_of_wal_static(&self.path, id)
-    }
-
     pub(super) fn sync_dir(&self) -> Result<()> {
         File::open(&self.path)?.sync_all()?;
         Ok(())
@@ -691,22 +1277,18 @@ impl LsmStorageInner {
     /// Force freeze the current memtable to an immutable memtable
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
         let memtable_id = self.next_sst_id();
-        let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
-                memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
-        } else {
-            Arc::new(MemTable::create(memtable_id))
+        let memtable = match &self.wal {
+            Some(wal) => Arc::new(MemTable::create_with_wal(memtable_id, wal.clone())),
+            None => Arc::new(MemTable::create(memtable_id)),
         };
 
         self.freeze_memtable_with_memtable(memtable)?;
 
-        self.manifest().add_record(
+        self.add_manifest_record(
             state_lock_observer,
             ManifestRecord::NewMemtable(memtable_id),
         )?;
-        self.sync_dir()?;
+        self.schedule_background_work(BackgroundEvent::MemtableFrozen);
 
         Ok(())
     }
@@ -716,23 +1298,25 @@ impl LsmStorageInner {
         let state_lock = self.state_lock.lock();
 
         let flush_memtable;
+        let min_memtable_id;
 
         {
             let guard = self.state.read();
-            flush_memtable = guard
-                .imm_memtables
-                .last()
-                .expect("no imm memtables!")
-                .clone();
+            // another thread may have flushed the last one while we waited for the lock
+            let Some(memtable) = guard.imm_memtables.last() else {
+                return Ok(());
+            };
+            flush_memtable = memtable.clone();
         }
 
         let mut builder = SsTableBuilder::new(self.options.block_size);
         flush_memtable.flush(&mut builder)?;
         let sst_id = flush_memtable.id();
-        let sst = Arc::new(builder.build(
+        let sst = Arc::new(builder.build_with_rate_limiter(
             sst_id,
             Some(self.block_cache.clone()),
             self.path_of_sst(sst_id),
+            self.rate_limiter_for(IoPriority::High),
         )?);
 
         // Add the flushed L0 table to the list.
@@ -751,19 +1335,30 @@ impl LsmStorageInner {
                 snapshot.levels.insert(0, (sst_id, vec![sst_id]));
             }
             println!("flushed {}.sst with size={}", sst_id, sst.table_size());
+            {
+                let mut stats = self.compaction_stats.lock();
+                stats.num_flushes += 1;
+                stats.flush_bytes += sst.table_size();
+            }
             snapshot.sstables.insert(sst_id, sst);
+            min_memtable_id = snapshot
+                .imm_memtables
+                .last()
+                .unwrap_or(&snapshot.memtable)
+                .id();
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
 
-        if self.options.enable_wal {
-            std::fs::remove_file(self.path_of_wal(sst_id))?;
-        }
+        self.add_manifest_record(&state_lock, ManifestRecord::Flush(sst_id))?;
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        // the WAL segments of the flushed memtable can be reused once the flush is recorded
+        if let Some(wal) = &self.wal {
+            wal.purge(min_memtable_id);
+        }
 
         self.sync_dir()?;
+        self.schedule_background_work(BackgroundEvent::FlushFinished);
 
         Ok(())
     }
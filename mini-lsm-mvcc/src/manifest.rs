@@ -1,64 +1,417 @@
+use std::collections::HashSet;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
 use crate::compact::CompactionTask;
+use crate::lsm_storage::LsmStorageState;
 
+/// Starts every binary manifest file, followed by the format version (u16).
+const MANIFEST_MAGIC: u32 = 0x4d4c_534d; // "MLSM"
+const MANIFEST_VERSION: u16 = 1;
+
+const TAG_FLUSH: u8 = 1;
+const TAG_NEW_MEMTABLE: u8 = 2;
+const TAG_COMPACTION: u8 = 3;
+const TAG_SNAPSHOT: u8 = 4;
+
+/// The manifest of a database directory. Records are appended to the manifest file named by `CURRENT`. Once it
+/// grows too large, a new manifest file starting with a snapshot of the storage layout replaces it.
+///
+/// A manifest file starts with `MANIFEST_MAGIC` and the format version. Each record is encoded as
+/// `len (u32) | tag (u8) | fields | checksum (u32)`, where all ids are u64 and lists are prefixed with their length
+/// (u32). Manifests written before the binary format hold JSON records and are only read, to be replaced by a binary
+/// manifest when the storage is opened.
 pub struct Manifest {
-    file: Arc<Mutex<File>>,
+    dir: PathBuf,
+    file: Arc<Mutex<ManifestFile>>,
+}
+
+struct ManifestFile {
+    file: File,
+    /// The number in the name of the manifest file.
+    number: u64,
+    /// The size of the manifest file in bytes.
+    size: u64,
+    /// Whether the file holds legacy JSON records.
+    legacy: bool,
+}
+
+/// The layout of the storage at the time the snapshot was taken.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestSnapshot {
+    /// Memtables that have not been flushed.
+    pub memtables: Vec<usize>,
+    pub l0_sstables: Vec<usize>,
+    pub levels: Vec<(usize, Vec<usize>)>,
+    /// The largest SST or memtable id allocated so far.
+    pub max_id: usize,
+}
+
+/// The SSTs added and removed by a compaction. Levels are identified by their ids in `LsmStorageState::levels`,
+/// which are tier ids in tiered compaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestEdit {
+    /// SSTs removed from L0.
+    pub l0_removed: Vec<usize>,
+    /// SSTs removed from each level.
+    pub removed: Vec<(usize, Vec<usize>)>,
+    /// SSTs appended to each level. A level that does not exist yet is inserted where the last dropped level was.
+    pub added: Vec<(usize, Vec<usize>)>,
+    /// Levels removed along with all their SSTs.
+    pub dropped_levels: Vec<usize>,
+}
+
+impl ManifestEdit {
+    /// The edit that turns the SST layout of `old` into the one of `new`.
+    pub fn between(old: &LsmStorageState, new: &LsmStorageState) -> Self {
+        let mut edit = Self::default();
+        let new_l0 = new.l0_sstables.iter().collect::<HashSet<_>>();
+        edit.l0_removed = old
+            .l0_sstables
+            .iter()
+            .filter(|id| !new_l0.contains(id))
+            .copied()
+            .collect();
+        for (level, old_ssts) in &old.levels {
+            let Some((_, new_ssts)) = new.levels.iter().find(|(id, _)| id == level) else {
+                edit.dropped_levels.push(*level);
+                continue;
+            };
+            let removed = old_ssts
+                .iter()
+                .filter(|id| !new_ssts.contains(id))
+                .copied()
+                .collect::<Vec<_>>();
+            if !removed.is_empty() {
+                edit.removed.push((*level, removed));
+            }
+            let added = new_ssts
+                .iter()
+                .filter(|id| !old_ssts.contains(id))
+                .copied()
+                .collect::<Vec<_>>();
+            if !added.is_empty() {
+                edit.added.push((*level, added));
+            }
+        }
+        for (level, new_ssts) in &new.levels {
+            if !old.levels.iter().any(|(id, _)| id == level) {
+                edit.added.push((*level, new_ssts.clone()));
+            }
+        }
+        edit
+    }
+
+    /// Apply the edit to the SST layout of `state`. The SSTs of a leveled level are not sorted by key afterwards.
+    pub fn apply(&self, state: &mut LsmStorageState) {
+        let l0_removed = self.l0_removed.iter().collect::<HashSet<_>>();
+        state.l0_sstables.retain(|id| !l0_removed.contains(id));
+        let mut levels = Vec::with_capacity(state.levels.len());
+        let mut insert_at = 0;
+        for (level, mut ssts) in std::mem::take(&mut state.levels) {
+            if self.dropped_levels.contains(&level) {
+                insert_at = levels.len();
+                continue;
+            }
+            if let Some((_, removed)) = self.removed.iter().find(|(id, _)| *id == level) {
+                ssts.retain(|id| !removed.contains(id));
+            }
+            if let Some((_, added)) = self.added.iter().find(|(id, _)| *id == level) {
+                ssts.extend(added);
+            }
+            levels.push((level, ssts));
+        }
+        for (level, added) in &self.added {
+            if !levels.iter().any(|(id, _)| id == level) {
+                levels.insert(insert_at, (*level, added.clone()));
+                insert_at += 1;
+            }
+        }
+        state.levels = levels;
+    }
 }
 
-#[derive(Serialize, Deserialize)]
 pub enum ManifestRecord {
+    Flush(usize),
+    NewMemtable(usize),
+    Compaction(ManifestEdit),
+    /// Replaces the state built from the records before it.
+    Snapshot(ManifestSnapshot),
+    /// A compaction read from a legacy JSON manifest, which has to be applied by the compaction controller.
+    LegacyCompaction(CompactionTask, Vec<usize>),
+}
+
+/// A record of a legacy JSON manifest.
+#[derive(Deserialize)]
+enum LegacyManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    Snapshot(ManifestSnapshot),
+}
+
+impl From<LegacyManifestRecord> for ManifestRecord {
+    fn from(record: LegacyManifestRecord) -> Self {
+        match record {
+            LegacyManifestRecord::Flush(id) => ManifestRecord::Flush(id),
+            LegacyManifestRecord::NewMemtable(id) => ManifestRecord::NewMemtable(id),
+            LegacyManifestRecord::Compaction(task, output) => {
+                ManifestRecord::LegacyCompaction(task, output)
+            }
+            LegacyManifestRecord::Snapshot(snapshot) => ManifestRecord::Snapshot(snapshot),
+        }
+    }
+}
+
+fn put_ids(buf: &mut Vec<u8>, ids: &[usize]) {
+    buf.put_u32(ids.len() as u32);
+    for id in ids {
+        buf.put_u64(*id as u64);
+    }
+}
+
+fn put_levels(buf: &mut Vec<u8>, levels: &[(usize, Vec<usize>)]) {
+    buf.put_u32(levels.len() as u32);
+    for (level, ids) in levels {
+        buf.put_u64(*level as u64);
+        put_ids(buf, ids);
+    }
+}
+
+fn get_id(buf: &mut &[u8]) -> Result<usize> {
+    if buf.remaining() < 8 {
+        bail!("incomplete manifest record");
+    }
+    Ok(buf.get_u64() as usize)
+}
+
+fn get_ids(buf: &mut &[u8]) -> Result<Vec<usize>> {
+    if buf.remaining() < 4 {
+        bail!("incomplete manifest record");
+    }
+    let len = buf.get_u32() as usize;
+    (0..len).map(|_| get_id(buf)).collect()
+}
+
+fn get_levels(buf: &mut &[u8]) -> Result<Vec<(usize, Vec<usize>)>> {
+    if buf.remaining() < 4 {
+        bail!("incomplete manifest record");
+    }
+    let len = buf.get_u32() as usize;
+    (0..len)
+        .map(|_| Ok((get_id(buf)?, get_ids(buf)?)))
+        .collect()
+}
+
+impl ManifestRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ManifestRecord::Flush(id) => {
+                buf.put_u8(TAG_FLUSH);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::NewMemtable(id) => {
+                buf.put_u8(TAG_NEW_MEMTABLE);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::Compaction(edit) => {
+                buf.put_u8(TAG_COMPACTION);
+                put_ids(buf, &edit.l0_removed);
+                put_levels(buf, &edit.removed);
+                put_levels(buf, &edit.added);
+                put_ids(buf, &edit.dropped_levels);
+            }
+            ManifestRecord::Snapshot(snapshot) => {
+                buf.put_u8(TAG_SNAPSHOT);
+                put_ids(buf, &snapshot.memtables);
+                put_ids(buf, &snapshot.l0_sstables);
+                put_levels(buf, &snapshot.levels);
+                buf.put_u64(snapshot.max_id as u64);
+            }
+            ManifestRecord::LegacyCompaction(..) => {
+                unreachable!("legacy compaction records are never written")
+            }
+        }
+    }
+
+    fn decode(mut buf: &[u8]) -> Result<Self> {
+        if !buf.has_remaining() {
+            bail!("empty manifest record");
+        }
+        let buf = &mut buf;
+        let record = match buf.get_u8() {
+            TAG_FLUSH => ManifestRecord::Flush(get_id(buf)?),
+            TAG_NEW_MEMTABLE => ManifestRecord::NewMemtable(get_id(buf)?),
+            TAG_COMPACTION => ManifestRecord::Compaction(ManifestEdit {
+                l0_removed: get_ids(buf)?,
+                removed: get_levels(buf)?,
+                added: get_levels(buf)?,
+                dropped_levels: get_ids(buf)?,
+            }),
+            TAG_SNAPSHOT => ManifestRecord::Snapshot(ManifestSnapshot {
+                memtables: get_ids(buf)?,
+                l0_sstables: get_ids(buf)?,
+                levels: get_levels(buf)?,
+                max_id: get_id(buf)?,
+            }),
+            tag => bail!("unknown manifest record tag {}", tag),
+        };
+        Ok(record)
+    }
 }
 
 impl Manifest {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    fn path_of_manifest(dir: &Path, number: u64) -> PathBuf {
+        dir.join(format!("MANIFEST-{:06}", number))
+    }
+
+    /// Point `CURRENT` to the manifest file `number`, replacing it atomically.
+    fn set_current(dir: &Path, number: u64) -> Result<()> {
+        let tmp_path = dir.join("CURRENT.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(format!("MANIFEST-{:06}\n", number).as_bytes())?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, dir.join("CURRENT"))?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Create the manifest file `number` and write the binary format header.
+    fn create_file(dir: &Path, number: u64) -> Result<ManifestFile> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(Self::path_of_manifest(dir, number))
+            .context("failed to create manifest")?;
+        let mut header = Vec::new();
+        header.put_u32(MANIFEST_MAGIC);
+        header.put_u16(MANIFEST_VERSION);
+        file.write_all(&header)?;
+        Ok(ManifestFile {
+            file,
+            number,
+            size: header.len() as u64,
+            legacy: false,
+        })
+    }
+
+    /// Remove the manifest files other than `current` left behind by a rollover that crashed.
+    fn remove_stale_files(dir: &Path, current: &Path) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if (name.starts_with("MANIFEST") || name == "CURRENT.tmp") && path != current {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `dir` contains a manifest, including one created before `CURRENT` was introduced.
+    pub fn exists(dir: impl AsRef<Path>) -> bool {
+        let dir = dir.as_ref();
+        dir.join("CURRENT").exists() || dir.join("MANIFEST").exists()
+    }
+
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let file = Self::create_file(dir, 1)?;
+        file.file.sync_all()?;
+        Self::set_current(dir, 1)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create manifest")?,
-            )),
+            dir: dir.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+    pub fn recover(dir: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let dir = dir.as_ref();
+        let current_path = dir.join("CURRENT");
+        let (path, number) = if current_path.exists() {
+            let current = std::fs::read_to_string(&current_path)?;
+            let name = current.trim();
+            let number = name
+                .strip_prefix("MANIFEST-")
+                .and_then(|number| number.parse::<u64>().ok())
+                .with_context(|| format!("invalid CURRENT file: {:?}", name))?;
+            (dir.join(name), number)
+        } else {
+            // a manifest from before `CURRENT` was introduced, replaced by `MANIFEST-000001` on the first rollover
+            (dir.join("MANIFEST"), 0)
+        };
         let mut file = OpenOptions::new()
             .read(true)
             .append(true)
-            .open(path)
+            .open(&path)
             .context("failed to recover manifest")?;
+        Self::remove_stale_files(dir, &path)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
         let mut buf_ptr = buf.as_slice();
         let mut records = Vec::new();
-        while buf_ptr.has_remaining() {
-            let len = buf_ptr.get_u64();
-            let slice = &buf_ptr[..len as usize];
-            let json = serde_json::from_slice::<ManifestRecord>(slice)?;
-            buf_ptr.advance(len as usize);
-            let checksum = buf_ptr.get_u32();
-            if checksum != crc32fast::hash(slice) {
-                bail!("checksum mismatched!");
-            }
-            records.push(json);
+        let legacy = buf_ptr.remaining() < 4 || (&buf_ptr[..4]).get_u32() != MANIFEST_MAGIC;
+        if legacy {
+            while buf_ptr.has_remaining() {
+                let len = buf_ptr.get_u64();
+                let slice = &buf_ptr[..len as usize];
+                let json = serde_json::from_slice::<LegacyManifestRecord>(slice)?;
+                buf_ptr.advance(len as usize);
+                let checksum = buf_ptr.get_u32();
+                if checksum != crc32fast::hash(slice) {
+                    bail!("checksum mismatched!");
+                }
+                records.push(json.into());
+            }
+        } else {
+            buf_ptr.advance(4);
+            if buf_ptr.remaining() < 2 {
+                bail!("incomplete manifest header");
+            }
+            let version = buf_ptr.get_u16();
+            if version > MANIFEST_VERSION {
+                bail!(
+                    "manifest version {} is newer than the supported version {}",
+                    version,
+                    MANIFEST_VERSION
+                );
+            }
+            while buf_ptr.has_remaining() {
+                if buf_ptr.remaining() < 4 {
+                    bail!("incomplete manifest record");
+                }
+                let len = buf_ptr.get_u32() as usize;
+                if buf_ptr.remaining() < len + 4 {
+                    bail!("incomplete manifest record");
+                }
+                let slice = &buf_ptr[..len];
+                buf_ptr.advance(len);
+                let checksum = buf_ptr.get_u32();
+                if checksum != crc32fast::hash(slice) {
+                    bail!("checksum mismatched!");
+                }
+                records.push(ManifestRecord::decode(slice)?);
+            }
         }
         Ok((
             Self {
-                file: Arc::new(Mutex::new(file)),
+                dir: dir.to_path_buf(),
+                file: Arc::new(Mutex::new(ManifestFile {
+                    file,
+                    number,
+                    size: buf.len() as u64,
+                    legacy,
+                })),
             },
             records,
         ))
@@ -74,12 +427,46 @@ impl Manifest {
 
     pub fn add_record_when_init(&self, record: ManifestRecord) -> Result<()> {
         let mut file = self.file.lock();
-        let mut buf = serde_json::to_vec(&record)?;
-        let hash = crc32fast::hash(&buf);
-        file.write_all(&(buf.len() as u64).to_be_bytes())?;
-        buf.put_u32(hash);
-        file.write_all(&buf)?;
-        file.sync_all()?;
+        assert!(!file.legacy, "cannot append to a legacy manifest");
+        Self::write_record(&mut file, &record)
+    }
+
+    fn write_record(file: &mut ManifestFile, record: &ManifestRecord) -> Result<()> {
+        let mut body = Vec::new();
+        record.encode(&mut body);
+        let mut buf = Vec::with_capacity(body.len() + 8);
+        buf.put_u32(body.len() as u32);
+        buf.put_slice(&body);
+        buf.put_u32(crc32fast::hash(&body));
+        file.file.write_all(&buf)?;
+        file.file.sync_all()?;
+        file.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// The size of the current manifest file in bytes.
+    pub fn size(&self) -> u64 {
+        self.file.lock().size
+    }
+
+    /// Whether the current manifest file is in the legacy JSON format, which can only be replaced by `rollover`.
+    pub fn is_legacy(&self) -> bool {
+        self.file.lock().legacy
+    }
+
+    /// Start a new manifest file with `snapshot` and switch to it.
+    pub fn rollover(&self, snapshot: ManifestSnapshot) -> Result<()> {
+        let mut file = self.file.lock();
+        let mut new_file = Self::create_file(&self.dir, file.number + 1)?;
+        Self::write_record(&mut new_file, &ManifestRecord::Snapshot(snapshot))?;
+        Self::set_current(&self.dir, new_file.number)?;
+        let old_path = if file.number == 0 {
+            self.dir.join("MANIFEST")
+        } else {
+            Self::path_of_manifest(&self.dir, file.number)
+        };
+        *file = new_file;
+        std::fs::remove_file(old_path)?;
         Ok(())
     }
 }
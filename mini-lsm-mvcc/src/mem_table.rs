@@ -1,5 +1,4 @@
 use std::ops::Bound;
-use std::path::Path;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 
@@ -12,15 +11,16 @@ use ouroboros::self_referencing;
 use crate::iterators::StorageIterator;
 use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{Wal, WalRecords};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
-    wal: Option<Wal>,
+    /// Maps each key to its value and optional expiry timestamp (unix time in milliseconds).
+    pub(crate) map: Arc<SkipMap<KeyBytes, (Bytes, Option<u64>)>>,
+    wal: Option<Arc<Wal>>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
 }
@@ -69,25 +69,28 @@ impl MemTable {
         }
     }
 
-    /// Create a new mem-table with WAL
-    pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
+    /// Create a new mem-table that logs its writes to the shared WAL
+    pub fn create_with_wal(id: usize, wal: Arc<Wal>) -> Self {
+        Self {
             id,
             map: Arc::new(SkipMap::new()),
-            wal: Some(Wal::create(path.as_ref())?),
+            wal: Some(wal),
             approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+        }
     }
 
-    /// Create a memtable from WAL
-    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
-        let map = Arc::new(SkipMap::new());
-        Ok(Self {
+    /// Create a memtable from the records recovered from the shared WAL
+    pub fn recover_from_wal(id: usize, wal: Arc<Wal>, records: WalRecords) -> Self {
+        let estimated_size = records
+            .iter()
+            .map(|entry| entry.key().raw_len() + entry.value().0.len())
+            .sum();
+        Self {
             id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
-            map,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+            map: Arc::new(records),
+            wal: Some(wal),
+            approximate_size: Arc::new(AtomicUsize::new(estimated_size)),
+        }
     }
 
     /// Get a value by key. Should not be used in week 3.
@@ -96,7 +99,7 @@ impl MemTable {
             Bytes::from_static(unsafe { std::mem::transmute::<&[u8], &[u8]>(key.key_ref()) }),
             key.ts(),
         );
-        self.map.get(&key_bytes).map(|e| e.value().clone())
+        self.map.get(&key_bytes).map(|e| e.value().0.clone())
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -127,20 +130,39 @@ impl MemTable {
         self.put_batch(&[(key, value)])
     }
 
+    /// Put a key-value pair that expires at `expire_at` (unix time in milliseconds) into the mem-table.
+    pub fn put_with_expiry(
+        &self,
+        key: KeySlice,
+        value: &[u8],
+        expire_at: Option<u64>,
+    ) -> Result<()> {
+        self.put_batch_with_expiry(&[(key, value, expire_at)])
+    }
+
     /// Implement this in week 3, day 5.
     pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        let data = data
+            .iter()
+            .map(|(key, value)| (*key, *value, None))
+            .collect::<Vec<_>>();
+        self.put_batch_with_expiry(&data)
+    }
+
+    /// Put a batch of key-value pairs, each with an optional expiry timestamp, into the mem-table.
+    pub fn put_batch_with_expiry(&self, data: &[(KeySlice, &[u8], Option<u64>)]) -> Result<()> {
         let mut estimated_size = 0;
-        for (key, value) in data {
+        for (key, value, expire_at) in data {
             estimated_size += key.raw_len() + value.len();
             self.map.insert(
                 key.to_key_vec().into_key_bytes(),
-                Bytes::copy_from_slice(value),
+                (Bytes::copy_from_slice(value), *expire_at),
             );
         }
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
         if let Some(ref wal) = self.wal {
-            wal.put_batch(data)?;
+            wal.put_batch_with_expiry(self.id, data)?;
         }
         Ok(())
     }
@@ -158,7 +180,7 @@ impl MemTable {
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
             iter_builder: |map| map.range((lower, upper)),
-            item: (KeyBytes::new(), Bytes::new()),
+            item: (KeyBytes::new(), Bytes::new(), None),
         }
         .build();
         iter.next().unwrap();
@@ -168,7 +190,8 @@ impl MemTable {
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+            let (value, expire_at) = entry.value();
+            builder.add_with_expiry(entry.key().as_key_slice(), &value[..], *expire_at);
         }
         Ok(())
     }
@@ -193,7 +216,7 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
     KeyBytes,
     (Bound<KeyBytes>, Bound<KeyBytes>),
     KeyBytes,
-    Bytes,
+    (Bytes, Option<u64>),
 >;
 
 /// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
@@ -203,20 +226,22 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
 #[self_referencing]
 pub struct MemTableIterator {
     /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<KeyBytes, Bytes>>,
+    map: Arc<SkipMap<KeyBytes, (Bytes, Option<u64>)>>,
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (KeyBytes, Bytes),
+    /// Stores the current key-value pair and its expiry timestamp.
+    item: (KeyBytes, Bytes, Option<u64>),
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, KeyBytes, (Bytes, Option<u64>)>>,
+    ) -> (KeyBytes, Bytes, Option<u64>) {
         entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
+            .map(|x| (x.key().clone(), x.value().0.clone(), x.value().1))
+            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new(), None))
     }
 }
 
@@ -231,6 +256,10 @@ impl StorageIterator for MemTableIterator {
         self.borrow_item().0.as_key_slice()
     }
 
+    fn expire_at(&self) -> Option<u64> {
+        self.borrow_item().2
+    }
+
     fn is_valid(&self) -> bool {
         !self.borrow_item().0.is_empty()
     }
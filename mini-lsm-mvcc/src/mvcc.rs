@@ -63,6 +63,7 @@ impl LsmMvccInner {
             inner,
             read_ts,
             local_storage: Arc::new(SkipMap::new()),
+            local_ttls: Arc::new(SkipMap::new()),
             committed: Arc::new(AtomicBool::new(false)),
             key_hashes: if serializable {
                 Some(Mutex::new((HashSet::new(), HashSet::new())))
@@ -5,6 +5,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use anyhow::{bail, Result};
@@ -25,6 +26,8 @@ pub struct Transaction {
     pub(crate) read_ts: u64,
     pub(crate) inner: Arc<LsmStorageInner>,
     pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
+    /// TTLs of the keys in `local_storage` written by `put_with_ttl`
+    pub(crate) local_ttls: Arc<SkipMap<Bytes, Duration>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
     pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
@@ -78,6 +81,7 @@ impl Transaction {
         }
         self.local_storage
             .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.local_ttls.remove(key);
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
@@ -85,12 +89,18 @@ impl Transaction {
         }
     }
 
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) {
+        self.put(key, value);
+        self.local_ttls.insert(Bytes::copy_from_slice(key), ttl);
+    }
+
     pub fn delete(&self, key: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
         self.local_storage
             .insert(Bytes::copy_from_slice(key), Bytes::new());
+        self.local_ttls.remove(key);
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
@@ -131,6 +141,12 @@ impl Transaction {
             .map(|entry| {
                 if entry.value().is_empty() {
                     WriteBatchRecord::Del(entry.key().clone())
+                } else if let Some(ttl) = self.local_ttls.get(entry.key()) {
+                    WriteBatchRecord::PutWithTtl(
+                        entry.key().clone(),
+                        entry.value().clone(),
+                        *ttl.value(),
+                    )
                 } else {
                     WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
                 }
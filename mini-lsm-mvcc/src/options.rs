@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::compact::{CompactionOptions, CompactionTask};
+use crate::lsm_storage::LsmStorageOptions;
+
+fn compaction_style(options: &CompactionOptions) -> &'static str {
+    match options {
+        CompactionOptions::Leveled(_) => "leveled",
+        CompactionOptions::Tiered(_) => "tiered",
+        CompactionOptions::Simple(_) => "simple leveled",
+        CompactionOptions::Fifo(_) => "FIFO",
+        CompactionOptions::LazyLeveled(_) => "lazy leveled",
+        CompactionOptions::NoCompaction => "no",
+    }
+}
+
+/// The compaction style that generated `task`, or `None` for a full compaction, which any style may run.
+fn task_compaction_style(task: &CompactionTask) -> Option<&'static str> {
+    match task {
+        CompactionTask::Leveled(_) => Some("leveled"),
+        CompactionTask::Tiered(_) => Some("tiered"),
+        CompactionTask::Simple(_) => Some("simple leveled"),
+        CompactionTask::Fifo(_) => Some("FIFO"),
+        CompactionTask::LazyLeveled(_) => Some("lazy leveled"),
+        CompactionTask::ForceFullCompaction { .. } => None,
+    }
+}
+
+/// The options a database was last opened with, stored as JSON in the `OPTIONS` file of the DB directory.
+pub struct OptionsFile;
+
+impl OptionsFile {
+    fn path(dir: &Path) -> PathBuf {
+        dir.join("OPTIONS")
+    }
+
+    /// Read the stored options, or `None` if the database predates the options file. Options missing from the file,
+    /// e.g., ones added after it was written, take their value from `current`.
+    pub fn read(
+        dir: impl AsRef<Path>,
+        current: &LsmStorageOptions,
+    ) -> Result<Option<LsmStorageOptions>> {
+        let path = Self::path(dir.as_ref());
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).context("failed to read OPTIONS")?;
+        let stored: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&data).context("failed to parse OPTIONS")?;
+        let serde_json::Value::Object(mut options) = serde_json::to_value(current)? else {
+            unreachable!("options are serialized as an object");
+        };
+        options.extend(stored);
+        Ok(Some(
+            serde_json::from_value(serde_json::Value::Object(options))
+                .context("failed to parse OPTIONS")?,
+        ))
+    }
+
+    /// Replace the stored options with `options` atomically.
+    pub fn write(dir: impl AsRef<Path>, options: &LsmStorageOptions) -> Result<()> {
+        let dir = dir.as_ref();
+        let tmp_path = dir.join("OPTIONS.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec_pretty(options)?)?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, Self::path(dir))?;
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Check that a database stored with `stored` options can be opened with `options`. Changing `max_levels` is
+    /// checked against the recovered levels instead, as shrinking is fine as long as the dropped levels are empty.
+    pub fn check_compatibility(
+        stored: &LsmStorageOptions,
+        options: &LsmStorageOptions,
+    ) -> Result<()> {
+        // tiered and lazy leveled compaction keep sorted runs instead of L0 SSTs and levels, which the others cannot
+        // read
+        let sorted_runs = |options: &LsmStorageOptions| {
+            matches!(
+                options.compaction_options,
+                CompactionOptions::Tiered(_) | CompactionOptions::LazyLeveled(_)
+            )
+        };
+        if sorted_runs(stored) != sorted_runs(options) {
+            bail!(
+                "cannot open a database created with {} compaction using {} compaction",
+                compaction_style(&stored.compaction_options),
+                compaction_style(&options.compaction_options)
+            );
+        }
+        // memtables are not flushed on close when the WAL is enabled
+        if stored.enable_wal && !options.enable_wal {
+            bail!("cannot disable the WAL, flush the memtables with the WAL enabled first");
+        }
+        Ok(())
+    }
+
+    /// Check that a compaction `task` of a legacy manifest can be replayed with `options`. Databases from before the
+    /// options file have no stored options to check against, but only the compaction style that generated a task can
+    /// apply it.
+    pub fn check_legacy_compaction(
+        task: &CompactionTask,
+        options: &LsmStorageOptions,
+    ) -> Result<()> {
+        let style = compaction_style(&options.compaction_options);
+        match task_compaction_style(task) {
+            Some(stored_style) if stored_style != style => bail!(
+                "cannot open a database created with {} compaction using {} compaction",
+                stored_style,
+                style
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Move the WAL segments from the WAL directory of `stored` to the one of `options`, if it changed.
+    pub fn migrate_wal_dir(
+        path: &Path,
+        stored: &LsmStorageOptions,
+        options: &LsmStorageOptions,
+    ) -> Result<()> {
+        let old_dir = stored.wal_dir.as_deref().unwrap_or(path);
+        let new_dir = options.wal_dir.as_deref().unwrap_or(path);
+        if !stored.enable_wal || old_dir == new_dir || !old_dir.exists() {
+            return Ok(());
+        }
+        let segments = std::fs::read_dir(old_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(new_dir).context("failed to create WAL dir")?;
+        for segment in &segments {
+            let new_path = new_dir.join(segment.file_name().unwrap());
+            if new_path.exists() {
+                bail!(
+                    "cannot move the WAL from {} to {}: {} already exists",
+                    old_dir.display(),
+                    new_dir.display(),
+                    new_path.display()
+                );
+            }
+        }
+        for segment in &segments {
+            std::fs::rename(segment, new_dir.join(segment.file_name().unwrap()))
+                .with_context(|| format!("failed to move WAL segment {}", segment.display()))?;
+        }
+        File::open(new_dir)?.sync_all()?;
+        File::open(old_dir)?.sync_all()?;
+        println!(
+            "{} WAL segments moved from {} to {}",
+            segments.len(),
+            old_dir.display(),
+            new_dir.display()
+        );
+        Ok(())
+    }
+}
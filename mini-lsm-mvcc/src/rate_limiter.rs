@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+use serde::{Deserialize, Serialize};
+
+/// Tokens are added to the bucket once per period, and the bucket holds at most one period worth of tokens.
+const REFILL_PERIOD: Duration = Duration::from_millis(10);
+
+/// The lowest rate an auto-tuned limiter goes down to, as a fraction of `bytes_per_sec`.
+const MIN_AUTO_TUNED_FRACTION: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimiterOptions {
+    /// The rate of background writes in bytes per second. The maximum rate if `auto_tune` is set.
+    pub bytes_per_sec: usize,
+    /// Follow the pending flush and compaction backlog, running slower when there is little to catch up with.
+    pub auto_tune: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Flushes, which block writes when they fall behind.
+    High,
+    /// Compactions and other background work.
+    Low,
+}
+
+struct RateLimiterInner {
+    max_bytes_per_sec: usize,
+    bytes_per_sec: usize,
+    available: usize,
+    last_refill: Instant,
+    high_pri_waiting: usize,
+    total_bytes: [u64; 2],
+}
+
+/// A token bucket shared by background writers. Writers request tokens for the bytes they are about to write and
+/// wait until they are granted. Waiting high priority requests are granted before any low priority one.
+pub struct RateLimiter {
+    inner: Mutex<RateLimiterInner>,
+    refilled: Condvar,
+    auto_tune: bool,
+}
+
+impl RateLimiter {
+    pub fn new(options: RateLimiterOptions) -> Self {
+        Self {
+            inner: Mutex::new(RateLimiterInner {
+                max_bytes_per_sec: options.bytes_per_sec,
+                bytes_per_sec: options.bytes_per_sec,
+                available: 0,
+                last_refill: Instant::now(),
+                high_pri_waiting: 0,
+                total_bytes: [0; 2],
+            }),
+            refilled: Condvar::new(),
+            auto_tune: options.auto_tune,
+        }
+    }
+
+    fn bytes_per_period(bytes_per_sec: usize) -> usize {
+        ((bytes_per_sec as u128 * REFILL_PERIOD.as_nanos() / Duration::from_secs(1).as_nanos())
+            as usize)
+            .max(1)
+    }
+
+    /// The largest request granted at once, larger requests are split into chunks of this size.
+    pub fn max_chunk_size(&self) -> usize {
+        Self::bytes_per_period(self.inner.lock().bytes_per_sec)
+    }
+
+    fn refill(inner: &mut RateLimiterInner) {
+        let periods = (inner.last_refill.elapsed().as_nanos() / REFILL_PERIOD.as_nanos()) as u32;
+        if periods == 0 {
+            return;
+        }
+        let bytes_per_period = Self::bytes_per_period(inner.bytes_per_sec);
+        inner.available = inner
+            .available
+            .saturating_add(bytes_per_period.saturating_mul(periods as usize))
+            .min(bytes_per_period);
+        inner.last_refill += REFILL_PERIOD * periods;
+    }
+
+    /// Wait until `bytes` can be written.
+    pub fn request(&self, mut bytes: usize, priority: IoPriority) {
+        let mut inner = self.inner.lock();
+        inner.total_bytes[priority as usize] += bytes as u64;
+        if priority == IoPriority::High {
+            inner.high_pri_waiting += 1;
+        }
+        while bytes > 0 {
+            Self::refill(&mut inner);
+            let chunk = bytes.min(Self::bytes_per_period(inner.bytes_per_sec));
+            if inner.available >= chunk
+                && (priority == IoPriority::High || inner.high_pri_waiting == 0)
+            {
+                inner.available -= chunk;
+                bytes -= chunk;
+                continue;
+            }
+            let next_refill =
+                (inner.last_refill + REFILL_PERIOD).saturating_duration_since(Instant::now());
+            self.refilled.wait_for(&mut inner, next_refill);
+        }
+        if priority == IoPriority::High {
+            inner.high_pri_waiting -= 1;
+            // low priority requests may proceed now
+            self.refilled.notify_all();
+        }
+    }
+
+    /// The current rate in bytes per second.
+    pub fn bytes_per_sec(&self) -> usize {
+        self.inner.lock().bytes_per_sec
+    }
+
+    /// Change the rate, which is the maximum rate if auto-tuned.
+    pub fn set_bytes_per_sec(&self, bytes_per_sec: usize) {
+        let mut inner = self.inner.lock();
+        inner.max_bytes_per_sec = bytes_per_sec;
+        inner.bytes_per_sec = bytes_per_sec;
+    }
+
+    /// Scale an auto-tuned rate by `backlog`, the pending work relative to what starts a flush or compaction. A
+    /// backlog of 1 or more runs at the maximum rate. Does nothing if the limiter is not auto-tuned.
+    pub fn tune(&self, backlog: f64) {
+        if !self.auto_tune {
+            return;
+        }
+        let fraction = backlog.clamp(MIN_AUTO_TUNED_FRACTION, 1.0);
+        let mut inner = self.inner.lock();
+        inner.bytes_per_sec = (inner.max_bytes_per_sec as f64 * fraction) as usize;
+    }
+
+    /// The total bytes requested with `priority`.
+    pub fn total_bytes(&self, priority: IoPriority) -> u64 {
+        self.inner.lock().total_bytes[priority as usize]
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use parking_lot::{Condvar, Mutex};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The pool a background job runs in. Flushes run in the high-priority pool, so that writes are not stalled by long
+/// compactions in the low-priority pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JobPriority {
+    High,
+    Low,
+}
+
+/// What happened to the storage that may require background work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundEvent {
+    /// The background work has been started or continued.
+    Started,
+    MemtableFrozen,
+    FlushFinished,
+    CompactionFinished,
+    /// A compaction that depends on the time, e.g., dropping expired SSTs, may be due.
+    TimerExpired,
+}
+
+/// A snapshot of the jobs of a pool.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolState {
+    pub threads: usize,
+    /// The jobs waiting for a free thread.
+    pub queued: usize,
+    pub running: usize,
+    pub completed: u64,
+}
+
+/// A snapshot of the background scheduler.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchedulerState {
+    pub high: PoolState,
+    pub low: PoolState,
+    /// The jobs waiting for their time to come.
+    pub timers: usize,
+    pub shutting_down: bool,
+}
+
+#[derive(Default)]
+struct PoolJobs {
+    queue: VecDeque<Job>,
+    threads: usize,
+    running: usize,
+    completed: u64,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct Pool {
+    jobs: Mutex<PoolJobs>,
+    job_added: Condvar,
+}
+
+impl Pool {
+    fn run_worker(&self) {
+        let mut jobs = self.jobs.lock();
+        loop {
+            if let Some(job) = jobs.queue.pop_front() {
+                jobs.running += 1;
+                drop(jobs);
+                // a failing job must not take the thread down with it
+                if std::panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    eprintln!("background job panicked");
+                }
+                jobs = self.jobs.lock();
+                jobs.running -= 1;
+                jobs.completed += 1;
+            } else if jobs.shutdown {
+                return;
+            } else {
+                self.job_added.wait(&mut jobs);
+            }
+        }
+    }
+
+    /// Queue `job` if the pool runs jobs.
+    fn push(&self, job: Job) -> bool {
+        let mut jobs = self.jobs.lock();
+        if jobs.threads == 0 || jobs.shutdown {
+            return false;
+        }
+        jobs.queue.push_back(job);
+        self.job_added.notify_one();
+        true
+    }
+
+    fn state(&self) -> PoolState {
+        let jobs = self.jobs.lock();
+        PoolState {
+            threads: jobs.threads,
+            queued: jobs.queue.len(),
+            running: jobs.running,
+            completed: jobs.completed,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TimerJobs {
+    jobs: Vec<(Instant, JobPriority, Job)>,
+    shutdown: bool,
+}
+
+/// The jobs to queue in a pool at a given time. They are few, so they are not kept sorted.
+#[derive(Default)]
+struct Timers {
+    jobs: Mutex<TimerJobs>,
+    changed: Condvar,
+}
+
+impl Timers {
+    fn run(&self, high: &Pool, low: &Pool) {
+        let mut jobs = self.jobs.lock();
+        while !jobs.shutdown {
+            let now = Instant::now();
+            if let Some(idx) = jobs.jobs.iter().position(|(deadline, ..)| *deadline <= now) {
+                let (_, priority, job) = jobs.jobs.swap_remove(idx);
+                drop(jobs);
+                match priority {
+                    JobPriority::High => high.push(job),
+                    JobPriority::Low => low.push(job),
+                };
+                jobs = self.jobs.lock();
+                continue;
+            }
+            match jobs.jobs.iter().map(|(deadline, ..)| *deadline).min() {
+                Some(deadline) => {
+                    self.changed.wait_until(&mut jobs, deadline);
+                }
+                None => self.changed.wait(&mut jobs),
+            }
+        }
+    }
+}
+
+/// Runs the background flushes and compactions in two thread pools. Jobs are scheduled when something happens that
+/// may require them (see `BackgroundEvent`), instead of by polling the storage state.
+#[derive(Default)]
+pub struct BackgroundScheduler {
+    high: Arc<Pool>,
+    low: Arc<Pool>,
+    timers: Arc<Timers>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl BackgroundScheduler {
+    /// Create a scheduler without threads, which rejects all jobs until it is started.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pool(&self, priority: JobPriority) -> &Arc<Pool> {
+        match priority {
+            JobPriority::High => &self.high,
+            JobPriority::Low => &self.low,
+        }
+    }
+
+    /// Spawn `high_threads` and `low_threads` worker threads for the two pools, and the thread running the timers.
+    pub fn start(&self, high_threads: usize, low_threads: usize) {
+        let mut workers = self.workers.lock();
+        if self.timers.jobs.lock().shutdown {
+            return;
+        }
+        let (timers, high, low) = (self.timers.clone(), self.high.clone(), self.low.clone());
+        workers.push(
+            std::thread::Builder::new()
+                .name("mini-lsm-timer".to_string())
+                .spawn(move || timers.run(&high, &low))
+                .expect("failed to spawn background thread"),
+        );
+        for (priority, threads) in [
+            (JobPriority::High, high_threads),
+            (JobPriority::Low, low_threads),
+        ] {
+            let pool = self.pool(priority);
+            let mut jobs = pool.jobs.lock();
+            for _ in 0..threads {
+                let pool = pool.clone();
+                workers.push(
+                    std::thread::Builder::new()
+                        .name(format!("mini-lsm-{:?}-{}", priority, jobs.threads).to_lowercase())
+                        .spawn(move || pool.run_worker())
+                        .expect("failed to spawn background thread"),
+                );
+                jobs.threads += 1;
+            }
+        }
+    }
+
+    /// Whether jobs of `priority` would run, i.e., the pool has threads and is not shutting down.
+    pub fn is_running(&self, priority: JobPriority) -> bool {
+        let jobs = self.pool(priority).jobs.lock();
+        jobs.threads > 0 && !jobs.shutdown
+    }
+
+    /// Queue `job` in the pool of `priority`. Returns false and drops the job if the pool does not run jobs.
+    pub fn schedule(&self, priority: JobPriority, job: impl FnOnce() + Send + 'static) -> bool {
+        self.pool(priority).push(Box::new(job))
+    }
+
+    /// Queue `job` in the pool of `priority` once `deadline` has passed. Returns false and drops the job if the pool
+    /// does not run jobs.
+    pub fn schedule_at(
+        &self,
+        deadline: Instant,
+        priority: JobPriority,
+        job: impl FnOnce() + Send + 'static,
+    ) -> bool {
+        if !self.is_running(priority) {
+            return false;
+        }
+        let mut timers = self.timers.jobs.lock();
+        if timers.shutdown {
+            return false;
+        }
+        timers.jobs.push((deadline, priority, Box::new(job)));
+        self.timers.changed.notify_one();
+        true
+    }
+
+    pub fn state(&self) -> SchedulerState {
+        SchedulerState {
+            high: self.high.state(),
+            low: self.low.state(),
+            timers: self.timers.jobs.lock().jobs.len(),
+            shutting_down: self.high.jobs.lock().shutdown,
+        }
+    }
+
+    /// Drop the queued jobs, and wait for the running ones to finish. No job can be scheduled afterwards.
+    pub fn shutdown(&self) {
+        let timers = {
+            let mut timers = self.timers.jobs.lock();
+            timers.shutdown = true;
+            std::mem::take(&mut timers.jobs)
+        };
+        drop(timers);
+        self.timers.changed.notify_all();
+        for pool in [&self.high, &self.low] {
+            let queue = {
+                let mut jobs = pool.jobs.lock();
+                jobs.shutdown = true;
+                std::mem::take(&mut jobs.queue)
+            };
+            // jobs may own the storage, so they are not dropped with the lock held
+            drop(queue);
+            pool.job_added.notify_all();
+        }
+        let workers = std::mem::take(&mut *self.workers.lock());
+        for worker in workers {
+            worker.join().ok();
+        }
+    }
+}
@@ -3,20 +3,27 @@ mod builder;
 mod iterator;
 
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail, Result};
 pub use builder::SsTableBuilder;
 use bytes::{Buf, BufMut};
 pub use iterator::SsTableIterator;
 
-use crate::block::Block;
+use crate::block::{Block, BlockFormat};
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::rate_limiter::{IoPriority, RateLimiter};
 
 use self::bloom::Bloom;
 
+/// The version of the SST format written, recorded in the block meta. Version 1 ends every block with a flags byte,
+/// and version 2 records the creation time after the version.
+const SST_FORMAT_VERSION: u32 = 2;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
     /// Offset of this data block.
@@ -27,9 +34,25 @@ pub struct BlockMeta {
     pub last_key: KeyBytes,
 }
 
+/// Statistics of an SST gathered while building it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Number of entries, counting each version of a key.
+    pub num_entries: u64,
+    /// Number of delete tombstones, i.e., entries with an empty value.
+    pub num_tombstones: u64,
+    /// When the SST was built, in unix time (milliseconds). 0 if it was written before the creation time was recorded.
+    pub creation_time: u64,
+}
+
 impl BlockMeta {
     /// Encode block meta to a buffer.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
+    pub fn encode_block_meta(
+        block_meta: &[BlockMeta],
+        max_ts: u64,
+        properties: &TableProperties,
+        buf: &mut Vec<u8>,
+    ) {
         let mut estimated_size = std::mem::size_of::<u32>(); // number of blocks
         for meta in block_meta {
             // The size of offset
@@ -44,6 +67,9 @@ impl BlockMeta {
             estimated_size += meta.last_key.raw_len();
         }
         estimated_size += std::mem::size_of::<u64>(); // max timestamp
+        estimated_size += std::mem::size_of::<u64>() * 2; // table properties
+        estimated_size += std::mem::size_of::<u32>(); // format version
+        estimated_size += std::mem::size_of::<u64>(); // creation time
         estimated_size += std::mem::size_of::<u32>(); // checksum
 
         // Reserve the space to improve performance, especially when the size of incoming data is
@@ -61,12 +87,18 @@ impl BlockMeta {
             buf.put_u64(meta.last_key.ts());
         }
         buf.put_u64(max_ts);
+        buf.put_u64(properties.num_entries);
+        buf.put_u64(properties.num_tombstones);
+        buf.put_u32(SST_FORMAT_VERSION);
+        buf.put_u64(properties.creation_time);
         buf.put_u32(crc32fast::hash(&buf[original_len + 4..]));
         assert_eq!(estimated_size, buf.len() - original_len);
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    /// Decode block meta from a buffer, along with the format of the blocks.
+    pub fn decode_block_meta(
+        mut buf: &[u8],
+    ) -> Result<(Vec<BlockMeta>, u64, TableProperties, BlockFormat)> {
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
@@ -85,11 +117,30 @@ impl BlockMeta {
             });
         }
         let max_ts = buf.get_u64();
+        // SSTs written before table properties were added end right after the max timestamp
+        let mut properties = TableProperties::default();
+        if buf.remaining() > 4 {
+            properties.num_entries = buf.get_u64();
+            properties.num_tombstones = buf.get_u64();
+        }
+        // SSTs written before the format version was added use the legacy block format
+        let block_format = if buf.remaining() > 4 {
+            match buf.get_u32() {
+                1 => BlockFormat::Flagged,
+                2 => {
+                    properties.creation_time = buf.get_u64();
+                    BlockFormat::Flagged
+                }
+                version => bail!("unsupported SST format version {}", version),
+            }
+        } else {
+            BlockFormat::Legacy
+        };
         if buf.get_u32() != checksum {
             bail!("meta checksum mismatched");
         }
 
-        Ok((block_meta, max_ts))
+        Ok((block_meta, max_ts, properties, block_format))
     }
 }
 
@@ -121,6 +172,25 @@ impl FileObject {
         ))
     }
 
+    /// Create a new file object like `create`, writing the file in chunks charged against `rate_limiter`.
+    pub fn create_with_rate_limiter(
+        path: &Path,
+        data: Vec<u8>,
+        rate_limiter: &RateLimiter,
+        priority: IoPriority,
+    ) -> Result<Self> {
+        let mut file = File::create(path)?;
+        for chunk in data.chunks(rate_limiter.max_chunk_size()) {
+            rate_limiter.request(chunk.len(), priority);
+            file.write_all(chunk)?;
+        }
+        file.sync_all()?;
+        Ok(FileObject(
+            Some(File::options().read(true).write(false).open(path)?),
+            data.len() as u64,
+        ))
+    }
+
     pub fn open(path: &Path) -> Result<Self> {
         let file = File::options().read(true).write(false).open(path)?;
         let size = file.metadata()?.len();
@@ -142,6 +212,9 @@ pub struct SsTable {
     last_key: KeyBytes,
     pub(crate) bloom: Option<Bloom>,
     max_ts: u64,
+    properties: TableProperties,
+    /// How the entries of the data blocks are encoded.
+    block_format: BlockFormat,
 }
 impl SsTable {
     #[cfg(test)]
@@ -152,14 +225,24 @@ impl SsTable {
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         let len = file.size();
+        if len < 8 {
+            bail!("SST file too small: {} bytes", len);
+        }
         let raw_bloom_offset = file.read(len - 4, 4)?;
         let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
+        if bloom_offset < 4 || bloom_offset > len - 4 {
+            bail!("bloom filter offset {} out of range", bloom_offset);
+        }
         let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
         let bloom_filter = Bloom::decode(&raw_bloom)?;
         let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
+        if block_meta_offset > bloom_offset - 4 {
+            bail!("block meta offset {} out of range", block_meta_offset);
+        }
         let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let (block_meta, max_ts, properties, block_format) =
+            BlockMeta::decode_block_meta(&raw_meta[..])?;
         Ok(Self {
             file,
             first_key: block_meta.first().unwrap().first_key.clone(),
@@ -170,6 +253,8 @@ impl SsTable {
             block_cache,
             bloom: Some(bloom_filter),
             max_ts,
+            properties,
+            block_format,
         })
     }
 
@@ -190,6 +275,8 @@ impl SsTable {
             last_key,
             bloom: None,
             max_ts: 0,
+            properties: TableProperties::default(),
+            block_format: BlockFormat::Flagged,
         }
     }
 
@@ -209,7 +296,10 @@ impl SsTable {
         if checksum != crc32fast::hash(block_data) {
             bail!("block checksum mismatched");
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        Ok(Arc::new(Block::decode_with_format(
+            block_data,
+            self.block_format,
+        )))
     }
 
     /// Read a block from disk, with block cache.
@@ -248,6 +338,15 @@ impl SsTable {
         self.file.1
     }
 
+    /// When the SST was built, as recorded in its properties. SSTs written before the creation time was recorded fall
+    /// back to when their file was last modified, as SSTs are never changed. `None` for mock SSTs, which have no file.
+    pub fn creation_time(&self) -> Option<SystemTime> {
+        match self.properties.creation_time {
+            0 => self.file.0.as_ref()?.metadata().ok()?.modified().ok(),
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
     pub fn sst_id(&self) -> usize {
         self.id
     }
@@ -255,4 +354,8 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
 }
@@ -5,10 +5,11 @@ use anyhow::Result;
 use bytes::BufMut;
 
 use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
-use crate::block::BlockBuilder;
+use super::{BlockMeta, FileObject, SsTable, TableProperties};
+use crate::block::{BlockBuilder, BlockFormat};
 use crate::key::{KeySlice, KeyVec};
-use crate::lsm_storage::BlockCache;
+use crate::lsm_storage::{unix_time_millis, BlockCache};
+use crate::rate_limiter::{IoPriority, RateLimiter};
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -20,6 +21,7 @@ pub struct SsTableBuilder {
     block_size: usize,
     key_hashes: Vec<u32>,
     max_ts: u64,
+    properties: TableProperties,
 }
 
 impl SsTableBuilder {
@@ -34,11 +36,17 @@ impl SsTableBuilder {
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
             max_ts: 0,
+            properties: TableProperties::default(),
         }
     }
 
     /// Adds a key-value pair to SSTable
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_with_expiry(key, value, None)
+    }
+
+    /// Adds a key-value pair that expires at `expire_at` (unix time in milliseconds) to SSTable
+    pub fn add_with_expiry(&mut self, key: KeySlice, value: &[u8], expire_at: Option<u64>) {
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -47,8 +55,12 @@ impl SsTableBuilder {
             self.max_ts = key.ts();
         }
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        self.properties.num_entries += 1;
+        if value.is_empty() {
+            self.properties.num_tombstones += 1;
+        }
 
-        if self.builder.add(key, value) {
+        if self.builder.add_with_expiry(key, value, expire_at) {
             self.last_key.set_from_slice(key);
             return;
         }
@@ -57,11 +69,16 @@ impl SsTableBuilder {
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_with_expiry(key, value, expire_at));
         self.first_key.set_from_slice(key);
         self.last_key.set_from_slice(key);
     }
 
+    /// Check if no key-value pair has been added.
+    pub fn is_empty(&self) -> bool {
+        self.key_hashes.is_empty()
+    }
+
     /// Get the estimated size of the SSTable.
     pub fn estimated_size(&self) -> usize {
         self.data.len()
@@ -82,15 +99,27 @@ impl SsTableBuilder {
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
     pub fn build(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        self.build_with_rate_limiter(id, block_cache, path, None)
+    }
+
+    /// Builds the SSTable like `build`, charging the write against the rate limiter with the given priority if any.
+    pub fn build_with_rate_limiter(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
+        rate_limiter: Option<(&RateLimiter, IoPriority)>,
     ) -> Result<SsTable> {
         self.finish_block();
+        self.properties.creation_time = unix_time_millis();
         let mut buf = self.data;
         let meta_offset = buf.len();
-        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
+        BlockMeta::encode_block_meta(&self.meta, self.max_ts, &self.properties, &mut buf);
         buf.put_u32(meta_offset as u32);
         let bloom = Bloom::build_from_key_hashes(
             &self.key_hashes,
@@ -99,7 +128,12 @@ impl SsTableBuilder {
         let bloom_offset = buf.len();
         bloom.encode(&mut buf);
         buf.put_u32(bloom_offset as u32);
-        let file = FileObject::create(path.as_ref(), buf)?;
+        let file = match rate_limiter {
+            Some((rate_limiter, priority)) => {
+                FileObject::create_with_rate_limiter(path.as_ref(), buf, rate_limiter, priority)?
+            }
+            None => FileObject::create(path.as_ref(), buf)?,
+        };
         Ok(SsTable {
             id,
             file,
@@ -110,6 +144,8 @@ impl SsTableBuilder {
             block_cache,
             bloom: Some(bloom),
             max_ts: self.max_ts,
+            properties: self.properties,
+            block_format: BlockFormat::Flagged,
         })
     }
 
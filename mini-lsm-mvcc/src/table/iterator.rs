@@ -86,6 +86,10 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.key()
     }
 
+    fn expire_at(&self) -> Option<u64> {
+        self.blk_iter.expire_at()
+    }
+
     fn is_valid(&self) -> bool {
         self.blk_iter.is_valid()
     }
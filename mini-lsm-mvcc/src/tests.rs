@@ -1,4 +1,27 @@
+mod background_scheduler;
+mod background_work;
+mod change_feed;
+mod compaction_filter;
+mod compaction_stats;
+mod concurrent_compaction;
+mod fifo;
 mod harness;
+mod lazy_leveling;
+mod manifest;
+mod mvcc_harness;
+mod options;
+mod orphan_files;
+mod parallel_open;
+mod point_lookup;
+mod range_compaction;
+mod rate_limiter;
+mod sst_partitioning;
+mod sst_selection;
+mod subcompaction;
+mod tiered_compaction;
+mod trivial_move;
+mod ttl;
+mod wal;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
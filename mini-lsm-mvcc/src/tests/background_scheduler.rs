@@ -0,0 +1,161 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, FifoCompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    scheduler::{BackgroundScheduler, JobPriority, PoolState},
+};
+
+fn wait_until(mut done: impl FnMut() -> bool) {
+    for _ in 0..100 {
+        if done() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    panic!("timed out");
+}
+
+#[test]
+fn test_scheduler_pools() {
+    let scheduler = BackgroundScheduler::new();
+    let counter = Arc::new(AtomicUsize::new(0));
+    let job = |counter: &Arc<AtomicUsize>| {
+        let counter = counter.clone();
+        move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+    // jobs are rejected until the scheduler is started
+    assert!(!scheduler.schedule(JobPriority::High, job(&counter)));
+
+    scheduler.start(2, 1);
+    for _ in 0..10 {
+        assert!(scheduler.schedule(JobPriority::High, job(&counter)));
+        assert!(scheduler.schedule(JobPriority::Low, job(&counter)));
+    }
+    wait_until(|| counter.load(Ordering::SeqCst) == 20);
+    wait_until(|| scheduler.state().low.completed == 10);
+    let state = scheduler.state();
+    assert_eq!(
+        state.high,
+        PoolState {
+            threads: 2,
+            queued: 0,
+            running: 0,
+            completed: 10
+        }
+    );
+    assert_eq!(state.low.threads, 1);
+
+    // a panicking job does not take its thread down
+    assert!(scheduler.schedule(JobPriority::Low, || panic!("job failed")));
+    assert!(scheduler.schedule(JobPriority::Low, job(&counter)));
+    wait_until(|| counter.load(Ordering::SeqCst) == 21);
+
+    assert!(scheduler.schedule_at(
+        Instant::now() + Duration::from_millis(100),
+        JobPriority::Low,
+        job(&counter)
+    ));
+    assert_eq!(scheduler.state().timers, 1);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(counter.load(Ordering::SeqCst), 21);
+    wait_until(|| counter.load(Ordering::SeqCst) == 22);
+    assert_eq!(scheduler.state().timers, 0);
+    scheduler.shutdown();
+}
+
+#[test]
+fn test_scheduler_shutdown_waits_for_running_jobs() {
+    let scheduler = BackgroundScheduler::new();
+    scheduler.start(1, 1);
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..3 {
+        let counter = counter.clone();
+        scheduler.schedule(JobPriority::Low, move || {
+            std::thread::sleep(Duration::from_millis(200));
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    wait_until(|| scheduler.state().low.running == 1);
+    assert_eq!(scheduler.state().low.queued, 2);
+
+    // the running job finishes, and the queued ones are dropped
+    scheduler.shutdown();
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    let state = scheduler.state();
+    assert!(state.shutting_down);
+    assert_eq!(state.low.queued, 0);
+    assert_eq!(state.low.running, 0);
+    assert!(!scheduler.schedule(JobPriority::High, || {}));
+    assert!(!scheduler.is_running(JobPriority::Low));
+}
+
+#[test]
+fn test_background_jobs_triggered_by_events() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.num_memtable_limit = 2;
+    options.max_background_flushes = 2;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let state = storage.background_scheduler_state();
+    assert_eq!(state.high.threads, 2);
+    assert_eq!(state.low.threads, 1);
+
+    // freezing the memtables over the limit schedules flushes, and the flushes schedule compactions
+    for i in 0..4 {
+        storage
+            .put(format!("key{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+    }
+    wait_until(|| storage.background_scheduler_state().low.completed > 0);
+    wait_until(|| {
+        let state = storage.background_scheduler_state();
+        state.high.queued + state.high.running + state.low.queued + state.low.running == 0
+    });
+    {
+        let state = storage.inner.state.read();
+        assert!(state.imm_memtables.len() < 2);
+        assert!(state.l0_sstables.len() < 2);
+    }
+    assert!(storage.background_scheduler_state().high.completed >= 3);
+    assert_eq!(storage.get(b"key0").unwrap(), Some(Bytes::from("value")));
+
+    storage.close().unwrap();
+    assert!(storage.background_scheduler_state().shutting_down);
+}
+
+#[test]
+fn test_expiring_ssts_dropped_by_timer() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(FifoCompactionOptions {
+            max_total_size_mb: 1024,
+            ttl_secs: Some(1),
+        }));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"value").unwrap();
+    storage.force_flush().unwrap();
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    assert_eq!(storage.background_scheduler_state().timers, 1);
+
+    // nothing happens to the storage, but the timer fires once the SST expires
+    wait_until(|| storage.inner.state.read().l0_sstables.is_empty());
+    assert_eq!(storage.get(b"a").unwrap(), None);
+}
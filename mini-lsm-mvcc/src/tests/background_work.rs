@@ -0,0 +1,279 @@
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{
+        CompactionDecision, CompactionFilter, LsmStorageInner, LsmStorageOptions, MiniLsm,
+    },
+};
+
+fn sst_files(path: &Path) -> BTreeSet<String> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".sst"))
+        .collect()
+}
+
+/// Pauses the background work the first time a compaction reaches `key`, as `pause_background_work` would from another
+/// thread. It cannot be called here, as it waits for the compaction to stop.
+struct PauseAt {
+    storage: Arc<LsmStorageInner>,
+    key: &'static [u8],
+    paused: AtomicBool,
+}
+
+impl CompactionFilter for PauseAt {
+    fn filter(&self, key: &[u8], _ts: u64, _value: &[u8], _level: usize) -> CompactionDecision {
+        if key == self.key && !self.paused.swap(true, Ordering::SeqCst) {
+            self.storage
+                .background_work_paused
+                .fetch_add(1, Ordering::SeqCst);
+        }
+        CompactionDecision::Keep
+    }
+}
+
+/// Blocks the first compaction reaching `key` until the background work is paused, and sets `reached` meanwhile.
+struct WaitForPause {
+    storage: Arc<LsmStorageInner>,
+    key: &'static [u8],
+    reached: Arc<AtomicBool>,
+}
+
+impl CompactionFilter for WaitForPause {
+    fn filter(&self, key: &[u8], _ts: u64, _value: &[u8], _level: usize) -> CompactionDecision {
+        if key == self.key && !self.reached.swap(true, Ordering::SeqCst) {
+            while self.storage.background_work_paused.load(Ordering::SeqCst) == 0 {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        CompactionDecision::Keep
+    }
+}
+
+#[test]
+fn test_pause_and_continue_background_work() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.continue_background_work().is_err());
+
+    storage.pause_background_work();
+    for i in 0..4 {
+        storage
+            .put(format!("key{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(300));
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables.len(), 4);
+        assert!(state.levels.iter().all(|(_, ssts)| ssts.is_empty()));
+    }
+
+    // pauses nest
+    storage.pause_background_work();
+    storage.continue_background_work().unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 4);
+
+    storage.continue_background_work().unwrap();
+    assert!(storage.continue_background_work().is_err());
+    for _ in 0..100 {
+        if storage.inner.state.read().l0_sstables.len() < 2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(storage.inner.state.read().l0_sstables.len() < 2);
+    assert_eq!(storage.get(b"key3").unwrap(), Some(Bytes::from("value")));
+}
+
+#[test]
+fn test_cancel_running_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 4096;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        for i in 0..1000 {
+            storage
+                .put(
+                    format!("key{:04}", i).as_bytes(),
+                    format!("value{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+        while !storage.inner.state.read().imm_memtables.is_empty() {
+            storage.force_flush().unwrap();
+        }
+    }
+    let files = sst_files(dir.path());
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+
+    // the compaction writes a few SSTs before it is cancelled
+    storage.add_compaction_filter(PauseAt {
+        storage: storage.inner.clone(),
+        key: b"key0800",
+        paused: AtomicBool::new(false),
+    });
+    let err = storage.force_full_compaction().unwrap_err();
+    assert!(err.to_string().contains("cancelled"), "{}", err);
+    assert_eq!(sst_files(dir.path()), files);
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables, l0_sstables);
+        assert!(state.levels.iter().all(|(_, ssts)| ssts.is_empty()));
+    }
+    assert_eq!(
+        storage.get(b"key0999").unwrap(),
+        Some(Bytes::from("value1"))
+    );
+
+    storage.continue_background_work().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(storage.inner.state.read().l0_sstables.is_empty());
+    assert_eq!(
+        storage.get(b"key0500").unwrap(),
+        Some(Bytes::from("value1"))
+    );
+    drop(storage);
+
+    // the manifest does not mention the cancelled compaction
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(b"key0999").unwrap(),
+        Some(Bytes::from("value1"))
+    );
+}
+
+#[test]
+fn test_pause_waits_for_running_flush() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.pause_background_work();
+    for i in 0..2 {
+        storage
+            .put(format!("key{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+    }
+
+    // the flush scheduled once continued blocks on the state lock
+    let state_lock = storage.inner.state_lock.lock();
+    storage.continue_background_work().unwrap();
+    while storage.inner.running_flushes.load(Ordering::SeqCst) == 0 {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let pause = std::thread::spawn({
+        let storage = storage.clone();
+        move || storage.pause_background_work()
+    });
+    std::thread::sleep(Duration::from_millis(300));
+    assert!(!pause.is_finished());
+
+    drop(state_lock);
+    pause.join().unwrap();
+    assert_eq!(storage.inner.running_flushes.load(Ordering::SeqCst), 0);
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables.len(), 1);
+        assert_eq!(state.imm_memtables.len(), 1);
+    }
+    storage.continue_background_work().unwrap();
+}
+
+#[test]
+fn test_pause_cancels_manual_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 10,
+            max_levels: 2,
+        },
+    ));
+    options.target_sst_size = 4096;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..1000 {
+        storage
+            .put(format!("key{:04}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    while !storage.inner.state.read().imm_memtables.is_empty() {
+        storage.force_flush().unwrap();
+    }
+    let files = sst_files(dir.path());
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+
+    // a pause cancels the running range compaction, and waits for it to stop
+    let reached = Arc::new(AtomicBool::new(false));
+    storage.add_compaction_filter(WaitForPause {
+        storage: storage.inner.clone(),
+        key: b"key0500",
+        reached: reached.clone(),
+    });
+    let pause = std::thread::spawn({
+        let storage = storage.clone();
+        move || {
+            while !reached.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            storage.pause_background_work()
+        }
+    });
+    let err = storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 1)
+        .unwrap_err();
+    assert!(err.to_string().contains("cancelled"), "{}", err);
+    pause.join().unwrap();
+    assert_eq!(sst_files(dir.path()), files);
+    assert_eq!(storage.inner.state.read().l0_sstables, l0_sstables);
+
+    // while paused, a manual compaction fails as well
+    let err = storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 1)
+        .unwrap_err();
+    assert!(err.to_string().contains("cancelled"), "{}", err);
+    assert_eq!(storage.inner.state.read().l0_sstables, l0_sstables);
+
+    storage.continue_background_work().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 1)
+        .unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert!(!state.levels[0].1.is_empty());
+    }
+    assert_eq!(storage.get(b"key0999").unwrap(), Some(Bytes::from("value")));
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+fn put(key: &'static str, value: &'static str) -> WriteBatchRecord<Bytes> {
+    WriteBatchRecord::Put(Bytes::from(key), Bytes::from(value))
+}
+
+fn del(key: &'static str) -> WriteBatchRecord<Bytes> {
+    WriteBatchRecord::Del(Bytes::from(key))
+}
+
+#[test]
+fn test_change_feed_replay_and_tail() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"b"[..], &b"2"[..]),
+            WriteBatchRecord::Del(&b"a"[..]),
+        ])
+        .unwrap();
+
+    let all = storage.subscribe(0).unwrap();
+    let latest = storage.subscribe(2).unwrap();
+    assert_eq!(all.try_recv().unwrap(), (1, vec![put("a", "1")]));
+    assert_eq!(all.try_recv().unwrap(), (2, vec![put("b", "2"), del("a")]));
+    assert!(all.try_recv().is_err());
+    assert_eq!(latest.try_recv().unwrap().0, 2);
+
+    storage.put(b"c", b"3").unwrap();
+    let change = (3, vec![put("c", "3")]);
+    assert_eq!(all.recv_timeout(Duration::from_secs(1)).unwrap(), change);
+    assert_eq!(latest.recv_timeout(Duration::from_secs(1)).unwrap(), change);
+
+    // transactions are published at their commit ts
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"d", b"4");
+    txn.commit().unwrap();
+    assert_eq!(
+        all.recv_timeout(Duration::from_secs(1)).unwrap(),
+        (4, vec![put("d", "4")])
+    );
+}
+
+#[test]
+fn test_change_feed_retention() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.change_feed_retention = 2;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.put(b"c", b"3").unwrap();
+    assert!(storage.subscribe(1).is_err());
+    let feed = storage.subscribe(2).unwrap();
+    assert_eq!(feed.try_recv().unwrap(), (2, vec![put("b", "2")]));
+    assert_eq!(feed.try_recv().unwrap(), (3, vec![put("c", "3")]));
+}
+
+#[test]
+fn test_change_feed_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.delete(b"a").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    // the first commit was flushed and can no longer be replayed
+    assert!(storage.subscribe(1).is_err());
+    let feed = storage.subscribe(2).unwrap();
+    assert_eq!(feed.try_recv().unwrap(), (2, vec![put("b", "2")]));
+    assert_eq!(feed.try_recv().unwrap(), (3, vec![del("a")]));
+    assert!(feed.try_recv().is_err());
+}
@@ -0,0 +1,114 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{CompactionDecision, CompactionFilter, LsmStorageOptions, MiniLsm},
+};
+
+/// Strips the `token` field from JSON values, and records the keys and levels it is called with.
+#[derive(Default)]
+struct StripToken {
+    calls: Mutex<Vec<(Bytes, usize)>>,
+}
+
+impl CompactionFilter for StripToken {
+    fn filter(&self, key: &[u8], _ts: u64, value: &[u8], level: usize) -> CompactionDecision {
+        self.calls.lock().push((Bytes::copy_from_slice(key), level));
+        let Ok(serde_json::Value::Object(mut object)) = serde_json::from_slice(value) else {
+            return CompactionDecision::Keep;
+        };
+        if object.remove("token").is_none() {
+            return CompactionDecision::Keep;
+        }
+        CompactionDecision::ChangeValue(serde_json::to_vec(&object).unwrap().into())
+    }
+}
+
+/// Removes the keys whose value is `drop`.
+struct DropValue;
+
+impl CompactionFilter for DropValue {
+    fn filter(&self, _key: &[u8], _ts: u64, value: &[u8], _level: usize) -> CompactionDecision {
+        if value == b"drop" {
+            CompactionDecision::Remove
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
+
+#[test]
+fn test_compaction_filter_change_value() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage
+        .put(b"session1", br#"{"user":"alice","token":"secret"}"#)
+        .unwrap();
+    storage.put(b"session2", br#"{"user":"bob"}"#).unwrap();
+    storage.put(b"session3", b"not json").unwrap();
+    storage.delete(b"session3").unwrap();
+    storage.force_flush().unwrap();
+    let filter = Arc::new(StripToken::default());
+    storage.add_compaction_filter(filter.clone());
+    storage.force_full_compaction().unwrap();
+
+    assert_eq!(
+        storage.get(b"session1").unwrap(),
+        Some(Bytes::from(r#"{"user":"alice"}"#))
+    );
+    assert_eq!(
+        storage.get(b"session2").unwrap(),
+        Some(Bytes::from(r#"{"user":"bob"}"#))
+    );
+    assert_eq!(storage.get(b"session3").unwrap(), None);
+    // tombstones are not passed to filters
+    let calls = filter.calls.lock().clone();
+    assert_eq!(
+        calls,
+        vec![(Bytes::from("session1"), 1), (Bytes::from("session2"), 1)]
+    );
+}
+
+#[test]
+fn test_compaction_filter_remove_above_bottom_level() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 2,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"key", b"old").unwrap();
+    storage.force_flush().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 2)
+        .unwrap();
+
+    storage.put(b"key", b"drop").unwrap();
+    storage.force_flush().unwrap();
+    storage.add_compaction_filter(DropValue);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 1)
+        .unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.levels[0].1.len(), 1);
+        assert_eq!(state.levels[1].1.len(), 1);
+    }
+    // the removed key is replaced with a tombstone, which keeps the old version in L2 hidden
+    assert_eq!(storage.get(b"key").unwrap(), None);
+
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 2)
+        .unwrap();
+    let state = storage.inner.state.read();
+    assert!(state.levels.iter().all(|(_, ssts)| ssts.is_empty()));
+}
@@ -0,0 +1,59 @@
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+#[test]
+fn test_flush_and_compaction_stats() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..100 {
+        storage
+            .put(format!("key{:03}", i).as_bytes(), b"value0")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    // overwrite the first half and delete the second half
+    for i in 0..100 {
+        let key = format!("key{:03}", i);
+        if i < 50 {
+            storage.put(key.as_bytes(), b"value1").unwrap();
+        } else {
+            storage.delete(key.as_bytes()).unwrap();
+        }
+    }
+    storage.force_flush().unwrap();
+
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.num_flushes, 2);
+    assert!(stats.flush_bytes > 0);
+    assert!(stats.levels.is_empty());
+    assert_eq!(stats.write_amplification(), 1.0);
+
+    let input_bytes = {
+        let state = storage.inner.state.read();
+        state
+            .l0_sstables
+            .iter()
+            .map(|id| state.sstables[id].table_size())
+            .sum::<u64>()
+    };
+    storage.force_full_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!(stats.levels.keys().copied().collect::<Vec<_>>(), vec![1]);
+    let l1 = &stats.levels[&1];
+    assert_eq!(l1.num_compactions, 1);
+    assert_eq!(l1.input_files, 2);
+    assert_eq!(l1.bytes_read, input_bytes);
+    assert!(l1.output_files >= 1);
+    assert!(l1.bytes_written > 0);
+    // the deleted keys are gone along with the versions they hide, and so are the overwritten versions
+    assert_eq!(l1.dropped_tombstones, 50);
+    assert_eq!(l1.dropped_versions, 100);
+    assert_eq!(stats.total(), *l1);
+    assert!(stats.write_amplification() > 1.0);
+    assert!(stats.to_string().contains("L1"));
+}
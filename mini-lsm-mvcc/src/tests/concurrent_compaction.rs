@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionTask, LeveledCompactionController, LeveledCompactionOptions,
+        RunningCompactions, SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+    },
+    key::KeyBytes,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+    mem_table::MemTable,
+    table::SsTable,
+};
+
+const MB: u64 = 1024 * 1024;
+
+pub(crate) fn key(key: &str) -> KeyBytes {
+    KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key.as_bytes()), 0)
+}
+
+/// Create a state of mock SSTs, given as `(id, first key, last key, size)` for L0 and each level.
+pub(crate) fn mock_state(
+    l0: &[(usize, &str, &str, u64)],
+    levels: &[&[(usize, &str, &str, u64)]],
+) -> LsmStorageState {
+    let mut sstables = HashMap::new();
+    for (id, first_key, last_key, size) in l0.iter().chain(levels.iter().copied().flatten()) {
+        sstables.insert(
+            *id,
+            Arc::new(SsTable::create_meta_only(
+                *id,
+                *size,
+                key(first_key),
+                key(last_key),
+            )),
+        );
+    }
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: l0.iter().map(|(id, ..)| *id).collect(),
+        levels: levels
+            .iter()
+            .enumerate()
+            .map(|(idx, ssts)| (idx + 1, ssts.iter().map(|(id, ..)| *id).collect()))
+            .collect(),
+        sstables,
+    }
+}
+
+#[test]
+fn test_leveled_concurrent_tasks() {
+    let controller = LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 1,
+        max_levels: 2,
+        base_level_size_mb: 1,
+    });
+    // L1 is larger than its target of 5MB
+    let mut state = mock_state(
+        &[],
+        &[
+            &[(1, "k00", "k09", 6 * MB), (2, "k10", "k19", 6 * MB)],
+            &[(3, "k00", "k09", 5 * MB), (4, "k10", "k19", 5 * MB)],
+        ],
+    );
+    let mut running = RunningCompactions::default();
+    let task1 = controller
+        .generate_compaction_task_excluding(&state, &running)
+        .unwrap();
+    assert_eq!(task1.upper_level_sst_ids, vec![1]);
+    assert_eq!(task1.lower_level_sst_ids, vec![3]);
+    let job1 = running.register(&state, &CompactionTask::Leveled(task1));
+    let task2 = controller
+        .generate_compaction_task_excluding(&state, &running)
+        .unwrap();
+    assert_eq!(task2.upper_level_sst_ids, vec![2]);
+    assert_eq!(task2.lower_level_sst_ids, vec![4]);
+    running.register(&state, &CompactionTask::Leveled(task2));
+    assert!(controller
+        .generate_compaction_task_excluding(&state, &running)
+        .is_none());
+
+    // an L0 SST apart from the running jobs is compacted right away
+    let l0_state = mock_state(
+        &[(5, "k30", "k39", MB)],
+        &[
+            &[(1, "k00", "k09", 6 * MB), (2, "k10", "k19", 6 * MB)],
+            &[(3, "k00", "k09", 5 * MB), (4, "k10", "k19", 5 * MB)],
+        ],
+    );
+    let task3 = controller
+        .generate_compaction_task_excluding(&l0_state, &running)
+        .unwrap();
+    assert_eq!(task3.upper_level, None);
+    assert!(task3.lower_level_sst_ids.is_empty());
+
+    // but one overlapping a running job waits for it
+    state = mock_state(
+        &[(5, "k05", "k05", MB)],
+        &[
+            &[(1, "k00", "k09", 6 * MB), (2, "k10", "k19", 6 * MB)],
+            &[(3, "k00", "k09", 5 * MB), (4, "k10", "k19", 5 * MB)],
+        ],
+    );
+    assert!(controller
+        .generate_compaction_task_excluding(&state, &running)
+        .is_none());
+    running.release(job1);
+    let task4 = controller
+        .generate_compaction_task_excluding(&state, &running)
+        .unwrap();
+    assert_eq!(task4.upper_level, None);
+    assert_eq!(task4.lower_level_sst_ids, vec![1]);
+}
+
+#[test]
+fn test_simple_leveled_concurrent_tasks() {
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 1,
+        max_levels: 3,
+    });
+    let state = mock_state(
+        &[(1, "k00", "k09", MB)],
+        &[&[(2, "k00", "k09", MB)], &[], &[]],
+    );
+    let mut running = RunningCompactions::default();
+    let task1 = controller
+        .generate_compaction_task_excluding(&state, &running)
+        .unwrap();
+    assert_eq!(task1.upper_level, None);
+    running.register(&state, &CompactionTask::Simple(task1));
+    // L1 is taken by the L0 compaction, so L1 and L2 cannot be compacted either
+    assert!(controller
+        .generate_compaction_task_excluding(&state, &running)
+        .is_none());
+
+    let state = mock_state(
+        &[(1, "k00", "k09", MB)],
+        &[&[], &[(2, "k00", "k09", MB)], &[]],
+    );
+    let running = {
+        let mut running = RunningCompactions::default();
+        let task = controller
+            .generate_compaction_task_excluding(&state, &running)
+            .unwrap();
+        assert_eq!(task.upper_level, None);
+        running.register(&state, &CompactionTask::Simple(task));
+        running
+    };
+    let task2 = controller
+        .generate_compaction_task_excluding(&state, &running)
+        .unwrap();
+    assert_eq!(task2.upper_level, Some(2));
+    assert_eq!(task2.lower_level, 3);
+}
+
+#[test]
+fn test_concurrent_compaction_jobs() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 1,
+        },
+    ));
+    options.target_sst_size = 64 << 10;
+    options.max_background_compactions = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..8 {
+        for i in 0..2000 {
+            storage
+                .put(
+                    format!("key{:05}", (i * 7919 + round) % 20000).as_bytes(),
+                    format!("value{:0100}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+    }
+    for _ in 0..200 {
+        std::thread::sleep(Duration::from_millis(50));
+        if storage.inner.running_compactions.lock().is_empty() {
+            break;
+        }
+    }
+    storage.close().unwrap();
+
+    // every level is still a sorted run
+    let state = storage.inner.state.read().clone();
+    for (_, ssts) in &state.levels {
+        for pair in ssts.windows(2) {
+            assert!(state.sstables[&pair[0]].last_key() < state.sstables[&pair[1]].first_key());
+        }
+    }
+    for round in 0..8 {
+        for i in (0..2000).step_by(97) {
+            assert_eq!(
+                storage
+                    .get(format!("key{:05}", (i * 7919 + round) % 20000).as_bytes())
+                    .unwrap(),
+                Some(Bytes::from(format!("value{:0100}", round)))
+            );
+        }
+    }
+}
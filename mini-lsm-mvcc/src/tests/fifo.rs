@@ -0,0 +1,108 @@
+use std::time::{Duration, SystemTime};
+
+use tempfile::tempdir;
+
+use super::concurrent_compaction::mock_state;
+use crate::{
+    compact::{CompactionOptions, FifoCompactionController, FifoCompactionOptions},
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{FileObject, SsTable, SsTableBuilder},
+};
+
+const MB: u64 = 1024 * 1024;
+
+#[test]
+fn test_fifo_drops_oldest_over_size_limit() {
+    let controller = FifoCompactionController::new(FifoCompactionOptions {
+        max_total_size_mb: 3,
+        ttl_secs: None,
+    });
+    // L0 is ordered from the newest to the oldest
+    let snapshot = mock_state(
+        &[
+            (5, "a", "z", MB),
+            (4, "a", "z", MB),
+            (3, "a", "z", MB),
+            (2, "a", "z", MB),
+            (1, "a", "z", MB),
+        ],
+        &[],
+    );
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.sst_ids, vec![1, 2]);
+    let (snapshot, removed) = controller.apply_compaction_result(&snapshot, &task);
+    assert_eq!(snapshot.l0_sstables, vec![5, 4, 3]);
+    assert_eq!(removed, vec![1, 2]);
+    assert!(controller.generate_compaction_task(&snapshot).is_none());
+}
+
+#[test]
+fn test_fifo_drops_expired_ssts() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::Fifo(FifoCompactionOptions {
+            max_total_size_mb: 1024,
+            ttl_secs: Some(3),
+        }));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for key in ["a", "b"] {
+        storage.put(key.as_bytes(), b"value").unwrap();
+        storage.force_flush().unwrap();
+    }
+    let old_ssts = storage.inner.state.read().l0_sstables.clone();
+    assert_eq!(old_ssts.len(), 2);
+
+    // the SSTs of `a` and `b` expire while the one of `c` is still fresh
+    std::thread::sleep(Duration::from_millis(2000));
+    storage.put(b"c", b"value").unwrap();
+    storage.force_flush().unwrap();
+    for _ in 0..30 {
+        // the files are deleted after the SSTs are removed from the state
+        if storage.inner.state.read().l0_sstables.len() == 1
+            && old_ssts
+                .iter()
+                .all(|id| !storage.inner.path_of_sst(*id).exists())
+        {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    for id in &old_ssts {
+        assert!(!storage.inner.path_of_sst(*id).exists());
+    }
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert!(storage.get(b"c").unwrap().is_some());
+    storage.close().unwrap();
+    drop(storage);
+
+    // the dropped SSTs are recorded in the manifest
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert!(storage.get(b"c").unwrap().is_some());
+}
+
+#[test]
+fn test_sst_creation_time_survives_copy() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    builder.add(KeySlice::for_testing_from_slice_no_ts(b"a"), b"value");
+    let before = SystemTime::now() - Duration::from_millis(1);
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let creation_time = sst.creation_time().unwrap();
+    assert!(creation_time >= before && creation_time <= SystemTime::now());
+
+    // the creation time is stored in the SST rather than taken from the file
+    std::thread::sleep(Duration::from_millis(10));
+    std::fs::copy(dir.path().join("1.sst"), dir.path().join("2.sst")).unwrap();
+    let copy = SsTable::open(
+        2,
+        None,
+        FileObject::open(&dir.path().join("2.sst")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(copy.creation_time(), Some(creation_time));
+}
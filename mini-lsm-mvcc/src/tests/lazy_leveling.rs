@@ -0,0 +1,106 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::concurrent_compaction::mock_state;
+use super::mvcc_harness::check_compaction_ratio;
+use crate::{
+    compact::{CompactionOptions, LazyLeveledCompactionController, LazyLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+const MB: u64 = 1024 * 1024;
+
+fn options() -> LazyLeveledCompactionOptions {
+    LazyLeveledCompactionOptions {
+        level_size_multiplier: 3,
+        max_levels: 3,
+        base_level_size_mb: 1,
+    }
+}
+
+#[test]
+fn test_lazy_leveled_upper_levels_are_tiered() {
+    let controller = LazyLeveledCompactionController::new(options());
+    // three runs of L1 are merged into one run of L2
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(1, "a", "z", MB)],
+            &[(2, "a", "z", MB)],
+            &[(3, "a", "z", MB)],
+            &[(4, "a", "z", 100 * MB)],
+        ],
+    );
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.runs, snapshot.levels[..3].to_vec());
+    assert!(!task.bottom_run_included);
+    let (snapshot, removed) = controller.apply_compaction_result(&snapshot, &task, &[5]);
+    assert_eq!(removed, vec![1, 2, 3]);
+    assert_eq!(snapshot.levels, vec![(5, vec![5]), (4, vec![4])]);
+
+    // three runs of L2, the last upper level, are merged into the bottom level
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(1, "a", "z", MB)],
+            &[(2, "a", "z", 3 * MB)],
+            &[(3, "a", "z", 3 * MB)],
+            &[(4, "a", "z", 3 * MB)],
+            &[(5, "a", "z", 100 * MB)],
+        ],
+    );
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.runs, snapshot.levels[1..].to_vec());
+    assert!(task.bottom_run_included);
+}
+
+#[test]
+fn test_lazy_leveled_bottom_level_bounds_upper_levels() {
+    let controller = LazyLeveledCompactionController::new(options());
+    // the upper levels hold more than half of the bottom level
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(1, "a", "z", MB)],
+            &[(2, "a", "z", MB)],
+            &[(3, "a", "z", 4 * MB)],
+        ],
+    );
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.runs, snapshot.levels);
+    assert!(task.bottom_run_included);
+}
+
+#[test]
+fn test_lazy_leveled_recovery() {
+    let dir = tempdir().unwrap();
+    let options =
+        LsmStorageOptions::default_for_week2_test(CompactionOptions::LazyLeveled(options()));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..4 {
+        for i in 0..1000 {
+            storage
+                .put(
+                    format!("key{:04}", i * 7 % 1000).as_bytes(),
+                    format!("value{}_{:0100}", round, i).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    storage.close().unwrap();
+    check_compaction_ratio(storage.clone());
+    let levels = storage.inner.state.read().levels.clone();
+    assert!(levels.len() < 4);
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels, levels);
+    for i in 0..1000 {
+        let value = storage
+            .get(format!("key{:04}", i * 7 % 1000).as_bytes())
+            .unwrap();
+        assert_eq!(value, Some(Bytes::from(format!("value3_{:0100}", i))));
+    }
+}
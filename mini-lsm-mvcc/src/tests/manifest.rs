@@ -0,0 +1,303 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+        TieredCompactionOptions,
+    },
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    manifest::{ManifestEdit, ManifestRecord},
+};
+
+fn manifest_files(dir: impl AsRef<Path>) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("MANIFEST"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_manifest_rollover() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.max_manifest_size = 512;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..20 {
+        storage
+            .put(format!("key{:02}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    storage.close().unwrap();
+
+    let files = manifest_files(&dir);
+    assert_eq!(files.len(), 1);
+    assert_ne!(files[0], "MANIFEST-000001");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("CURRENT"))
+            .unwrap()
+            .trim(),
+        files[0]
+    );
+    let (l0_sstables, levels) = {
+        let state = storage.inner.state.read();
+        (state.l0_sstables.clone(), state.levels.clone())
+    };
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.l0_sstables, l0_sstables);
+        assert_eq!(state.levels, levels);
+    }
+    for i in 0..20 {
+        assert_eq!(
+            storage.get(format!("key{:02}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
+
+#[test]
+fn test_manifest_migrate_legacy_json() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // a database created before the binary format has a `MANIFEST` file of JSON records and no `CURRENT`
+    for file in manifest_files(&dir) {
+        std::fs::remove_file(dir.path().join(file)).unwrap();
+    }
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    let mut legacy = Vec::new();
+    for record in [
+        r#"{"NewMemtable":0}"#,
+        r#"{"NewMemtable":1}"#,
+        r#"{"Flush":0}"#,
+    ] {
+        legacy.extend((record.len() as u64).to_be_bytes());
+        legacy.extend(record.as_bytes());
+        legacy.extend(crc32fast::hash(record.as_bytes()).to_be_bytes());
+    }
+    std::fs::write(dir.path().join("MANIFEST"), legacy).unwrap();
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(manifest_files(&dir), vec!["MANIFEST-000001"]);
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 2);
+}
+
+#[test]
+fn test_manifest_legacy_json_other_compaction_style() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // a legacy database has no `OPTIONS` file, but its manifest has the tasks of simple leveled compaction
+    for file in manifest_files(&dir) {
+        std::fs::remove_file(dir.path().join(file)).unwrap();
+    }
+    std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+    std::fs::remove_file(dir.path().join("OPTIONS")).unwrap();
+    let mut legacy = Vec::new();
+    for record in [
+        r#"{"NewMemtable":0}"#,
+        r#"{"NewMemtable":1}"#,
+        r#"{"Flush":0}"#,
+        r#"{"Compaction":[{"Simple":{"upper_level":null,"upper_level_sst_ids":[0],"lower_level":1,"lower_level_sst_ids":[],"is_lower_level_bottom_level":true}},[0]]}"#,
+    ] {
+        legacy.extend((record.len() as u64).to_be_bytes());
+        legacy.extend(record.as_bytes());
+        legacy.extend(crc32fast::hash(record.as_bytes()).to_be_bytes());
+    }
+    std::fs::write(dir.path().join("MANIFEST"), legacy).unwrap();
+
+    let tiered = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    let err = MiniLsm::open(&dir, tiered).err().unwrap();
+    assert!(
+        err.to_string().contains("simple leveled compaction"),
+        "{}",
+        err
+    );
+    let leveled = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let err = MiniLsm::open(&dir, leveled).err().unwrap();
+    assert!(
+        err.to_string().contains("using leveled compaction"),
+        "{}",
+        err
+    );
+
+    let simple = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, simple).unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert_eq!(state.levels[0], (1, vec![0]));
+    }
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_manifest_replay_compaction_edits() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..10 {
+        storage
+            .put(format!("key{:02}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    storage.close().unwrap();
+    let levels = storage.inner.state.read().levels.clone();
+    // some tiers have been merged
+    assert!(levels.len() < 10);
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels, levels);
+    for i in 0..10 {
+        assert_eq!(
+            storage.get(format!("key{:02}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
+
+#[test]
+fn test_manifest_replay_full_compaction() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.close().unwrap();
+    let levels = storage.inner.state.read().levels.clone();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert_eq!(state.levels, levels);
+    }
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_manifest_replay_sorts_levels() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 64;
+    options.target_sst_size = 1024;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for round in 0..2 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key{:03}", i).as_bytes(),
+                    format!("value{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    storage.close().unwrap();
+    let (level, ssts) = storage
+        .inner
+        .state
+        .read()
+        .levels
+        .iter()
+        .find(|(_, ssts)| ssts.len() > 2)
+        .cloned()
+        .unwrap();
+
+    // edits append SSTs to a level, so the SSTs of a level are replayed out of key order
+    storage
+        .inner
+        .manifest()
+        .add_record_when_init(ManifestRecord::Compaction(ManifestEdit {
+            removed: vec![(level, ssts.clone())],
+            added: vec![(level, ssts.iter().rev().copied().collect())],
+            ..Default::default()
+        }))
+        .unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let state = storage.inner.state.read().clone();
+    let (_, recovered) = state.levels.iter().find(|(id, _)| *id == level).unwrap();
+    assert_eq!(recovered, &ssts);
+    drop(state);
+    for i in 0..100 {
+        assert_eq!(
+            storage.get(format!("key{:03}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value1"))
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::{ops::Bound, sync::Arc};
+
+use crate::{
+    compact::{CompactionOptions, LazyLeveledCompactionOptions},
+    iterators::StorageIterator,
+    key::TS_ENABLED,
+    lsm_storage::MiniLsm,
+};
+
+use super::harness;
+
+/// Check the shape of the LSM tree like `harness::check_compaction_ratio`, also covering the compaction strategies that
+/// only exist in this crate.
+pub fn check_compaction_ratio(storage: Arc<MiniLsm>) {
+    let compaction_options = storage.inner.options.compaction_options.clone();
+    let CompactionOptions::LazyLeveled(LazyLeveledCompactionOptions {
+        level_size_multiplier,
+        ..
+    }) = compaction_options
+    else {
+        return harness::check_compaction_ratio(storage);
+    };
+    let state = storage.inner.state.read().clone();
+    let level_size = state
+        .levels
+        .iter()
+        .map(|(_, files)| {
+            files
+                .iter()
+                .map(|x| state.sstables[x].table_size())
+                .sum::<u64>()
+        })
+        .collect::<Vec<_>>();
+    let extra_iterators = if TS_ENABLED {
+        1 /* txn local iterator for OCC */
+    } else {
+        0
+    };
+    let num_iters = storage
+        .scan(Bound::Unbounded, Bound::Unbounded)
+        .unwrap()
+        .num_active_iterators();
+    let num_memtables = storage.inner.state.read().imm_memtables.len() + 1;
+
+    assert_eq!(state.l0_sstables.len(), 0);
+    if let Some((bottom_size, upper_sizes)) = level_size.split_last() {
+        let upper_size = upper_sizes.iter().sum::<u64>();
+        assert!(
+            upper_size * (level_size_multiplier as u64 - 1) < *bottom_size,
+            "upper levels too large: {}/{} bytes",
+            upper_size,
+            bottom_size
+        );
+    }
+    let num_runs = level_size.len();
+    assert!(
+        num_iters <= num_memtables + num_runs + extra_iterators,
+        "we found {num_iters} iterators in your implementation, (num_memtables={num_memtables}, num_runs={num_runs}) did you use concat iterators?"
+    );
+}
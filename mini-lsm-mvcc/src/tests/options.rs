@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    options::OptionsFile,
+};
+
+fn leveled(max_levels: usize) -> CompactionOptions {
+    CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels,
+        base_level_size_mb: 1,
+    })
+}
+
+#[test]
+fn test_options_incompatible() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let stored = OptionsFile::read(&dir, &options).unwrap();
+    assert_eq!(stored, Some(options.clone()));
+
+    let mut tiered = options.clone();
+    tiered.compaction_options = CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width: None,
+    });
+    let err = MiniLsm::open(&dir, tiered).err().unwrap();
+    assert!(err.to_string().contains("tiered compaction"), "{}", err);
+
+    let mut without_wal = options.clone();
+    without_wal.enable_wal = false;
+    let err = MiniLsm::open(&dir, without_wal).err().unwrap();
+    assert!(err.to_string().contains("WAL"), "{}", err);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_options_migrate_levels() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // added levels go on top, so the existing data stays in the bottom level
+    let leveled_options = LsmStorageOptions::default_for_week2_test(leveled(4));
+    let storage = MiniLsm::open(&dir, leveled_options.clone()).unwrap();
+    let mut state = storage.inner.state.read().as_ref().clone();
+    assert_eq!(
+        state
+            .levels
+            .iter()
+            .map(|(level, _)| *level)
+            .collect::<Vec<_>>(),
+        vec![1, 2, 3, 4]
+    );
+    assert!(state.levels[..3].iter().all(|(_, ssts)| ssts.is_empty()));
+    assert_eq!(state.levels[3].1.len(), 1);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    storage.close().unwrap();
+    drop(storage);
+    assert_eq!(
+        OptionsFile::read(&dir, &options).unwrap(),
+        Some(leveled_options.clone())
+    );
+
+    // only empty levels can be removed
+    let ssts = std::mem::take(&mut state.levels[3].1);
+    state.levels[0].1 = ssts;
+    let err = state
+        .migrate_levels(&LsmStorageOptions::default_for_week2_test(leveled(3)))
+        .unwrap_err();
+    assert!(err.to_string().contains("level 1 is not empty"), "{}", err);
+
+    // the migrated layout was persisted, so the levels are removed from the top again
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let levels = storage.inner.state.read().levels.clone();
+    assert_eq!(levels.len(), 1);
+    assert_eq!(levels[0].1.len(), 1);
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_options_migrate_wal_dir() {
+    let dir = tempdir().unwrap();
+    let wal_dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    options.wal_dir = Some(wal_dir.path().to_path_buf());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert!(!dir.path().join("00000.log").exists());
+    assert!(wal_dir.path().join("00000.log").exists());
+}
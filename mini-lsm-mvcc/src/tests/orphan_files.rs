@@ -0,0 +1,65 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+#[test]
+fn test_orphan_files_cleaned_on_open() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let compacted = storage.inner.state.read().l0_sstables[0];
+    storage.put(b"b", b"2").unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    let live = storage.inner.state.read().levels[0].1[0];
+    storage.close().unwrap();
+    drop(storage);
+
+    let sst_path = |id: usize| dir.path().join(format!("{:05}.sst", id));
+    // an input of a recorded compaction that was not deleted before a crash
+    std::fs::copy(sst_path(live), sst_path(compacted)).unwrap();
+    // the output of a compaction that crashed before it was recorded
+    std::fs::copy(sst_path(live), sst_path(100)).unwrap();
+    // a WAL from before the WAL was segmented
+    std::fs::write(dir.path().join("00101.wal"), b"").unwrap();
+    // leftovers of a manifest rollover
+    std::fs::write(dir.path().join("MANIFEST-000100"), b"").unwrap();
+    std::fs::write(dir.path().join("CURRENT.tmp"), b"").unwrap();
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(!sst_path(compacted).exists());
+    assert!(!sst_path(100).exists());
+    assert!(dir.path().join("lost/00100.sst").exists());
+    assert!(dir.path().join("lost/00101.wal").exists());
+    assert!(!dir
+        .path()
+        .join(format!("lost/{:05}.sst", compacted))
+        .exists());
+    assert!(!dir.path().join("MANIFEST-000100").exists());
+    assert!(!dir.path().join("CURRENT.tmp").exists());
+    assert!(dir.path().join("MANIFEST-000001").exists());
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_missing_sst_fails_open() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    storage.close().unwrap();
+    drop(storage);
+
+    std::fs::remove_file(dir.path().join(format!("{:05}.sst", sst_id))).unwrap();
+    let err = MiniLsm::open(&dir, options).err().unwrap();
+    assert!(err.to_string().contains("missing"), "{}", err);
+}
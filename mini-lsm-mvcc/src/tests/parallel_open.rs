@@ -0,0 +1,65 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+#[test]
+fn test_parallel_open_deterministic() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..20 {
+        storage
+            .put(b"key", format!("value{}", i).as_bytes())
+            .unwrap();
+        storage
+            .put(format!("key{:02}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+    storage.close().unwrap();
+    drop(storage);
+
+    for threads in [1, 3, 16] {
+        let mut options = options.clone();
+        options.recovery_threads = threads;
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        let state = storage.inner.state.read().clone();
+        assert_eq!(state.l0_sstables, l0_sstables);
+        assert_eq!(state.sstables.len(), 20);
+        assert_eq!(storage.get(b"key").unwrap(), Some(Bytes::from("value19")));
+        assert_eq!(storage.get(b"key07").unwrap(), Some(Bytes::from("value")));
+        storage.close().unwrap();
+    }
+}
+
+#[test]
+fn test_parallel_open_reports_all_errors() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..5 {
+        storage
+            .put(format!("key{}", i).as_bytes(), b"value")
+            .unwrap();
+        storage.force_flush().unwrap();
+    }
+    let mut l0_sstables = storage.inner.state.read().l0_sstables.clone();
+    storage.close().unwrap();
+    drop(storage);
+
+    l0_sstables.sort();
+    for sst_id in [l0_sstables[3], l0_sstables[1]] {
+        let sst_path = dir.path().join(format!("{:05}.sst", sst_id));
+        std::fs::write(sst_path, b"garbage").unwrap();
+    }
+    let err = MiniLsm::open(&dir, options).err().unwrap().to_string();
+    assert!(err.starts_with("failed to open 2 SSTs"), "{}", err);
+    let first = err.find(&format!("SST {}:", l0_sstables[1])).unwrap();
+    let second = err.find(&format!("SST {}:", l0_sstables[3])).unwrap();
+    assert!(first < second, "{}", err);
+}
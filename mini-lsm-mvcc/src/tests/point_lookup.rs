@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, TieredCompactionOptions},
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::txn::Transaction,
+};
+
+/// The value of `key` seen by a scan of `txn`, which merges all sources instead of probing them one by one.
+fn scan_get(txn: &Arc<Transaction>, key: &[u8]) -> Option<Bytes> {
+    let iter = txn
+        .scan(Bound::Included(key), Bound::Included(key))
+        .unwrap();
+    if iter.is_valid() {
+        Some(Bytes::copy_from_slice(iter.value()))
+    } else {
+        None
+    }
+}
+
+fn key_of(i: usize) -> Vec<u8> {
+    format!("key{:03}", i).into_bytes()
+}
+
+fn check_point_lookups(options: LsmStorageOptions) {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut snapshots: Vec<(Arc<Transaction>, BTreeMap<usize, Bytes>)> = Vec::new();
+    let mut model = BTreeMap::new();
+    for round in 0..8 {
+        for _ in 0..200 {
+            let i = rng.gen_range(0..100);
+            if rng.gen_bool(0.2) {
+                storage.delete(&key_of(i)).unwrap();
+                model.remove(&i);
+            } else {
+                let value = Bytes::from(format!("value{}_{}", i, rng.gen::<u32>()));
+                storage.put(&key_of(i), &value).unwrap();
+                model.insert(i, value);
+            }
+        }
+        snapshots.push((storage.new_txn().unwrap(), model.clone()));
+        match round % 4 {
+            // leave the writes in the memtable or an immutable memtable
+            0 => {}
+            1 => storage
+                .inner
+                .force_freeze_memtable(&storage.inner.state_lock.lock())
+                .unwrap(),
+            _ => storage.force_flush().unwrap(),
+        }
+    }
+    if let CompactionOptions::NoCompaction = storage.inner.options.compaction_options {
+        storage.force_full_compaction().unwrap();
+    }
+    for _ in 0..50 {
+        let i = rng.gen_range(0..100);
+        let value = Bytes::from(format!("newest{}", i));
+        storage.put(&key_of(i), &value).unwrap();
+        model.insert(i, value);
+    }
+    snapshots.push((storage.new_txn().unwrap(), model));
+
+    for (txn, model) in &snapshots {
+        for i in 0..100 {
+            let key = key_of(i);
+            let value = txn.get(&key).unwrap();
+            assert_eq!(value, scan_get(txn, &key), "key {}", i);
+            assert_eq!(value.as_ref(), model.get(&i), "key {}", i);
+        }
+    }
+}
+
+#[test]
+fn test_point_lookup_matches_scan() {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.target_sst_size = 1024;
+    check_point_lookups(options);
+}
+
+#[test]
+fn test_point_lookup_matches_scan_tiered() {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    options.target_sst_size = 1024;
+    check_point_lookups(options);
+}
+
+#[test]
+fn test_point_lookup_stops_at_newest_version() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"old").unwrap();
+    storage.put(b"b", b"old").unwrap();
+    storage.put(b"c", b"old").unwrap();
+    storage.force_flush().unwrap();
+    let old = storage.new_txn().unwrap();
+
+    // a tombstone and an expired value in the memtable hide the older values in the SST
+    storage.delete(b"a").unwrap();
+    storage
+        .put_with_ttl(b"b", b"new", Duration::from_millis(1))
+        .unwrap();
+    storage.put(b"c", b"new").unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("new")));
+
+    // the versions newer than a snapshot are skipped
+    assert_eq!(old.get(b"a").unwrap(), Some(Bytes::from("old")));
+    assert_eq!(old.get(b"b").unwrap(), Some(Bytes::from("old")));
+    assert_eq!(old.get(b"c").unwrap(), Some(Bytes::from("old")));
+}
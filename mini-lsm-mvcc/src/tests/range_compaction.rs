@@ -0,0 +1,146 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+        TieredCompactionOptions,
+    },
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm, PrefixCompactionFilter},
+    table::SsTableIterator,
+};
+
+const TENANT_START: Bound<&[u8]> = Bound::Included(b"tenant1_");
+const TENANT_END: Bound<&[u8]> = Bound::Excluded(b"tenant2_");
+
+/// Write the keys of two tenants over several flushes, and delete the ones of `tenant1` if `delete` is set.
+fn write_tenants(storage: &MiniLsm, delete: bool) {
+    for round in 0..3 {
+        for i in 0..100 {
+            for tenant in ["tenant1", "tenant2"] {
+                storage
+                    .put(
+                        format!("{}_{:03}", tenant, i).as_bytes(),
+                        format!("value{}", round).as_bytes(),
+                    )
+                    .unwrap();
+            }
+        }
+        storage.force_flush().unwrap();
+    }
+    if delete {
+        for i in 0..100 {
+            storage
+                .delete(format!("tenant1_{:03}", i).as_bytes())
+                .unwrap();
+        }
+    }
+}
+
+/// The number of entries, including tombstones, of `tenant1` stored in the SSTs.
+fn tenant1_entries(state: &LsmStorageState) -> usize {
+    let mut count = 0;
+    for sst in state.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            if iter.key().key_ref().starts_with(b"tenant1_") {
+                count += 1;
+            }
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+fn check_tenant2(storage: &MiniLsm) {
+    for i in 0..100 {
+        assert_eq!(
+            storage.get(format!("tenant2_{:03}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value2"))
+        );
+    }
+}
+
+#[test]
+fn test_compact_range_leveled() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    write_tenants(&storage, true);
+    assert!(storage.compact_range(TENANT_START, TENANT_END, 4).is_err());
+    storage.compact_range(TENANT_START, TENANT_END, 3).unwrap();
+
+    let state = storage.inner.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert!(state.levels[0].1.is_empty());
+    assert!(state.levels[1].1.is_empty());
+    assert_eq!(tenant1_entries(&state), 0);
+    assert_eq!(storage.get(b"tenant1_042").unwrap(), None);
+    check_tenant2(&storage);
+}
+
+#[test]
+fn test_compact_range_simple_with_filter() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 2,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    write_tenants(&storage, false);
+    storage.add_compaction_filter(PrefixCompactionFilter(Bytes::from("tenant1_")));
+    storage.compact_range(TENANT_START, TENANT_END, 2).unwrap();
+
+    // the keys of tenant1 are dropped by the first step, the rest of L1 is left alone
+    let state = storage.inner.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    assert_eq!(tenant1_entries(&state), 0);
+    check_tenant2(&storage);
+}
+
+#[test]
+fn test_compact_range_tiered() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 100,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    write_tenants(&storage, true);
+    storage.compact_range(TENANT_START, TENANT_END, 0).unwrap();
+
+    let state = storage.inner.state.read().clone();
+    assert_eq!(state.levels.len(), 1);
+    assert_eq!(tenant1_entries(&state), 0);
+    assert_eq!(storage.get(b"tenant1_042").unwrap(), None);
+    check_tenant2(&storage);
+}
+
+#[test]
+fn test_compact_range_unsupported() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let err = storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 1)
+        .unwrap_err();
+    assert!(err.to_string().contains("range compaction"), "{}", err);
+}
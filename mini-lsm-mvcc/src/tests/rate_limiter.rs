@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    rate_limiter::{IoPriority, RateLimiter, RateLimiterOptions},
+};
+
+#[test]
+fn test_rate_limiter_paces_requests() {
+    let limiter = RateLimiter::new(RateLimiterOptions {
+        bytes_per_sec: 100_000,
+        auto_tune: false,
+    });
+    let start = Instant::now();
+    for _ in 0..10 {
+        limiter.request(3000, IoPriority::Low);
+    }
+    // 30KB at 100KB/s, minus at most one refill period of burst
+    assert!(start.elapsed() >= Duration::from_millis(280));
+    assert_eq!(limiter.total_bytes(IoPriority::Low), 30000);
+    assert_eq!(limiter.total_bytes(IoPriority::High), 0);
+}
+
+#[test]
+fn test_rate_limiter_high_priority_first() {
+    let limiter = Arc::new(RateLimiter::new(RateLimiterOptions {
+        bytes_per_sec: 100_000,
+        auto_tune: false,
+    }));
+    let low = {
+        let limiter = limiter.clone();
+        std::thread::spawn(move || {
+            limiter.request(20_000, IoPriority::Low);
+            Instant::now()
+        })
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    limiter.request(10_000, IoPriority::High);
+    let high_finished = Instant::now();
+    let low_finished = low.join().unwrap();
+    assert!(high_finished < low_finished);
+}
+
+#[test]
+fn test_rate_limiter_auto_tune() {
+    let limiter = RateLimiter::new(RateLimiterOptions {
+        bytes_per_sec: 1_000_000,
+        auto_tune: true,
+    });
+    limiter.tune(0.0);
+    assert_eq!(limiter.bytes_per_sec(), 50_000);
+    limiter.tune(0.5);
+    assert_eq!(limiter.bytes_per_sec(), 500_000);
+    limiter.tune(3.0);
+    assert_eq!(limiter.bytes_per_sec(), 1_000_000);
+    limiter.set_bytes_per_sec(2_000_000);
+    limiter.tune(0.5);
+    assert_eq!(limiter.bytes_per_sec(), 1_000_000);
+
+    let fixed = RateLimiter::new(RateLimiterOptions {
+        bytes_per_sec: 1_000_000,
+        auto_tune: false,
+    });
+    fixed.tune(0.0);
+    assert_eq!(fixed.bytes_per_sec(), 1_000_000);
+}
+
+#[test]
+fn test_rate_limited_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.rate_limiter = Some(RateLimiterOptions {
+        bytes_per_sec: 10 << 20,
+        auto_tune: false,
+    });
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key{:03}", i).as_bytes(),
+                    format!("value{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let limiter = storage.rate_limiter().unwrap();
+    assert!(limiter.total_bytes(IoPriority::High) > 0);
+    assert_eq!(limiter.total_bytes(IoPriority::Low), 0);
+    storage.force_full_compaction().unwrap();
+    assert!(limiter.total_bytes(IoPriority::Low) > 0);
+    assert_eq!(
+        storage.get(b"key042").unwrap().as_deref(),
+        Some(&b"value1"[..])
+    );
+}
@@ -0,0 +1,133 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        BoundarySstPartitioner, CompactionOptions, FixedPrefixSstPartitioner,
+        LeveledCompactionOptions, SstPartitioner,
+    },
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+#[test]
+fn test_sst_partitioners() {
+    let prefix = FixedPrefixSstPartitioner(8);
+    assert!(!prefix.should_partition(b"tenant1_a", b"tenant1_b"));
+    assert!(prefix.should_partition(b"tenant1_z", b"tenant2_a"));
+    assert!(prefix.should_partition(b"tenant", b"tenant1_a"));
+
+    let boundaries =
+        BoundarySstPartitioner::new(vec![Bytes::from("m"), Bytes::from("d"), Bytes::from("x")]);
+    assert!(!boundaries.should_partition(b"a", b"c"));
+    assert!(boundaries.should_partition(b"c", b"d"));
+    assert!(boundaries.should_partition(b"c", b"z"));
+    assert!(!boundaries.should_partition(b"d", b"l"));
+    assert!(!boundaries.should_partition(b"y", b"z"));
+}
+
+#[test]
+fn test_compaction_output_partitioned_by_prefix() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.set_sst_partitioner(FixedPrefixSstPartitioner(8));
+    for round in 0..2 {
+        for tenant in ["tenant1", "tenant2", "tenant3"] {
+            for i in 0..100 {
+                storage
+                    .put(
+                        format!("{}_{:03}", tenant, i).as_bytes(),
+                        format!("value{}", round).as_bytes(),
+                    )
+                    .unwrap();
+            }
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+
+    let state = storage.inner.state.read().clone();
+    let ssts = &state.levels[0].1;
+    assert_eq!(ssts.len(), 3);
+    for id in ssts {
+        let sst = &state.sstables[id];
+        assert_eq!(
+            sst.first_key().key_ref()[..8],
+            sst.last_key().key_ref()[..8]
+        );
+    }
+    assert_eq!(
+        storage.get(b"tenant2_042").unwrap(),
+        Some(Bytes::from("value1"))
+    );
+}
+
+#[test]
+fn test_compaction_output_cut_by_grandparent_overlap() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 10,
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 2,
+            base_level_size_mb: 100,
+        },
+    ));
+    // fill L2 with many small SSTs
+    options.target_sst_size = 4096;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in (0..2000).step_by(2) {
+        storage
+            .put(format!("key{:05}", i).as_bytes(), &[b'x'; 100])
+            .unwrap();
+    }
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 2)
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    // the new L1 SSTs would fit into one SST of the target size, but each overlaps L2 SSTs of at most 16KB
+    let max_overlap = 16 << 10;
+    options.target_sst_size = 1 << 20;
+    options.max_grandparent_overlap_bytes = Some(max_overlap);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in (1..2000).step_by(2) {
+        storage
+            .put(format!("key{:05}", i).as_bytes(), &[b'y'; 100])
+            .unwrap();
+    }
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, 1)
+        .unwrap();
+
+    let state = storage.inner.state.read().clone();
+    let l1 = &state.levels[0].1;
+    let l2 = &state.levels[1].1;
+    assert!(l1.len() > 1);
+    let max_l2_sst_size = l2
+        .iter()
+        .map(|id| state.sstables[id].table_size())
+        .max()
+        .unwrap();
+    for id in l1 {
+        let sst = &state.sstables[id];
+        let overlap = l2
+            .iter()
+            .map(|id| &state.sstables[id])
+            .filter(|l2_sst| {
+                l2_sst.first_key().key_ref() <= sst.last_key().key_ref()
+                    && l2_sst.last_key().key_ref() >= sst.first_key().key_ref()
+            })
+            .map(|l2_sst| l2_sst.table_size())
+            .sum::<u64>();
+        // the L2 SSTs at both ends of the L1 SST only partially overlap it
+        assert!(overlap <= max_overlap as u64 + 2 * max_l2_sst_size);
+    }
+    assert_eq!(
+        storage.get(b"key01001").unwrap().as_deref(),
+        Some(&[b'y'; 100][..])
+    );
+}
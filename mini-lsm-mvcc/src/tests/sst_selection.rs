@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::concurrent_compaction::mock_state;
+use crate::{
+    compact::{LeveledCompactionController, LeveledCompactionOptions, SstSelection},
+    key::KeySlice,
+    lsm_storage::LsmStorageState,
+    mem_table::MemTable,
+    table::{FileObject, SsTable, SsTableBuilder, TableProperties},
+};
+
+const MB: u64 = 1024 * 1024;
+
+fn controller(max_levels: usize, sst_selection: SstSelection) -> LeveledCompactionController {
+    LeveledCompactionController::with_sst_selection(
+        LeveledCompactionOptions {
+            level_size_multiplier: 10,
+            level0_file_num_compaction_trigger: 4,
+            max_levels,
+            base_level_size_mb: 1,
+        },
+        sst_selection.build(),
+    )
+}
+
+/// Build an SST with keys `prefix000..prefix099`, every `delete_every`-th of them deleted.
+fn build_sst(dir: &Path, id: usize, prefix: &str, delete_every: usize) -> SsTable {
+    let mut builder = SsTableBuilder::new(128);
+    for i in 0..100 {
+        let key = format!("{}{:03}", prefix, i);
+        let value = if i % delete_every == 0 { "" } else { "value" };
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(key.as_bytes()),
+            value.as_bytes(),
+        );
+    }
+    builder
+        .build(id, None, dir.join(format!("{}.sst", id)))
+        .unwrap()
+}
+
+#[test]
+fn test_table_properties() {
+    let dir = tempdir().unwrap();
+    let sst = build_sst(dir.path(), 1, "key", 4);
+    assert!(sst.properties().creation_time > 0);
+    let expected = TableProperties {
+        num_entries: 100,
+        num_tombstones: 25,
+        creation_time: sst.properties().creation_time,
+    };
+    assert_eq!(sst.properties(), &expected);
+    let reopened = SsTable::open(
+        1,
+        None,
+        FileObject::open(&dir.path().join("1.sst")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(reopened.properties(), &expected);
+}
+
+#[test]
+fn test_most_tombstones_selection() {
+    let dir = tempdir().unwrap();
+    let ssts = [
+        build_sst(dir.path(), 1, "a", 10),
+        build_sst(dir.path(), 2, "b", 2),
+        build_sst(dir.path(), 3, "c", 5),
+    ];
+    let snapshot = LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables: Vec::new(),
+        levels: vec![(1, vec![1, 2, 3]), (2, Vec::new())],
+        sstables: ssts
+            .into_iter()
+            .map(|sst| (sst.sst_id(), Arc::new(sst)))
+            .collect::<HashMap<_, _>>(),
+    };
+    let task = controller(2, SstSelection::Oldest)
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    let task = controller(2, SstSelection::MostTombstones)
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    assert_eq!(task.upper_level, Some(1));
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+}
+
+#[test]
+fn test_min_overlap_selection() {
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(1, "a", "c", MB), (2, "d", "f", MB), (3, "g", "i", MB)],
+            &[
+                (4, "a", "b", 4 * MB),
+                (5, "d", "e", MB),
+                (6, "g", "h", 2 * MB),
+            ],
+        ],
+    );
+    let task = controller(2, SstSelection::MinOverlap)
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert_eq!(task.lower_level_sst_ids, vec![5]);
+}
+
+#[test]
+fn test_round_robin_selection() {
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(3, "a", "c", MB), (1, "d", "f", MB), (2, "g", "i", MB)],
+            &[(4, "a", "i", 4 * MB)],
+        ],
+    );
+    let oldest = controller(2, SstSelection::Oldest);
+    let round_robin = controller(2, SstSelection::RoundRobin);
+    let mut picked = Vec::new();
+    for _ in 0..4 {
+        assert_eq!(
+            oldest
+                .generate_compaction_task(&snapshot)
+                .unwrap()
+                .upper_level_sst_ids,
+            vec![1]
+        );
+        picked.extend(
+            round_robin
+                .generate_compaction_task(&snapshot)
+                .unwrap()
+                .upper_level_sst_ids,
+        );
+    }
+    assert_eq!(picked, vec![3, 1, 2, 3]);
+}
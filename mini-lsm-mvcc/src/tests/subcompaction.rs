@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    tests::harness::check_lsm_iter_result_by_key,
+};
+
+/// Write overlapping versions and deletes over several L0 SSTs, then run a full compaction.
+fn full_compaction(max_subcompactions: usize) -> (tempfile::TempDir, std::sync::Arc<MiniLsm>) {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.max_subcompactions = max_subcompactions;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..4 {
+        for i in (round..1000).step_by(4) {
+            let key = format!("key{:04}", i);
+            storage
+                .put(key.as_bytes(), format!("value{}", round).as_bytes())
+                .unwrap();
+            if i % 10 == 0 {
+                storage.delete(key.as_bytes()).unwrap();
+            }
+        }
+        storage.force_flush().unwrap();
+    }
+    storage.force_full_compaction().unwrap();
+    (dir, storage)
+}
+
+#[test]
+fn test_subcompactions() {
+    let (_dir, storage) = full_compaction(4);
+    let state = storage.inner.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    let (_dir, single) = full_compaction(1);
+    assert_eq!(single.inner.state.read().levels[0].1.len(), 1);
+    // each range has its own output, and the outputs are in key order without overlapping
+    let ssts = state.levels[0]
+        .1
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(ssts.len(), 4);
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key().key_ref() < pair[1].first_key().key_ref());
+    }
+
+    let expected = (0..1000)
+        .filter(|i| i % 10 != 0)
+        .map(|i| {
+            (
+                Bytes::from(format!("key{:04}", i)),
+                Bytes::from(format!("value{}", i % 4)),
+            )
+        })
+        .collect::<Vec<_>>();
+    check_lsm_iter_result_by_key(
+        &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+fn sst_files(path: &Path) -> BTreeSet<String> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".sst"))
+        .collect()
+}
+
+#[test]
+fn test_failed_subcompactions_delete_their_output() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_size = 256;
+    options.target_sst_size = 1024;
+    options.max_subcompactions = 2;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..2 {
+        for i in 0..1000 {
+            storage
+                .put(
+                    format!("key{:04}", i).as_bytes(),
+                    format!("value{}", round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+        while !storage.inner.state.read().imm_memtables.is_empty() {
+            storage.force_flush().unwrap();
+        }
+    }
+
+    // the last block of the keys is corrupted, so each subcompaction writes a few SSTs before one of them fails
+    let sst = {
+        let state = storage.inner.state.read();
+        state
+            .l0_sstables
+            .iter()
+            .map(|id| state.sstables[id].clone())
+            .max_by(|a, b| a.last_key().cmp(b.last_key()))
+            .unwrap()
+    };
+    let path = storage.inner.path_of_sst(sst.sst_id());
+    let mut data = std::fs::read(&path).unwrap();
+    data[sst.block_meta.last().unwrap().offset] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+    let files = sst_files(dir.path());
+
+    let err = storage.force_full_compaction().unwrap_err();
+    assert!(err.to_string().contains("checksum"), "{}", err);
+    assert_eq!(sst_files(dir.path()), files);
+}
@@ -0,0 +1,90 @@
+use super::concurrent_compaction::mock_state;
+use crate::compact::{TieredCompactionController, TieredCompactionOptions};
+
+const MB: u64 = 1024 * 1024;
+
+fn controller(
+    num_tiers: usize,
+    max_size_amplification_percent: usize,
+    max_merge_width: Option<usize>,
+) -> TieredCompactionController {
+    TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers,
+        max_size_amplification_percent,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width,
+    })
+}
+
+#[test]
+fn test_tiered_sizes_in_bytes() {
+    // many small SSTs in the newest tier are no larger than one SST
+    let small_ssts = (1..=10)
+        .map(|id| (id, "a", "z", MB / 10))
+        .collect::<Vec<_>>();
+    let snapshot = mock_state(
+        &[],
+        &[
+            &small_ssts,
+            &[(11, "a", "z", MB)],
+            &[(12, "a", "z", 20 * MB)],
+        ],
+    );
+    let task = controller(3, 200, None)
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    // not a full compaction for space amplification, which 12 upper SSTs over 1 bottom SST would be
+    assert_eq!(task.tiers, snapshot.levels[..2].to_vec());
+    assert!(!task.bottom_tier_included);
+}
+
+#[test]
+fn test_tiered_merge_subset_by_size_ratio() {
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(1, "a", "z", 5 * MB)],
+            &[(2, "a", "z", MB)],
+            &[(3, "a", "z", MB)],
+            &[(4, "a", "z", 30 * MB)],
+        ],
+    );
+    let task = controller(4, 200, Some(2))
+        .generate_compaction_task(&snapshot)
+        .unwrap();
+    assert_eq!(task.tiers, snapshot.levels[1..3].to_vec());
+    assert!(!task.bottom_tier_included);
+}
+
+#[test]
+fn test_tiered_reduce_sorted_runs_cheapest() {
+    let snapshot = mock_state(
+        &[],
+        &[
+            &[(1, "a", "z", 3 * MB)],
+            &[(2, "a", "z", 2 * MB)],
+            &[(3, "a", "z", MB)],
+            &[(4, "a", "z", MB)],
+        ],
+    );
+    let controller = controller(4, 1000, Some(2));
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.tiers, snapshot.levels[2..].to_vec());
+    assert!(task.bottom_tier_included);
+    let (snapshot, removed) = controller.apply_compaction_result(&snapshot, &task, &[5]);
+    assert_eq!(removed, vec![3, 4]);
+    assert_eq!(
+        snapshot.levels,
+        vec![(1, vec![1]), (2, vec![2]), (5, vec![5])]
+    );
+}
+
+#[test]
+fn test_tiered_single_tier() {
+    let controller = controller(1, 0, Some(4));
+    let snapshot = mock_state(&[], &[]);
+    assert!(controller.generate_compaction_task(&snapshot).is_none());
+    let snapshot = mock_state(&[], &[&[(1, "a", "z", MB)]]);
+    assert!(controller.generate_compaction_task(&snapshot).is_none());
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions},
+    lsm_storage::{LsmStorageOptions, LsmStorageState, MiniLsm},
+};
+
+fn sst_ids(state: &LsmStorageState) -> BTreeSet<usize> {
+    state
+        .l0_sstables
+        .iter()
+        .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        .copied()
+        .collect()
+}
+
+/// Flush a memtable with the keys `key{begin..end}`, and wait for the compaction of L0.
+fn flush_keys(storage: &MiniLsm, keys: std::ops::Range<usize>) {
+    for i in keys {
+        storage
+            .put(format!("key{:04}", i).as_bytes(), b"value")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    for _ in 0..100 {
+        if storage.inner.state.read().l0_sstables.is_empty() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("L0 is not compacted");
+}
+
+#[test]
+fn test_trivial_move_leveled() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 1,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut flushed = BTreeSet::new();
+    for round in 0..5 {
+        flush_keys(&storage, round * 100..(round + 1) * 100);
+        flushed.insert(*storage.inner.state.read().levels[2].1.last().unwrap());
+    }
+    // sequential keys never overlap, so every flushed SST is moved into the bottom level as it is
+    let state = storage.inner.state.read().clone();
+    assert_eq!(sst_ids(&state), flushed);
+    assert_eq!(state.levels[2].1.len(), 5);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels, state.levels);
+    for i in 0..500 {
+        assert_eq!(
+            storage.get(format!("key{:04}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
+
+#[test]
+fn test_trivial_move_simple_leveled() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 1,
+            max_levels: 2,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    flush_keys(&storage, 0..100);
+    let moved = sst_ids(&storage.inner.state.read());
+    assert_eq!(moved.len(), 1);
+
+    // overlapping keys have to be merged with the existing SST
+    flush_keys(&storage, 50..150);
+    let state = storage.inner.state.read().clone();
+    assert!(sst_ids(&state).is_disjoint(&moved));
+    for i in 0..150 {
+        assert_eq!(
+            storage.get(format!("key{:04}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
+
+#[test]
+fn test_trivial_move_l0_ssts_sorted_by_key() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 3,
+            max_levels: 1,
+        },
+    ));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // L0 holds the SSTs from the newest to the oldest, i.e., with decreasing keys
+    storage.pause_background_work();
+    for round in 0..3 {
+        for i in round * 100..(round + 1) * 100 {
+            storage
+                .put(format!("key{:04}", i).as_bytes(), b"value")
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    let mut flushed = storage.inner.state.read().l0_sstables.clone();
+    assert_eq!(flushed.len(), 3);
+    flushed.reverse();
+    storage.continue_background_work().unwrap();
+    for _ in 0..100 {
+        if storage.inner.state.read().l0_sstables.is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let state = storage.inner.state.read().clone();
+    assert_eq!(state.levels[0].1, flushed);
+    assert!(state.levels[0].1.windows(2).all(|ssts| {
+        state.sstables[&ssts[0]].last_key() < state.sstables[&ssts[1]].first_key()
+    }));
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.inner.state.read().levels, state.levels);
+    for i in 0..300 {
+        assert_eq!(
+            storage.get(format!("key{:04}", i).as_bytes()).unwrap(),
+            Some(Bytes::from("value"))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockFormat, BlockIterator},
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+};
+
+use super::harness::{
+    check_iter_result_by_key, check_lsm_iter_result_by_key, construct_merge_iterator_over_storage,
+};
+
+#[test]
+fn test_ttl_expiry_on_read() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .put_with_ttl(b"b", b"1", Duration::from_secs(3600))
+        .unwrap();
+    storage.put_with_ttl(b"c", b"1", Duration::ZERO).unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::PutWithTtl(b"d", b"1", Duration::ZERO),
+            WriteBatchRecord::PutWithTtl(b"e", b"1", Duration::from_secs(3600)),
+        ])
+        .unwrap();
+    let check = |storage: &MiniLsm| {
+        assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+        assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+        assert_eq!(storage.get(b"c").unwrap(), None);
+        assert_eq!(storage.get(b"d").unwrap(), None);
+        assert_eq!(storage.get(b"e").unwrap(), Some(Bytes::from("1")));
+        check_lsm_iter_result_by_key(
+            &mut storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("a"), Bytes::from("1")),
+                (Bytes::from("b"), Bytes::from("1")),
+                (Bytes::from("e"), Bytes::from("1")),
+            ],
+        );
+    };
+    check(&storage);
+    storage.force_flush().unwrap();
+    check(&storage);
+    // a plain put removes the expiry of the key
+    storage.put(b"c", b"2").unwrap();
+    assert_eq!(storage.get(b"c").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_ttl_expiry_in_txn() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.serializable = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put_with_ttl(b"a", b"1", Duration::ZERO);
+    txn.put_with_ttl(b"b", b"1", Duration::from_secs(3600));
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_ttl_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage
+        .put_with_ttl(b"a", b"1", Duration::from_secs(3600))
+        .unwrap();
+    storage.put_with_ttl(b"b", b"1", Duration::ZERO).unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+}
+
+#[test]
+fn test_ttl_expired_dropped_by_compaction() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.force_flush().unwrap();
+    let snapshot = storage.new_txn().unwrap();
+    storage.put_with_ttl(b"a", b"2", Duration::ZERO).unwrap();
+    storage
+        .put_with_ttl(b"b", b"2", Duration::from_secs(3600))
+        .unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    // expired versions above the watermark are retained
+    let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("2")),
+            (Bytes::from("a"), Bytes::from("1")),
+            (Bytes::from("b"), Bytes::from("2")),
+            (Bytes::from("b"), Bytes::from("1")),
+        ],
+    );
+    assert_eq!(snapshot.get(b"a").unwrap(), Some(Bytes::from("1")));
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    drop(snapshot);
+
+    storage.force_full_compaction().unwrap();
+    let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
+    check_iter_result_by_key(&mut iter, vec![(Bytes::from("b"), Bytes::from("2"))]);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from("2")));
+}
+
+#[test]
+fn test_large_values_through_wal_flush_and_reopen() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    // values of 32KB and more used to collide with the expiry flag
+    let large = Bytes::from(vec![b'x'; 40 << 10]);
+    {
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        storage.put(b"large", &large).unwrap();
+        storage
+            .put_with_ttl(b"large_ttl", &large, Duration::from_secs(3600))
+            .unwrap();
+        storage.put(b"small", b"1").unwrap();
+        assert!(storage.put(b"too_large", &vec![b'x'; 64 << 10]).is_err());
+        assert_eq!(storage.get(b"too_large").unwrap(), None);
+        storage.inner.sync().unwrap();
+    }
+
+    // replayed from the WAL
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"large").unwrap(), Some(large.clone()));
+    assert_eq!(storage.get(b"large_ttl").unwrap(), Some(large.clone()));
+    assert_eq!(storage.get(b"small").unwrap(), Some(Bytes::from("1")));
+    storage.force_flush().unwrap();
+    assert_eq!(storage.get(b"large").unwrap(), Some(large.clone()));
+    storage.close().unwrap();
+    drop(storage);
+
+    // read from the flushed SST
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(!storage.inner.state.read().l0_sstables.is_empty());
+    assert_eq!(storage.get(b"large").unwrap(), Some(large.clone()));
+    assert_eq!(storage.get(b"large_ttl").unwrap(), Some(large));
+    assert_eq!(storage.get(b"small").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_decode_legacy_block_with_large_value() {
+    // an entry as SSTs without a format version encode it: no flags, and a value length using all 16 bits
+    let value = vec![b'x'; 40 << 10];
+    let mut data = Vec::new();
+    data.put_u16(0);
+    data.put_u16(3);
+    data.put_slice(b"key");
+    data.put_u64(1);
+    data.put_u16(value.len() as u16);
+    data.put_slice(&value);
+    data.put_u16(0);
+    data.put_u16(1);
+    let block = Arc::new(Block::decode_with_format(&data, BlockFormat::Legacy));
+    let iter = BlockIterator::create_and_seek_to_first(block);
+    assert_eq!(iter.key().key_ref(), b"key");
+    assert_eq!(iter.value(), &value[..]);
+    assert_eq!(iter.expire_at(), None);
+}
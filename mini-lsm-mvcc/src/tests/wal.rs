@@ -0,0 +1,328 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    wal::{WalCorruption, WalCorruptionKind, WalRecoveryMode},
+};
+
+fn files_with_extension(dir: impl AsRef<Path>, extension: &str) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == extension)
+        })
+        .count()
+}
+
+#[test]
+fn test_wal_segments_recycled() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_segment_size = 4096;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..20 {
+        for i in 0..100 {
+            storage
+                .put(
+                    format!("key{:03}", i).as_bytes(),
+                    format!("value{:03}@{}", i, round).as_bytes(),
+                )
+                .unwrap();
+        }
+        storage.force_flush().unwrap();
+    }
+    // every memtable spans several segments, but flushed segments are reused instead of growing the log
+    let num_segments = storage.inner.wal.as_ref().unwrap().num_segments();
+    assert!(num_segments <= 4);
+    assert_eq!(files_with_extension(&dir, "log"), num_segments);
+    assert_eq!(files_with_extension(&dir, "wal"), 0);
+}
+
+#[test]
+fn test_wal_recover_from_recycled_segments() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_segment_size = 4096;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for i in 0..100 {
+        storage
+            .put(format!("key{:03}", i).as_bytes(), b"flushed")
+            .unwrap();
+    }
+    storage.force_flush().unwrap();
+    // overwrite the recycled segments, leaving records of the flushed memtable behind the new ones
+    for i in 0..50 {
+        storage
+            .put(format!("key{:03}", i).as_bytes(), b"unflushed")
+            .unwrap();
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for i in 0..100 {
+        let expected = if i < 50 { "unflushed" } else { "flushed" };
+        assert_eq!(
+            storage.get(format!("key{:03}", i).as_bytes()).unwrap(),
+            Some(Bytes::from(expected))
+        );
+    }
+}
+
+#[test]
+fn test_wal_dir() {
+    let dir = tempdir().unwrap();
+    let wal_dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_dir = Some(wal_dir.path().to_path_buf());
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    assert_eq!(files_with_extension(&dir, "log"), 0);
+    assert_eq!(files_with_extension(&wal_dir, "log"), 1);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+/// Write `a`, `b` and `c` as three WAL records of 39 bytes each, starting at offset 12 of the first segment.
+fn create_wal_with_three_records(dir: impl AsRef<Path>) -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    storage.close().unwrap();
+    options
+}
+
+/// Flip the checksum of the record at `offset` of the first segment.
+fn corrupt_record(dir: impl AsRef<Path>, offset: usize) {
+    let path = dir.as_ref().join("00000.log");
+    let mut data = std::fs::read(&path).unwrap();
+    data[offset + 38] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+}
+
+fn get_keys(storage: &MiniLsm) -> Vec<Option<Bytes>> {
+    [b"a", b"b", b"c"]
+        .iter()
+        .map(|key| storage.get(*key).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_wal_recovery_torn_tail() {
+    let dir = tempdir().unwrap();
+    let mut options = create_wal_with_three_records(&dir);
+    corrupt_record(&dir, 90);
+
+    options.wal_recovery_mode = WalRecoveryMode::AbsoluteConsistency;
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    options.wal_recovery_mode = WalRecoveryMode::TolerateCorruptedTailRecords;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(
+        storage.wal_recovery_report().corruptions,
+        vec![WalCorruption {
+            segment_seq: 1,
+            legacy_memtable_id: None,
+            offset: 90,
+            kind: WalCorruptionKind::ChecksumMismatch,
+        }]
+    );
+    let one = Some(Bytes::from("1"));
+    assert_eq!(get_keys(&storage), vec![one.clone(), one.clone(), None]);
+    storage.close().unwrap();
+    drop(storage);
+
+    // the torn record was truncated, so the log is consistent again
+    options.wal_recovery_mode = WalRecoveryMode::AbsoluteConsistency;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.wal_recovery_report().is_clean());
+    assert_eq!(get_keys(&storage), vec![one.clone(), one, None]);
+}
+
+#[test]
+fn test_wal_recovery_corrupted_middle() {
+    let dir = tempdir().unwrap();
+    let mut options = create_wal_with_three_records(&dir);
+    corrupt_record(&dir, 51);
+    let one = Some(Bytes::from("1"));
+
+    options.wal_recovery_mode = WalRecoveryMode::TolerateCorruptedTailRecords;
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    options.wal_recovery_mode = WalRecoveryMode::SkipAnyCorruptedRecords;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let report = storage.wal_recovery_report();
+    assert_eq!(report.corruptions.len(), 1);
+    assert_eq!(report.corruptions[0].offset, 51);
+    assert_eq!(report.dropped_records, 0);
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, one.clone()]);
+    storage.close().unwrap();
+    drop(storage);
+
+    options.wal_recovery_mode = WalRecoveryMode::PointInTime;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let report = storage.wal_recovery_report();
+    assert_eq!(report.corruptions.len(), 1);
+    assert_eq!(report.dropped_records, 1);
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, None]);
+    storage.close().unwrap();
+    drop(storage);
+
+    options.wal_recovery_mode = WalRecoveryMode::AbsoluteConsistency;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.wal_recovery_report().is_clean());
+    assert_eq!(get_keys(&storage), vec![one, None, None]);
+}
+
+/// Encode `batch` as a record of a per-memtable WAL file from before the WAL was segmented.
+fn legacy_wal_record(batch: &[(&[u8], u64, &[u8])]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for (key, ts, value) in batch {
+        buf.extend((key.len() as u16).to_be_bytes());
+        buf.extend(*key);
+        buf.extend(ts.to_be_bytes());
+        buf.extend((value.len() as u16).to_be_bytes());
+        buf.extend(*value);
+    }
+    let mut record = (buf.len() as u32).to_be_bytes().to_vec();
+    record.extend(crc32fast::hash(&buf).to_be_bytes());
+    record.splice(4..4, buf);
+    record
+}
+
+/// Create a database from before the WAL was segmented, which has a WAL file for each of the unflushed memtables 1 and
+/// 2.
+fn create_legacy_db(dir: impl AsRef<Path>, wal1: &[u8], wal2: &[u8]) {
+    let dir = dir.as_ref();
+    let mut legacy_manifest = Vec::new();
+    for record in [r#"{"NewMemtable":1}"#, r#"{"NewMemtable":2}"#] {
+        legacy_manifest.extend((record.len() as u64).to_be_bytes());
+        legacy_manifest.extend(record.as_bytes());
+        legacy_manifest.extend(crc32fast::hash(record.as_bytes()).to_be_bytes());
+    }
+    std::fs::write(dir.join("MANIFEST"), legacy_manifest).unwrap();
+    std::fs::write(dir.join("00001.wal"), wal1).unwrap();
+    std::fs::write(dir.join("00002.wal"), wal2).unwrap();
+}
+
+#[test]
+fn test_wal_migrate_legacy_files() {
+    let dir = tempdir().unwrap();
+    let mut wal = legacy_wal_record(&[(b"a", 1, b"1"), (b"b", 1, b"1")]);
+    wal.extend(legacy_wal_record(&[(b"b", 2, b"")]));
+    create_legacy_db(&dir, &wal, &legacy_wal_record(&[(b"c", 3, b"1")]));
+
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let one = Some(Bytes::from("1"));
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, one.clone()]);
+    assert_eq!(files_with_extension(&dir, "wal"), 0);
+    assert!(!dir.path().join("lost").exists());
+    storage.put(b"d", b"1").unwrap();
+    drop(storage);
+
+    // the records now live in the segmented WAL
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, one.clone()]);
+    assert_eq!(storage.get(b"d").unwrap(), one);
+    storage.force_flush().unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from("1")));
+}
+
+#[test]
+fn test_wal_recover_legacy_torn_tail() {
+    let mut wal2 = legacy_wal_record(&[(b"c", 2, b"1")]);
+    let torn_offset = wal2.len();
+    let mut torn = legacy_wal_record(&[(b"d", 3, b"1")]);
+    torn.truncate(torn.len() - 3);
+    wal2.extend(torn);
+    let one = Some(Bytes::from("1"));
+
+    let dir = tempdir().unwrap();
+    create_legacy_db(&dir, &legacy_wal_record(&[(b"a", 1, b"1")]), &wal2);
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    options.wal_recovery_mode = WalRecoveryMode::AbsoluteConsistency;
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    options.wal_recovery_mode = WalRecoveryMode::TolerateCorruptedTailRecords;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(
+        storage.wal_recovery_report().corruptions,
+        vec![WalCorruption {
+            segment_seq: 0,
+            legacy_memtable_id: Some(2),
+            offset: torn_offset,
+            kind: WalCorruptionKind::Incomplete,
+        }]
+    );
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, one.clone()]);
+    assert_eq!(storage.get(b"d").unwrap(), None);
+    assert_eq!(files_with_extension(&dir, "wal"), 0);
+    drop(storage);
+
+    // the intact records were migrated
+    options.wal_recovery_mode = WalRecoveryMode::AbsoluteConsistency;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert!(storage.wal_recovery_report().is_clean());
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, one]);
+}
+
+#[test]
+fn test_wal_recover_legacy_corrupted_middle() {
+    let mut wal1 = legacy_wal_record(&[(b"a", 1, b"1")]);
+    let corrupted_offset = wal1.len();
+    let mut corrupted = legacy_wal_record(&[(b"b", 2, b"1")]);
+    *corrupted.last_mut().unwrap() ^= 0xff;
+    wal1.extend(corrupted);
+    let wal2 = legacy_wal_record(&[(b"c", 3, b"1")]);
+    let one = Some(Bytes::from("1"));
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+
+    // the damaged record is not at the end of the log
+    let dir = tempdir().unwrap();
+    create_legacy_db(&dir, &wal1, &wal2);
+    options.wal_recovery_mode = WalRecoveryMode::TolerateCorruptedTailRecords;
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    options.wal_recovery_mode = WalRecoveryMode::SkipAnyCorruptedRecords;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let report = storage.wal_recovery_report();
+    assert_eq!(report.corruptions.len(), 1);
+    assert_eq!(report.corruptions[0].legacy_memtable_id, Some(1));
+    assert_eq!(report.corruptions[0].offset, corrupted_offset);
+    assert_eq!(report.dropped_records, 0);
+    assert_eq!(get_keys(&storage), vec![one.clone(), None, one.clone()]);
+    drop(storage);
+
+    let dir = tempdir().unwrap();
+    create_legacy_db(&dir, &wal1, &wal2);
+    options.wal_recovery_mode = WalRecoveryMode::PointInTime;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let report = storage.wal_recovery_report();
+    assert_eq!(report.corruptions.len(), 1);
+    assert_eq!(report.dropped_records, 1);
+    assert_eq!(get_keys(&storage), vec![one, None, None]);
+}
@@ -3,7 +3,7 @@ use tempfile::tempdir;
 
 use crate::{
     compact::CompactionOptions,
-    lsm_storage::{CompactionFilter, LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    lsm_storage::{LsmStorageOptions, MiniLsm, PrefixCompactionFilter, WriteBatchRecord},
 };
 
 use super::harness::{check_iter_result_by_key, construct_merge_iterator_over_storage};
@@ -36,7 +36,7 @@ fn test_task3_mvcc_compaction() {
         ])
         .unwrap();
     storage.force_flush().unwrap();
-    storage.add_compaction_filter(CompactionFilter::Prefix(Bytes::from("table2_")));
+    storage.add_compaction_filter(PrefixCompactionFilter(Bytes::from("table2_")));
     storage.force_full_compaction().unwrap();
 
     let mut iter = construct_merge_iterator_over_storage(&storage.inner.state.read());
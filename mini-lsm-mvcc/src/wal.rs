@@ -1,114 +1,682 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::{File, OpenOptions};
-use std::hash::Hasher;
-use std::io::{BufWriter, Read, Write};
-use std::path::Path;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
 use bytes::{Buf, BufMut, Bytes};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 
+use crate::block::MAX_VALUE_SIZE;
 use crate::key::{KeyBytes, KeySlice};
 
+/// Set in the flags byte following the value of a record when the expiry timestamp (u64) follows the flags.
+const ENTRY_EXPIRES: u8 = 1;
+
+/// Segment header: seq (u64) | checksum (u32).
+const SEGMENT_HEADER_SIZE: usize = 12;
+/// Record header: seq (u64) | memtable id (u64) | batch size (u32), followed by the batch and a u32 checksum.
+const RECORD_HEADER_SIZE: usize = 20;
+
+/// The key-value pairs recovered for a memtable.
+pub type WalRecords = SkipMap<KeyBytes, (Bytes, Option<u64>)>;
+
+/// The key-value pairs of a record of a legacy WAL file.
+pub type LegacyWalBatch = Vec<(KeyBytes, Bytes)>;
+
+/// How recovery treats incomplete or corrupted WAL records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalRecoveryMode {
+    /// Fail on any damaged record.
+    AbsoluteConsistency,
+    /// Truncate a damaged record at the end of the log, which is what a torn write looks like. Fail on damage
+    /// anywhere else.
+    TolerateCorruptedTailRecords,
+    /// Stop at the first damaged record and drop everything after it, recovering a consistent prefix of the log.
+    PointInTime,
+    /// Skip damaged records and replay all intact ones.
+    SkipAnyCorruptedRecords,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalCorruptionKind {
+    /// The record extends past the end of the segment file.
+    Incomplete,
+    ChecksumMismatch,
+}
+
+/// A damaged WAL record that was not replayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalCorruption {
+    /// The sequence number of the segment holding the record, or 0 for a legacy WAL file.
+    pub segment_seq: u64,
+    /// The memtable whose legacy WAL file from before the WAL was segmented holds the record.
+    pub legacy_memtable_id: Option<usize>,
+    /// The offset of the record in the segment or legacy WAL file.
+    pub offset: usize,
+    pub kind: WalCorruptionKind,
+}
+
+impl std::fmt::Display for WalCorruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            WalCorruptionKind::Incomplete => "incomplete WAL record",
+            WalCorruptionKind::ChecksumMismatch => "WAL checksum mismatch",
+        };
+        match self.legacy_memtable_id {
+            Some(id) => write!(
+                f,
+                "{} in the legacy WAL file of memtable {} at offset {}",
+                kind, id, self.offset
+            ),
+            None => write!(
+                f,
+                "{} in segment {} at offset {}",
+                kind, self.segment_seq, self.offset
+            ),
+        }
+    }
+}
+
+/// What WAL recovery dropped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalRecoveryReport {
+    /// Damaged records that were truncated or skipped.
+    pub corruptions: Vec<WalCorruption>,
+    /// Intact records dropped because point-in-time recovery stopped at an earlier damaged record.
+    pub dropped_records: usize,
+}
+
+impl WalRecoveryReport {
+    /// Whether all records in the WAL were replayed.
+    pub fn is_clean(&self) -> bool {
+        self.corruptions.is_empty() && self.dropped_records == 0
+    }
+}
+
+enum DecodedRecord<'a> {
+    /// No record of the segment starts at the offset.
+    End,
+    Valid {
+        memtable_id: usize,
+        batch: &'a [u8],
+        len: usize,
+    },
+    Damaged {
+        kind: WalCorruptionKind,
+        /// The length of the record, if it fits in the segment file.
+        len: Option<usize>,
+    },
+}
+
+struct Segment {
+    /// The index of the file holding this segment.
+    slot: usize,
+    seq: u64,
+    /// The smallest and largest memtable ids with records in this segment.
+    memtable_ids: Option<(usize, usize)>,
+}
+
+impl Segment {
+    fn add_memtable_id(&mut self, id: usize) {
+        self.memtable_ids = Some(match self.memtable_ids {
+            Some((first, last)) => (first.min(id), last.max(id)),
+            None => (id, id),
+        });
+    }
+}
+
+struct WalInner {
+    file: BufWriter<File>,
+    /// The segment currently being appended to.
+    active: Segment,
+    /// Bytes written to the active segment, including its header.
+    offset: usize,
+    /// Segments that still hold records of unflushed memtables, oldest first.
+    sealed: VecDeque<Segment>,
+    /// Slots whose segments can be overwritten.
+    free: Vec<usize>,
+    next_slot: usize,
+}
+
+/// The write-ahead log shared by all memtables.
+///
+/// The log is a sequence of segments, each stored in a file preallocated to `segment_size` bytes. A segment starts
+/// with a header holding its sequence number, and every record repeats that number so that stale records left over
+/// from an earlier use of a recycled file are recognized as the end of the segment. Each record is tagged with the id
+/// of the memtable it belongs to, and a segment tracks the range of memtable ids it covers. Once all of them have been
+/// flushed, the file goes back to a free list and is reused for a later segment instead of being deleted.
 pub struct Wal {
-    file: Arc<Mutex<BufWriter<File>>>,
+    dir: PathBuf,
+    segment_size: usize,
+    inner: Arc<Mutex<WalInner>>,
 }
 
 impl Wal {
-    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+    fn path_of_segment(dir: &Path, slot: usize) -> PathBuf {
+        dir.join(format!("{:05}.log", slot))
+    }
+
+    /// Open the file of `slot` for a new segment `seq`, creating and preallocating it if needed.
+    fn open_segment(dir: &Path, segment_size: usize, slot: usize, seq: u64) -> Result<File> {
+        let path = Self::path_of_segment(dir, slot);
+        let created = !path.exists();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .context("failed to open WAL segment")?;
+        if created {
+            file.set_len(segment_size as u64)?;
+            file.sync_all()?;
+            File::open(dir)?.sync_all()?;
+        }
+        let mut header = Vec::with_capacity(SEGMENT_HEADER_SIZE);
+        header.put_u64(seq);
+        header.put_u32(crc32fast::hash(&seq.to_be_bytes()));
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+        Ok(file)
+    }
+
+    pub fn create(dir: impl AsRef<Path>, segment_size: usize) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).context("failed to create WAL dir")?;
+        let file = Self::open_segment(dir, segment_size, 0, 1)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+            dir: dir.to_path_buf(),
+            segment_size,
+            inner: Arc::new(Mutex::new(WalInner {
+                file: BufWriter::new(file),
+                active: Segment {
+                    slot: 0,
+                    seq: 1,
+                    memtable_ids: None,
+                },
+                offset: SEGMENT_HEADER_SIZE,
+                sealed: VecDeque::new(),
+                free: Vec::new(),
+                next_slot: 1,
+            })),
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
-        let path = path.as_ref();
+    /// Decode the record starting at `offset` of a segment with sequence number `seq`.
+    fn decode_record(buf: &[u8], offset: usize, seq: u64) -> DecodedRecord<'_> {
+        let mut rbuf = &buf[offset.min(buf.len())..];
+        // A record written for a previous segment in this file, or the zeroed space after the last record, ends the
+        // segment.
+        if rbuf.remaining() < RECORD_HEADER_SIZE || (&rbuf[..8]).get_u64() != seq {
+            return DecodedRecord::End;
+        }
+        rbuf.advance(8);
+        let memtable_id = rbuf.get_u64() as usize;
+        let batch_size = rbuf.get_u32() as usize;
+        if rbuf.remaining() < batch_size + 4 {
+            return DecodedRecord::Damaged {
+                kind: WalCorruptionKind::Incomplete,
+                len: None,
+            };
+        }
+        let batch = &rbuf[..batch_size];
+        rbuf.advance(batch_size);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&(memtable_id as u64).to_be_bytes());
+        hasher.update(batch);
+        let len = RECORD_HEADER_SIZE + batch_size + 4;
+        if rbuf.get_u32() != hasher.finalize() {
+            return DecodedRecord::Damaged {
+                kind: WalCorruptionKind::ChecksumMismatch,
+                len: Some(len),
+            };
+        }
+        DecodedRecord::Valid {
+            memtable_id,
+            batch,
+            len,
+        }
+    }
+
+    /// Count the intact records from `offset` to the end of a segment.
+    fn count_records(buf: &[u8], mut offset: usize, seq: u64) -> usize {
+        let mut cnt = 0;
+        while let DecodedRecord::Valid { len, .. } = Self::decode_record(buf, offset, seq) {
+            offset += len;
+            cnt += 1;
+        }
+        cnt
+    }
+
+    fn replay_batch(mut batch_buf: &[u8], skiplist: &WalRecords) {
+        while batch_buf.has_remaining() {
+            let key_len = batch_buf.get_u16() as usize;
+            let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
+            batch_buf.advance(key_len);
+            let ts = batch_buf.get_u64();
+            let value_len = batch_buf.get_u16() as usize;
+            let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
+            batch_buf.advance(value_len);
+            let expire_at = if batch_buf.get_u8() & ENTRY_EXPIRES != 0 {
+                Some(batch_buf.get_u64())
+            } else {
+                None
+            };
+            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), (value, expire_at));
+        }
+    }
+
+    /// Decode the record starting at `offset` of a per-memtable WAL file written before the WAL was segmented. Each
+    /// record of such a file is a u32 batch size, the batch without expiry flags and a u32 checksum of the batch.
+    fn decode_legacy_record(buf: &[u8], offset: usize, memtable_id: usize) -> DecodedRecord<'_> {
+        let mut rbuf = &buf[offset.min(buf.len())..];
+        if !rbuf.has_remaining() {
+            return DecodedRecord::End;
+        }
+        if rbuf.remaining() < 4 {
+            return DecodedRecord::Damaged {
+                kind: WalCorruptionKind::Incomplete,
+                len: None,
+            };
+        }
+        let batch_size = rbuf.get_u32() as usize;
+        if rbuf.remaining() < batch_size + 4 {
+            return DecodedRecord::Damaged {
+                kind: WalCorruptionKind::Incomplete,
+                len: None,
+            };
+        }
+        let batch = &rbuf[..batch_size];
+        rbuf.advance(batch_size);
+        let len = batch_size + 8;
+        if rbuf.get_u32() != crc32fast::hash(batch) {
+            return DecodedRecord::Damaged {
+                kind: WalCorruptionKind::ChecksumMismatch,
+                len: Some(len),
+            };
+        }
+        DecodedRecord::Valid {
+            memtable_id,
+            batch,
+            len,
+        }
+    }
+
+    /// Count the intact records from `offset` to the end of a legacy WAL file.
+    fn count_legacy_records(buf: &[u8], mut offset: usize) -> usize {
+        let mut cnt = 0;
+        while let DecodedRecord::Valid { len, .. } = Self::decode_legacy_record(buf, offset, 0) {
+            offset += len;
+            cnt += 1;
+        }
+        cnt
+    }
+
+    /// Read the batches of the per-memtable WAL files written before the WAL was segmented, given as the memtable id
+    /// and path of each file in the order of the memtables. The files are the log before the first segment, and
+    /// damaged records are handled according to `mode` as in `recover`, adding them to `report`.
+    pub fn read_legacy(
+        files: &[(usize, PathBuf)],
+        mode: WalRecoveryMode,
+        report: &mut WalRecoveryReport,
+    ) -> Result<Vec<Vec<LegacyWalBatch>>> {
+        let mut batches_of_files = Vec::new();
+        let mut stopped = false;
+        for (idx, (memtable_id, path)) in files.iter().enumerate() {
+            let buf = std::fs::read(path).context("failed to read legacy WAL")?;
+            let mut batches = Vec::new();
+            if stopped {
+                // point-in-time recovery stopped in an earlier file, discard this one entirely
+                report.dropped_records += Self::count_legacy_records(&buf, 0);
+                batches_of_files.push(batches);
+                continue;
+            }
+            let mut offset = 0;
+            loop {
+                match Self::decode_legacy_record(&buf, offset, *memtable_id) {
+                    DecodedRecord::End => break,
+                    DecodedRecord::Valid { mut batch, len, .. } => {
+                        let mut records = Vec::new();
+                        while batch.has_remaining() {
+                            let key_len = batch.get_u16() as usize;
+                            let key = Bytes::copy_from_slice(&batch[..key_len]);
+                            batch.advance(key_len);
+                            let ts = batch.get_u64();
+                            let value_len = batch.get_u16() as usize;
+                            let value = Bytes::copy_from_slice(&batch[..value_len]);
+                            batch.advance(value_len);
+                            records.push((KeyBytes::from_bytes_with_ts(key, ts), value));
+                        }
+                        batches.push(records);
+                        offset += len;
+                    }
+                    DecodedRecord::Damaged { kind, len } => {
+                        let corruption = WalCorruption {
+                            segment_seq: 0,
+                            legacy_memtable_id: Some(*memtable_id),
+                            offset,
+                            kind,
+                        };
+                        let next = len.map(|len| offset + len);
+                        // only the file of the newest memtable was being written to, and the files are deleted once
+                        // migrated, so there is nothing to truncate
+                        let at_tail =
+                            idx + 1 == files.len() && next.is_none_or(|next| next == buf.len());
+                        match mode {
+                            WalRecoveryMode::AbsoluteConsistency => bail!("{}", corruption),
+                            WalRecoveryMode::TolerateCorruptedTailRecords if !at_tail => {
+                                bail!("{}", corruption)
+                            }
+                            WalRecoveryMode::TolerateCorruptedTailRecords => {
+                                report.corruptions.push(corruption);
+                                break;
+                            }
+                            WalRecoveryMode::PointInTime => {
+                                report.dropped_records +=
+                                    next.map_or(0, |next| Self::count_legacy_records(&buf, next));
+                                report.corruptions.push(corruption);
+                                stopped = true;
+                                break;
+                            }
+                            WalRecoveryMode::SkipAnyCorruptedRecords => {
+                                report.corruptions.push(corruption);
+                                match next {
+                                    Some(next) => offset = next,
+                                    None => break,
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            batches_of_files.push(batches);
+        }
+        Ok(batches_of_files)
+    }
+
+    /// Zero `len` bytes at `offset` of the file of `slot`, so that a later recovery stops reading there.
+    fn erase(dir: &Path, slot: usize, offset: usize, len: usize) -> Result<()> {
         let mut file = OpenOptions::new()
-            .read(true)
-            .append(true)
-            .open(path)
-            .context("failed to recover from WAL")?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
-            let batch_size = rbuf.get_u32() as usize;
-            if rbuf.remaining() < batch_size {
-                bail!("incomplete WAL");
+            .write(true)
+            .open(Self::path_of_segment(dir, slot))
+            .context("failed to truncate WAL segment")?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(&vec![0; len])?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Read all segments in `dir` and return the records of each memtable, along with what `mode` dropped.
+    /// Appending resumes in a new segment.
+    pub fn recover(
+        dir: impl AsRef<Path>,
+        segment_size: usize,
+        mode: WalRecoveryMode,
+    ) -> Result<(Self, BTreeMap<usize, WalRecords>, WalRecoveryReport)> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).context("failed to create WAL dir")?;
+        let mut slots = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "log") {
+                if let Some(slot) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok())
+                {
+                    slots.push(slot);
+                }
             }
-            let mut batch_buf = &rbuf[..batch_size];
-            let mut kv_pairs = Vec::new();
-            let mut hasher = crc32fast::Hasher::new();
-            // The checksum computed from the individual components should be the same as a direct checksum on the buffer.
-            // Students' implementation only needs to do a single checksum on the buffer. We compute both for verification purpose.
-            let single_checksum = crc32fast::hash(batch_buf);
-            while batch_buf.has_remaining() {
-                let key_len = batch_buf.get_u16() as usize;
-                hasher.write(&(key_len as u16).to_be_bytes());
-                let key = Bytes::copy_from_slice(&batch_buf[..key_len]);
-                hasher.write(&key);
-                batch_buf.advance(key_len);
-                let ts = batch_buf.get_u64();
-                hasher.write(&ts.to_be_bytes());
-                let value_len = batch_buf.get_u16() as usize;
-                hasher.write(&(value_len as u16).to_be_bytes());
-                let value = Bytes::copy_from_slice(&batch_buf[..value_len]);
-                hasher.write(&value);
-                kv_pairs.push((key, ts, value));
-                batch_buf.advance(value_len);
+        }
+        let next_slot = slots.iter().max().map_or(0, |slot| slot + 1);
+
+        let mut segments = Vec::new();
+        let mut free = Vec::new();
+        for slot in slots {
+            let mut buf = Vec::new();
+            File::open(Self::path_of_segment(dir, slot))
+                .context("failed to recover from WAL")?
+                .read_to_end(&mut buf)?;
+            let mut rbuf = buf.as_slice();
+            if rbuf.remaining() < SEGMENT_HEADER_SIZE {
+                free.push(slot);
+                continue;
             }
-            rbuf.advance(batch_size);
-            let expected_checksum = rbuf.get_u32();
-            let component_checksum = hasher.finalize();
-            assert_eq!(component_checksum, single_checksum);
-            if single_checksum != expected_checksum {
-                bail!("checksum mismatch");
+            let seq = rbuf.get_u64();
+            if rbuf.get_u32() != crc32fast::hash(&seq.to_be_bytes()) {
+                // the header of a new segment was torn, so it holds no records yet
+                free.push(slot);
+                continue;
             }
-            for (key, ts, value) in kv_pairs {
-                skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            segments.push((seq, slot, buf));
+        }
+        segments.sort_by_key(|(seq, _, _)| *seq);
+        let next_seq = segments.last().map_or(1, |(seq, _, _)| seq + 1);
+
+        let mut memtables = BTreeMap::<usize, WalRecords>::new();
+        let mut sealed = VecDeque::new();
+        let mut report = WalRecoveryReport::default();
+        let mut stopped = false;
+        let num_segments = segments.len();
+        for (idx, (seq, slot, buf)) in segments.into_iter().enumerate() {
+            if stopped {
+                // point-in-time recovery stopped in an earlier segment, discard this one entirely
+                report.dropped_records += Self::count_records(&buf, SEGMENT_HEADER_SIZE, seq);
+                Self::erase(dir, slot, 0, SEGMENT_HEADER_SIZE)?;
+                free.push(slot);
+                continue;
+            }
+            let mut segment = Segment {
+                slot,
+                seq,
+                memtable_ids: None,
+            };
+            let mut offset = SEGMENT_HEADER_SIZE;
+            loop {
+                match Self::decode_record(&buf, offset, seq) {
+                    DecodedRecord::End => break,
+                    DecodedRecord::Valid {
+                        memtable_id,
+                        batch,
+                        len,
+                    } => {
+                        Self::replay_batch(batch, memtables.entry(memtable_id).or_default());
+                        segment.add_memtable_id(memtable_id);
+                        offset += len;
+                    }
+                    DecodedRecord::Damaged { kind, len } => {
+                        let corruption = WalCorruption {
+                            segment_seq: seq,
+                            legacy_memtable_id: None,
+                            offset,
+                            kind,
+                        };
+                        let next = len.map(|len| offset + len);
+                        // only unsynced writes to the last segment can be torn, and they are never followed by an
+                        // intact record
+                        let at_tail = idx + 1 == num_segments
+                            && next.is_none_or(|next| {
+                                matches!(Self::decode_record(&buf, next, seq), DecodedRecord::End)
+                            });
+                        match mode {
+                            WalRecoveryMode::AbsoluteConsistency => bail!("{}", corruption),
+                            WalRecoveryMode::TolerateCorruptedTailRecords if !at_tail => {
+                                bail!("{}", corruption)
+                            }
+                            WalRecoveryMode::TolerateCorruptedTailRecords => {
+                                Self::erase(dir, slot, offset, 8)?;
+                                report.corruptions.push(corruption);
+                                break;
+                            }
+                            WalRecoveryMode::PointInTime => {
+                                report.dropped_records +=
+                                    next.map_or(0, |next| Self::count_records(&buf, next, seq));
+                                Self::erase(dir, slot, offset, 8)?;
+                                report.corruptions.push(corruption);
+                                stopped = true;
+                                break;
+                            }
+                            WalRecoveryMode::SkipAnyCorruptedRecords => {
+                                report.corruptions.push(corruption);
+                                match next {
+                                    Some(next) => offset = next,
+                                    None => break,
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if segment.memtable_ids.is_some() {
+                sealed.push_back(segment);
+            } else {
+                free.push(slot);
             }
         }
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
-        })
+
+        let (slot, next_slot) = match free.pop() {
+            Some(slot) => (slot, next_slot),
+            None => (next_slot, next_slot + 1),
+        };
+        let file = Self::open_segment(dir, segment_size, slot, next_seq)?;
+        let wal = Self {
+            dir: dir.to_path_buf(),
+            segment_size,
+            inner: Arc::new(Mutex::new(WalInner {
+                file: BufWriter::new(file),
+                active: Segment {
+                    slot,
+                    seq: next_seq,
+                    memtable_ids: None,
+                },
+                offset: SEGMENT_HEADER_SIZE,
+                sealed,
+                free,
+                next_slot,
+            })),
+        };
+        Ok((wal, memtables, report))
     }
 
     /// Implement this in week 3, day 5.
-    pub fn put_batch(&self, data: &[(KeySlice, &[u8])]) -> Result<()> {
-        let mut file = self.file.lock();
+    pub fn put_batch(&self, memtable_id: usize, data: &[(KeySlice, &[u8])]) -> Result<()> {
+        let data = data
+            .iter()
+            .map(|(key, value)| (*key, *value, None))
+            .collect::<Vec<_>>();
+        self.put_batch_with_expiry(memtable_id, &data)
+    }
+
+    /// Write a batch of key-value pairs, each with an optional expiry timestamp, as one record of `memtable_id`.
+    pub fn put_batch_with_expiry(
+        &self,
+        memtable_id: usize,
+        data: &[(KeySlice, &[u8], Option<u64>)],
+    ) -> Result<()> {
         let mut buf = Vec::<u8>::new();
-        for (key, value) in data {
+        for (key, value, expire_at) in data {
+            if value.len() > MAX_VALUE_SIZE {
+                bail!(
+                    "value of {} bytes is larger than {} bytes",
+                    value.len(),
+                    MAX_VALUE_SIZE
+                );
+            }
             buf.put_u16(key.key_len() as u16);
             buf.put_slice(key.key_ref());
             buf.put_u64(key.ts());
             buf.put_u16(value.len() as u16);
             buf.put_slice(value);
+            if let Some(expire_at) = expire_at {
+                buf.put_u8(ENTRY_EXPIRES);
+                buf.put_u64(*expire_at);
+            } else {
+                buf.put_u8(0);
+            }
         }
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&(memtable_id as u64).to_be_bytes());
+        hasher.update(&buf);
+
+        let mut inner = self.inner.lock();
+        let record_size = RECORD_HEADER_SIZE + buf.len() + 4;
+        // a record larger than a segment is written to an empty segment, growing the file
+        if inner.offset + record_size > self.segment_size && inner.active.memtable_ids.is_some() {
+            self.roll_segment(&mut inner)?;
+        }
+        let seq = inner.active.seq;
+        let file = &mut inner.file;
+        file.write_all(&seq.to_be_bytes())?;
+        file.write_all(&(memtable_id as u64).to_be_bytes())?;
         // write batch_size header (u32)
         file.write_all(&(buf.len() as u32).to_be_bytes())?;
         // write key-value pairs body
         file.write_all(&buf)?;
         // write checksum (u32)
-        file.write_all(&crc32fast::hash(&buf).to_be_bytes())?;
+        file.write_all(&hasher.finalize().to_be_bytes())?;
+        inner.offset += record_size;
+        inner.active.add_memtable_id(memtable_id);
         Ok(())
     }
 
-    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        self.put_batch(&[(key, value)])
+    pub fn put(&self, memtable_id: usize, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.put_batch(memtable_id, &[(key, value)])
+    }
+
+    /// Seal the active segment and continue in a recycled or newly created one.
+    fn roll_segment(&self, inner: &mut WalInner) -> Result<()> {
+        inner.file.flush()?;
+        inner.file.get_mut().sync_data()?;
+        let (slot, next_slot) = match inner.free.pop() {
+            Some(slot) => (slot, inner.next_slot),
+            None => (inner.next_slot, inner.next_slot + 1),
+        };
+        let seq = inner.active.seq + 1;
+        let file = Self::open_segment(&self.dir, self.segment_size, slot, seq)?;
+        inner.file = BufWriter::new(file);
+        inner.next_slot = next_slot;
+        inner.offset = SEGMENT_HEADER_SIZE;
+        let sealed = std::mem::replace(
+            &mut inner.active,
+            Segment {
+                slot,
+                seq,
+                memtable_ids: None,
+            },
+        );
+        inner.sealed.push_back(sealed);
+        Ok(())
+    }
+
+    /// Recycle the sealed segments that only hold records of memtables with ids below `min_memtable_id`, i.e., of
+    /// memtables that have all been flushed.
+    pub fn purge(&self, min_memtable_id: usize) {
+        let mut inner = self.inner.lock();
+        while let Some(segment) = inner.sealed.front() {
+            match segment.memtable_ids {
+                Some((_, last)) if last >= min_memtable_id => break,
+                _ => {
+                    let segment = inner.sealed.pop_front().unwrap();
+                    inner.free.push(segment.slot);
+                }
+            }
+        }
+    }
+
+    /// The number of segment files, including the free ones.
+    pub fn num_segments(&self) -> usize {
+        let inner = self.inner.lock();
+        1 + inner.sealed.len() + inner.free.len()
     }
 
     pub fn sync(&self) -> Result<()> {
-        let mut file = self.file.lock();
-        file.flush()?;
-        file.get_mut().sync_all()?;
+        let mut inner = self.inner.lock();
+        inner.file.flush()?;
+        inner.file.get_mut().sync_data()?;
         Ok(())
     }
 }
@@ -1,19 +1,60 @@
+// The compaction strategies and options that only mini-lsm-mvcc has are behind its `mvcc` feature, which the other
+// crates sharing this file through symlinks do not declare.
+#![allow(unexpected_cfgs)]
+
 mod wrapper;
 use wrapper::mini_lsm_wrapper;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use bytes::{Buf, BufMut, BytesMut};
-use clap::Parser;
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use clap::{Parser, ValueEnum};
+#[cfg(feature = "mvcc")]
+use mini_lsm_wrapper::compact::{
+    LazyLeveledCompactionController, LazyLeveledCompactionOptions, LazyLeveledCompactionTask,
+    SstSelection,
+};
 use mini_lsm_wrapper::compact::{
-    LeveledCompactionController, LeveledCompactionOptions, SimpleLeveledCompactionController,
-    SimpleLeveledCompactionOptions, TieredCompactionController, TieredCompactionOptions,
+    LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask,
+    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
+    TieredCompactionController, TieredCompactionOptions, TieredCompactionTask,
 };
 use mini_lsm_wrapper::key::KeyBytes;
 use mini_lsm_wrapper::lsm_storage::LsmStorageState;
 use mini_lsm_wrapper::mem_table::MemTable;
 use mini_lsm_wrapper::table::SsTable;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+
+#[derive(Debug, Clone, ValueEnum)]
+enum TraceCompaction {
+    Simple,
+    Leveled,
+    Tiered,
+    #[cfg(feature = "mvcc")]
+    LazyLeveled,
+}
+
+#[cfg(feature = "mvcc")]
+#[derive(Debug, Clone, ValueEnum)]
+enum TraceSstSelection {
+    Oldest,
+    MinOverlap,
+    RoundRobin,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum ReportFormat {
+    Text,
+    Csv,
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -63,6 +104,11 @@ enum Args {
         max_merge_width: Option<usize>,
         #[clap(long, default_value = "50")]
         iterations: usize,
+        /// The size of each flushed SST is picked at random between `min_sst_size_mb` and `max_sst_size_mb`.
+        #[clap(long, default_value = "1")]
+        min_sst_size_mb: usize,
+        #[clap(long, default_value = "1")]
+        max_sst_size_mb: usize,
     },
     Leveled {
         /// Dump the generated ID instead of where the original data comes from.
@@ -89,6 +135,90 @@ enum Args {
         #[clap(long, default_value = "32")]
         sst_size_mb: usize,
     },
+    #[cfg(feature = "mvcc")]
+    LazyLeveled {
+        /// Dump the generated ID instead of where the original data comes from.
+        /// For example, if SST 1, 2, 3 is compacted to another level, it should have
+        /// a new SST ID 4, 5, 6 as SSTs are immutable and write-once. With this flag
+        /// enabled, you will see the new level has SST 1, 2, 3 because the data of
+        /// 4, 5, 6 are originated from 1, 2, 3.
+        #[clap(long)]
+        dump_real_id: bool,
+        /// Only dump size information instead of the layer files. if this is enabled,
+        /// it will print one row per compaction iteration.
+        #[clap(long)]
+        size_only: bool,
+        #[clap(long, default_value = "4")]
+        level_size_multiplier: usize,
+        #[clap(long, default_value = "4")]
+        max_levels: usize,
+        #[clap(long, default_value = "1")]
+        base_level_size_mb: usize,
+        #[clap(long, default_value = "50")]
+        iterations: usize,
+        #[clap(long, default_value = "1")]
+        sst_size_mb: usize,
+    },
+    /// Replay a workload with real keys against the compaction controllers, and report the amplifications over time.
+    Trace {
+        #[clap(long, default_value = "leveled")]
+        compaction: TraceCompaction,
+        /// A recorded workload with one `put <key> <value size>` or `del <key>` per line. If not given, a workload
+        /// with zipfian distributed keys is generated.
+        #[clap(long)]
+        trace: Option<PathBuf>,
+        #[clap(long, default_value = "1000000")]
+        num_ops: usize,
+        #[clap(long, default_value = "100000")]
+        num_keys: usize,
+        /// The skew of the generated keys, where 0 is uniform.
+        #[clap(long, default_value = "0.99")]
+        zipf_theta: f64,
+        #[clap(long, default_value = "100")]
+        value_size: usize,
+        #[clap(long, default_value = "0")]
+        delete_percent: usize,
+        #[clap(long, default_value = "0")]
+        seed: u64,
+        #[clap(long, default_value = "1")]
+        memtable_size_mb: usize,
+        #[clap(long, default_value = "2")]
+        sst_size_mb: usize,
+        #[clap(long, default_value = "4")]
+        level0_file_num_compaction_trigger: usize,
+        #[clap(long, default_value = "4")]
+        max_levels: usize,
+        #[clap(long, default_value = "200")]
+        size_ratio_percent: usize,
+        #[clap(long, default_value = "4")]
+        level_size_multiplier: usize,
+        #[clap(long, default_value = "8")]
+        base_level_size_mb: usize,
+        #[cfg(feature = "mvcc")]
+        #[clap(long, default_value = "oldest")]
+        sst_selection: TraceSstSelection,
+        #[clap(long, default_value = "8")]
+        num_tiers: usize,
+        #[clap(long, default_value = "200")]
+        max_size_amplification_percent: usize,
+        #[clap(long, default_value = "1")]
+        size_ratio: usize,
+        #[clap(long, default_value = "2")]
+        min_merge_width: usize,
+        #[clap(long)]
+        max_merge_width: Option<usize>,
+        /// Report the amplifications every `report_every` flushes.
+        #[clap(long, default_value = "1")]
+        report_every: usize,
+        #[clap(long, default_value = "text")]
+        format: ReportFormat,
+        /// Write the report to this file instead of stdout, which the controllers also log to.
+        #[clap(long)]
+        output: Option<PathBuf>,
+        /// Print every compaction task.
+        #[clap(long)]
+        verbose: bool,
+    },
 }
 
 pub struct MockStorage {
@@ -139,12 +269,13 @@ impl MockStorage {
         id
     }
 
-    pub fn flush_sst_to_new_tier(&mut self) {
+    pub fn flush_sst_to_new_tier(&mut self) -> usize {
         let id = self.generate_sst_id();
         self.snapshot.levels.insert(0, (id, vec![id]));
         self.file_list.insert(id, id);
         self.total_flushes += 1;
         self.total_writes += 1;
+        id
     }
 
     pub fn remove(&mut self, files_to_remove: &[usize]) {
@@ -185,6 +316,18 @@ impl MockStorage {
         println!();
     }
 
+    pub fn dump_tier_sizes(&self) {
+        print!("Tiers (MB):");
+        for (_, files) in &self.snapshot.levels {
+            let size = files
+                .iter()
+                .map(|id| self.snapshot.sstables[id].table_size())
+                .sum::<u64>();
+            print!(" {:.1}", size as f64 / 1024.0 / 1024.0);
+        }
+        println!();
+    }
+
     pub fn dump_original_id(&self, always_show_l0: bool, with_key: bool) {
         if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
             println!(
@@ -263,7 +406,598 @@ fn generate_random_split(
     result
 }
 
-fn main() {
+/// An operation of a workload.
+enum TraceOp {
+    Put { key: Bytes, value_size: usize },
+    Delete { key: Bytes },
+}
+
+/// Read a recorded workload, with one `put <key> <value size>` or `del <key>` per line. Empty lines and lines
+/// starting with `#` are skipped.
+fn read_trace(path: &PathBuf) -> Result<impl Iterator<Item = Result<TraceOp>>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let ops = BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter_map(|(lineno, line)| {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let op = match fields.as_slice() {
+                [] => return None,
+                [first, ..] if first.starts_with('#') => return None,
+                ["put", key, value_size] => match value_size.parse() {
+                    Ok(value_size) => Ok(TraceOp::Put {
+                        key: Bytes::copy_from_slice(key.as_bytes()),
+                        value_size,
+                    }),
+                    Err(e) => Err(anyhow::anyhow!("line {}: {}", lineno + 1, e)),
+                },
+                ["del", key] => Ok(TraceOp::Delete {
+                    key: Bytes::copy_from_slice(key.as_bytes()),
+                }),
+                _ => Err(anyhow::anyhow!(
+                    "line {}: invalid operation {:?}",
+                    lineno + 1,
+                    line
+                )),
+            };
+            Some(op)
+        });
+    Ok(ops)
+}
+
+/// Samples key ranks in `0..n`, where rank `i` is picked with a probability proportional to `1 / (i + 1)^theta`.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, theta: f64) -> Self {
+        let mut cdf = Vec::with_capacity(n);
+        let mut sum = 0.0;
+        for i in 0..n {
+            sum += 1.0 / ((i + 1) as f64).powf(theta);
+            cdf.push(sum);
+        }
+        for p in &mut cdf {
+            *p /= sum;
+        }
+        Self { cdf }
+    }
+
+    fn sample(&self, rng: &mut impl Rng) -> usize {
+        let p = rng.gen::<f64>();
+        self.cdf.partition_point(|&c| c < p).min(self.cdf.len() - 1)
+    }
+}
+
+/// Generate `num_ops` operations on `num_keys` keys. The hot keys are scattered over the key space, so that they do
+/// not all land in the same SSTs.
+fn generate_zipfian_workload(
+    num_ops: usize,
+    num_keys: usize,
+    theta: f64,
+    value_size: usize,
+    delete_percent: usize,
+    seed: u64,
+) -> impl Iterator<Item = Result<TraceOp>> {
+    let zipf = Zipf::new(num_keys.max(1), theta);
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..num_ops).map(move |_| {
+        let rank = zipf.sample(&mut rng) as u64;
+        let key = Bytes::from(format!(
+            "user{:016x}",
+            farmhash::fingerprint64(&rank.to_le_bytes())
+        ));
+        if rng.gen_range(0..100) < delete_percent {
+            Ok(TraceOp::Delete { key })
+        } else {
+            Ok(TraceOp::Put { key, value_size })
+        }
+    })
+}
+
+enum TraceController {
+    Simple(SimpleLeveledCompactionController),
+    Leveled(LeveledCompactionController),
+    Tiered(TieredCompactionController),
+    #[cfg(feature = "mvcc")]
+    LazyLeveled(LazyLeveledCompactionController),
+}
+
+enum TraceTask {
+    Simple(SimpleLeveledCompactionTask),
+    Leveled(LeveledCompactionTask),
+    Tiered(TieredCompactionTask),
+    #[cfg(feature = "mvcc")]
+    LazyLeveled(LazyLeveledCompactionTask),
+}
+
+impl TraceController {
+    fn flush_to_l0(&self) -> bool {
+        match self {
+            TraceController::Simple(_) | TraceController::Leveled(_) => true,
+            TraceController::Tiered(_) => false,
+            #[cfg(feature = "mvcc")]
+            TraceController::LazyLeveled(_) => false,
+        }
+    }
+
+    fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<TraceTask> {
+        match self {
+            TraceController::Simple(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(TraceTask::Simple),
+            TraceController::Leveled(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(TraceTask::Leveled),
+            TraceController::Tiered(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(TraceTask::Tiered),
+            #[cfg(feature = "mvcc")]
+            TraceController::LazyLeveled(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(TraceTask::LazyLeveled),
+        }
+    }
+
+    /// The SSTs that `task` moves into the lower level as they are, if it is a trivial move.
+    #[cfg(feature = "mvcc")]
+    fn trivial_move(&self, snapshot: &LsmStorageState, task: &TraceTask) -> Option<Vec<usize>> {
+        match (self, task) {
+            (TraceController::Simple(ctrl), TraceTask::Simple(task))
+                if ctrl.is_trivial_move(snapshot, task) =>
+            {
+                Some(task.upper_level_sst_ids.clone())
+            }
+            (TraceController::Leveled(ctrl), TraceTask::Leveled(task))
+                if ctrl.is_trivial_move(snapshot, task) =>
+            {
+                Some(task.upper_level_sst_ids.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Only the controllers of mini-lsm-mvcc detect trivial moves.
+    #[cfg(not(feature = "mvcc"))]
+    fn trivial_move(&self, _snapshot: &LsmStorageState, _task: &TraceTask) -> Option<Vec<usize>> {
+        None
+    }
+
+    fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &TraceTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        match (self, task) {
+            (TraceController::Simple(ctrl), TraceTask::Simple(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            (TraceController::Leveled(ctrl), TraceTask::Leveled(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output, false)
+            }
+            (TraceController::Tiered(ctrl), TraceTask::Tiered(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            #[cfg(feature = "mvcc")]
+            (TraceController::LazyLeveled(ctrl), TraceTask::LazyLeveled(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl TraceTask {
+    /// The sorted runs merged by the task, newest first.
+    fn sorted_runs(&self) -> Vec<Vec<usize>> {
+        let (upper_level, upper_level_sst_ids, lower_level_sst_ids) = match self {
+            TraceTask::Simple(task) => (
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                &task.lower_level_sst_ids,
+            ),
+            TraceTask::Leveled(task) => (
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                &task.lower_level_sst_ids,
+            ),
+            TraceTask::Tiered(TieredCompactionTask { tiers: runs, .. }) => {
+                return runs.iter().map(|(_, ssts)| ssts.clone()).collect();
+            }
+            #[cfg(feature = "mvcc")]
+            TraceTask::LazyLeveled(LazyLeveledCompactionTask { runs, .. }) => {
+                return runs.iter().map(|(_, ssts)| ssts.clone()).collect();
+            }
+        };
+        let mut runs = match upper_level {
+            Some(_) => vec![upper_level_sst_ids.clone()],
+            None => {
+                // flushed SSTs get increasing ids, and nothing else is written to L0
+                let mut l0_sst_ids = upper_level_sst_ids.clone();
+                l0_sst_ids.sort_by(|a, b| b.cmp(a));
+                l0_sst_ids.into_iter().map(|id| vec![id]).collect()
+            }
+        };
+        runs.push(lower_level_sst_ids.clone());
+        runs
+    }
+
+    fn is_bottom_level(&self) -> bool {
+        match self {
+            TraceTask::Simple(task) => task.is_lower_level_bottom_level,
+            TraceTask::Leveled(task) => task.is_lower_level_bottom_level,
+            TraceTask::Tiered(task) => task.bottom_tier_included,
+            #[cfg(feature = "mvcc")]
+            TraceTask::LazyLeveled(task) => task.bottom_run_included,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            TraceTask::Simple(task) => format!(
+                "L{} {:?} + L{} {:?}",
+                task.upper_level.unwrap_or_default(),
+                task.upper_level_sst_ids,
+                task.lower_level,
+                task.lower_level_sst_ids
+            ),
+            TraceTask::Leveled(task) => format!(
+                "L{} {:?} + L{} {:?}",
+                task.upper_level.unwrap_or_default(),
+                task.upper_level_sst_ids,
+                task.lower_level,
+                task.lower_level_sst_ids
+            ),
+            TraceTask::Tiered(TieredCompactionTask { tiers: runs, .. }) => describe_runs(runs),
+            #[cfg(feature = "mvcc")]
+            TraceTask::LazyLeveled(LazyLeveledCompactionTask { runs, .. }) => describe_runs(runs),
+        }
+    }
+}
+
+fn describe_runs(runs: &[(usize, Vec<usize>)]) -> String {
+    runs.iter()
+        .map(|(tier_id, ssts)| format!("T{} {:?}", tier_id, ssts))
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+/// An entry of a simulated SST: the key and the size of its value, `None` for a delete tombstone.
+type TraceEntry = (Bytes, Option<usize>);
+
+fn entry_size((key, value_size): &TraceEntry) -> usize {
+    key.len() + value_size.unwrap_or_default()
+}
+
+/// The amplifications of the storage after a flush and the compactions it triggered.
+#[derive(Serialize)]
+struct TraceReport {
+    ops: usize,
+    flushes: usize,
+    compactions: usize,
+    /// The size of the live keys and their latest values.
+    live_bytes: u64,
+    /// The total size of the SSTs.
+    sst_bytes: u64,
+    flushed_bytes: u64,
+    compaction_read_bytes: u64,
+    compaction_written_bytes: u64,
+    /// The bytes written by flushes and compactions per byte flushed.
+    write_amplification: f64,
+    /// The number of sorted runs a point lookup may probe.
+    read_amplification: usize,
+    /// The total size of the SSTs per byte of live data.
+    space_amplification: f64,
+}
+
+/// A storage model whose SSTs hold the keys that were actually written, so that flushes and compactions produce the
+/// real key ranges and sizes, and drop overwritten and deleted keys as the storage engine does.
+struct TraceStorage {
+    snapshot: LsmStorageState,
+    controller: TraceController,
+    /// The entries of each SST in key order.
+    entries: HashMap<usize, Vec<TraceEntry>>,
+    memtable: BTreeMap<Bytes, Option<usize>>,
+    memtable_size: usize,
+    memtable_limit: usize,
+    sst_size: usize,
+    /// The size of the latest value of each live key.
+    live_keys: HashMap<Bytes, usize>,
+    live_bytes: u64,
+    next_sst_id: usize,
+    ops: usize,
+    flushes: usize,
+    compactions: usize,
+    flushed_bytes: u64,
+    compaction_read_bytes: u64,
+    compaction_written_bytes: u64,
+}
+
+impl TraceStorage {
+    fn new(controller: TraceController, memtable_limit: usize, sst_size: usize) -> Self {
+        let snapshot = LsmStorageState {
+            memtable: Arc::new(MemTable::create(0)),
+            imm_memtables: Vec::new(),
+            l0_sstables: Vec::new(),
+            levels: Vec::new(),
+            sstables: Default::default(),
+        };
+        Self {
+            snapshot,
+            controller,
+            entries: HashMap::new(),
+            memtable: BTreeMap::new(),
+            memtable_size: 0,
+            memtable_limit,
+            sst_size,
+            live_keys: HashMap::new(),
+            live_bytes: 0,
+            next_sst_id: 1,
+            ops: 0,
+            flushes: 0,
+            compactions: 0,
+            flushed_bytes: 0,
+            compaction_read_bytes: 0,
+            compaction_written_bytes: 0,
+        }
+    }
+
+    /// Apply `op`, returning whether the memtable is full and should be flushed.
+    fn apply(&mut self, op: TraceOp) -> bool {
+        let (key, value_size) = match op {
+            TraceOp::Put { key, value_size } => (key, Some(value_size)),
+            TraceOp::Delete { key } => (key, None),
+        };
+        let old_size = match value_size {
+            Some(value_size) => self.live_keys.insert(key.clone(), key.len() + value_size),
+            None => self.live_keys.remove(&key),
+        };
+        self.live_bytes -= old_size.unwrap_or_default() as u64;
+        self.live_bytes += self.live_keys.get(&key).copied().unwrap_or_default() as u64;
+        // the memtable keeps every write, like the skiplist does
+        self.memtable_size += key.len() + value_size.unwrap_or_default();
+        self.memtable.insert(key, value_size);
+        self.ops += 1;
+        self.memtable_size >= self.memtable_limit
+    }
+
+    fn new_sst(&mut self, entries: Vec<TraceEntry>) -> usize {
+        let id = self.next_sst_id;
+        self.next_sst_id += 1;
+        let size = entries.iter().map(entry_size).sum::<usize>() as u64;
+        let first_key = KeyBytes::for_testing_from_bytes_no_ts(entries.first().unwrap().0.clone());
+        let last_key = KeyBytes::for_testing_from_bytes_no_ts(entries.last().unwrap().0.clone());
+        self.snapshot.sstables.insert(
+            id,
+            Arc::new(SsTable::create_meta_only(id, size, first_key, last_key)),
+        );
+        self.entries.insert(id, entries);
+        id
+    }
+
+    fn flush(&mut self) {
+        if self.memtable.is_empty() {
+            return;
+        }
+        let entries = std::mem::take(&mut self.memtable)
+            .into_iter()
+            .collect::<Vec<_>>();
+        self.memtable_size = 0;
+        let id = self.new_sst(entries);
+        if self.controller.flush_to_l0() {
+            self.snapshot.l0_sstables.insert(0, id);
+        } else {
+            self.snapshot.levels.insert(0, (id, vec![id]));
+        }
+        self.flushes += 1;
+        self.flushed_bytes += self.snapshot.sstables[&id].table_size();
+    }
+
+    /// Merge the sorted runs of `task`, and split the result into SSTs of about `sst_size`.
+    fn compact(&mut self, task: &TraceTask) -> Vec<usize> {
+        let mut merged = BTreeMap::new();
+        for run in task.sorted_runs().iter().rev() {
+            for id in run {
+                self.compaction_read_bytes += self.snapshot.sstables[id].table_size();
+                for (key, value_size) in &self.entries[id] {
+                    merged.insert(key.clone(), *value_size);
+                }
+            }
+        }
+        let bottom_level = task.is_bottom_level();
+        let mut output = Vec::new();
+        let mut entries = Vec::new();
+        let mut size = 0;
+        for entry in merged {
+            if bottom_level && entry.1.is_none() {
+                continue;
+            }
+            size += entry_size(&entry);
+            entries.push(entry);
+            if size >= self.sst_size {
+                output.push(self.new_sst(std::mem::take(&mut entries)));
+                size = 0;
+            }
+        }
+        if !entries.is_empty() {
+            output.push(self.new_sst(entries));
+        }
+        self.compaction_written_bytes += output
+            .iter()
+            .map(|id| self.snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        output
+    }
+
+    /// Run compactions until the controller has nothing left to do.
+    fn compact_until_stable(&mut self, verbose: bool) -> Result<()> {
+        let mut num_compactions = 0;
+        while let Some(task) = self.controller.generate_compaction_task(&self.snapshot) {
+            let output = match self.controller.trivial_move(&self.snapshot, &task) {
+                Some(output) => output,
+                None => self.compact(&task),
+            };
+            if verbose {
+                println!("compaction: {} -> {:?}", task.describe(), output);
+            }
+            let (mut snapshot, del) =
+                self.controller
+                    .apply_compaction_result(&self.snapshot, &task, &output);
+            for id in del.iter().filter(|id| !output.contains(id)) {
+                snapshot.sstables.remove(id);
+                self.entries.remove(id);
+            }
+            self.snapshot = snapshot;
+            self.compactions += 1;
+            num_compactions += 1;
+            if num_compactions >= 10000 {
+                bail!("compaction does not converge?");
+            }
+        }
+        Ok(())
+    }
+
+    fn report(&self) -> TraceReport {
+        let sst_bytes = self
+            .snapshot
+            .sstables
+            .values()
+            .map(|sst| sst.table_size())
+            .sum::<u64>();
+        TraceReport {
+            ops: self.ops,
+            flushes: self.flushes,
+            compactions: self.compactions,
+            live_bytes: self.live_bytes,
+            sst_bytes,
+            flushed_bytes: self.flushed_bytes,
+            compaction_read_bytes: self.compaction_read_bytes,
+            compaction_written_bytes: self.compaction_written_bytes,
+            write_amplification: (self.flushed_bytes + self.compaction_written_bytes) as f64
+                / self.flushed_bytes.max(1) as f64,
+            read_amplification: self.snapshot.l0_sstables.len()
+                + self
+                    .snapshot
+                    .levels
+                    .iter()
+                    .filter(|(_, ssts)| !ssts.is_empty())
+                    .count(),
+            space_amplification: sst_bytes as f64 / self.live_bytes.max(1) as f64,
+        }
+    }
+}
+
+/// Writes the reports as they come in. JSON reports are written as one array once the workload is done.
+struct ReportWriter {
+    format: ReportFormat,
+    out: Box<dyn Write>,
+    reports: Vec<TraceReport>,
+}
+
+impl ReportWriter {
+    fn new(format: ReportFormat, output: Option<&PathBuf>) -> Result<Self> {
+        let out: Box<dyn Write> = match output {
+            Some(path) => {
+                Box::new(BufWriter::new(File::create(path).with_context(|| {
+                    format!("failed to create {}", path.display())
+                })?))
+            }
+            None => Box::new(std::io::stdout()),
+        };
+        let mut writer = Self {
+            format,
+            out,
+            reports: Vec::new(),
+        };
+        if let ReportFormat::Csv = writer.format {
+            writeln!(
+                writer.out,
+                "ops,flushes,compactions,live_bytes,sst_bytes,flushed_bytes,compaction_read_bytes,\
+                 compaction_written_bytes,write_amplification,read_amplification,space_amplification"
+            )?;
+        }
+        Ok(writer)
+    }
+
+    fn write(&mut self, report: TraceReport) -> Result<()> {
+        match self.format {
+            ReportFormat::Text => writeln!(
+                self.out,
+                "ops={} flushes={} compactions={} live={:.1}MB sst={:.1}MB write_amp={:.3}x read_amp={}x space_amp={:.3}x",
+                report.ops,
+                report.flushes,
+                report.compactions,
+                report.live_bytes as f64 / 1024.0 / 1024.0,
+                report.sst_bytes as f64 / 1024.0 / 1024.0,
+                report.write_amplification,
+                report.read_amplification,
+                report.space_amplification
+            )?,
+            ReportFormat::Csv => writeln!(
+                self.out,
+                "{},{},{},{},{},{},{},{},{:.6},{},{:.6}",
+                report.ops,
+                report.flushes,
+                report.compactions,
+                report.live_bytes,
+                report.sst_bytes,
+                report.flushed_bytes,
+                report.compaction_read_bytes,
+                report.compaction_written_bytes,
+                report.write_amplification,
+                report.read_amplification,
+                report.space_amplification
+            )?,
+            ReportFormat::Json => self.reports.push(report),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let ReportFormat::Json = self.format {
+            serde_json::to_writer_pretty(&mut self.out, &self.reports)?;
+            writeln!(self.out)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Replay `ops` on `storage`, reporting every `report_every` flushes and once more at the end.
+fn run_trace(
+    mut storage: TraceStorage,
+    ops: impl Iterator<Item = Result<TraceOp>>,
+    report_every: usize,
+    mut writer: ReportWriter,
+    verbose: bool,
+) -> Result<()> {
+    let report_every = report_every.max(1);
+    let mut reported = false;
+    for op in ops {
+        if storage.apply(op?) {
+            storage.flush();
+            storage.compact_until_stable(verbose)?;
+            reported = storage.flushes.is_multiple_of(report_every);
+            if reported {
+                writer.write(storage.report())?;
+            }
+        }
+    }
+    if !storage.memtable.is_empty() || !reported {
+        storage.flush();
+        storage.compact_until_stable(verbose)?;
+        writer.write(storage.report())?;
+    }
+    writer.finish()
+}
+
+fn main() -> Result<()> {
     let args = Args::parse();
     match args {
         Args::Simple {
@@ -384,7 +1118,11 @@ fn main() {
             min_merge_width,
             max_merge_width,
             iterations,
+            min_sst_size_mb,
+            max_sst_size_mb,
         } => {
+            use rand::Rng;
+            let mut rng = rand::thread_rng();
             let controller = TieredCompactionController::new(TieredCompactionOptions {
                 num_tiers: level0_file_num_compaction_trigger,
                 max_size_amplification_percent,
@@ -394,12 +1132,23 @@ fn main() {
             });
             let mut storage = MockStorage::new();
             let mut max_space = 0;
+            let mut flushed_bytes = 0;
+            let mut written_bytes = 0;
+            let mut max_space_bytes = 0;
             for i in 0..iterations {
                 println!("=== Iteration {i} ===");
-                storage.flush_sst_to_new_tier();
+                let id = storage.flush_sst_to_new_tier();
+                let (first_key, last_key) = generate_random_key_range();
+                let size = rng.gen_range(min_sst_size_mb..=max_sst_size_mb) as u64 * 1024 * 1024;
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(id, size, first_key, last_key)),
+                );
+                flushed_bytes += size;
+                written_bytes += size;
                 println!("--- After Flush ---");
                 if size_only {
-                    storage.dump_size_only();
+                    storage.dump_tier_sizes();
                 } else if dump_real_id {
                     storage.dump_real_id(false, false);
                 } else {
@@ -418,22 +1167,44 @@ fn main() {
                     let mut sst_ids = Vec::new();
                     for (tier_id, files) in &task.tiers {
                         for file in files {
+                            // the merged SSTs keep the size of their input, as if no key was overwritten
+                            let input = storage.snapshot.sstables[file].clone();
                             let new_sst_id = storage.generate_sst_id();
                             sst_ids.push(new_sst_id);
                             storage.file_list.insert(new_sst_id, *file);
                             storage.total_writes += 1;
+                            written_bytes += input.table_size();
+                            storage.snapshot.sstables.insert(
+                                new_sst_id,
+                                Arc::new(SsTable::create_meta_only(
+                                    new_sst_id,
+                                    input.table_size(),
+                                    input.first_key().clone(),
+                                    input.last_key().clone(),
+                                )),
+                            );
                         }
                         print!("L{} {:?} ", tier_id, files);
                     }
                     println!("-> {:?}", sst_ids);
                     max_space = max_space.max(storage.file_list.len());
-                    let (snapshot, del) =
+                    max_space_bytes = max_space_bytes.max(
+                        storage
+                            .file_list
+                            .keys()
+                            .map(|id| storage.snapshot.sstables[id].table_size())
+                            .sum::<u64>(),
+                    );
+                    let (mut snapshot, del) =
                         controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    for id in &del {
+                        snapshot.sstables.remove(id);
+                    }
                     storage.snapshot = snapshot;
                     storage.remove(&del);
                     println!("--- After Compaction ---");
                     if size_only {
-                        storage.dump_size_only();
+                        storage.dump_tier_sizes();
                     } else if dump_real_id {
                         storage.dump_real_id(false, false);
                     } else {
@@ -450,6 +1221,13 @@ fn main() {
                     println!("{num_compactions} compaction triggered in this iteration");
                 }
                 max_space = max_space.max(storage.file_list.len());
+                max_space_bytes = max_space_bytes.max(
+                    storage
+                        .file_list
+                        .keys()
+                        .map(|id| storage.snapshot.sstables[id].table_size())
+                        .sum::<u64>(),
+                );
                 println!("--- Statistics ---");
                 println!(
                     "Write Amplification: {}/{}={:.3}x",
@@ -463,6 +1241,14 @@ fn main() {
                     storage.total_flushes,
                     max_space as f64 / storage.total_flushes as f64
                 );
+                println!(
+                    "Write Amplification (bytes): {:.3}x",
+                    written_bytes as f64 / flushed_bytes as f64
+                );
+                println!(
+                    "Maximum Space Usage (bytes): {:.3}x",
+                    max_space_bytes as f64 / flushed_bytes as f64
+                );
                 println!(
                     "Read Amplification: {}x",
                     storage.snapshot.l0_sstables.len()
@@ -674,5 +1460,234 @@ fn main() {
                 println!();
             }
         }
+        #[cfg(feature = "mvcc")]
+        Args::LazyLeveled {
+            dump_real_id,
+            size_only,
+            level_size_multiplier,
+            max_levels,
+            base_level_size_mb,
+            iterations,
+            sst_size_mb,
+        } => {
+            let controller = LazyLeveledCompactionController::new(LazyLeveledCompactionOptions {
+                level_size_multiplier,
+                max_levels,
+                base_level_size_mb,
+            });
+            let mut storage = MockStorage::new();
+            let mut max_space_bytes = 0;
+            let mut flushed_bytes = 0;
+            let mut written_bytes = 0;
+            for i in 0..iterations {
+                println!("=== Iteration {i} ===");
+                let id = storage.flush_sst_to_new_tier();
+                let (first_key, last_key) = generate_random_key_range();
+                let size = sst_size_mb as u64 * 1024 * 1024;
+                storage.snapshot.sstables.insert(
+                    id,
+                    Arc::new(SsTable::create_meta_only(id, size, first_key, last_key)),
+                );
+                flushed_bytes += size;
+                written_bytes += size;
+                println!("--- After Flush ---");
+                if size_only {
+                    storage.dump_tier_sizes();
+                } else if dump_real_id {
+                    storage.dump_real_id(false, false);
+                } else {
+                    storage.dump_original_id(false, false);
+                }
+                let mut num_compactions = 0;
+                while let Some(task) = {
+                    if !size_only {
+                        println!("--- Compaction Task ---");
+                    }
+                    controller.generate_compaction_task(&storage.snapshot)
+                } {
+                    let mut sst_ids = Vec::new();
+                    for (run_id, files) in &task.runs {
+                        for file in files {
+                            // the merged SSTs keep the size of their input, as if no key was overwritten
+                            let input = storage.snapshot.sstables[file].clone();
+                            let new_sst_id = storage.generate_sst_id();
+                            sst_ids.push(new_sst_id);
+                            storage.file_list.insert(new_sst_id, *file);
+                            storage.total_writes += 1;
+                            written_bytes += input.table_size();
+                            storage.snapshot.sstables.insert(
+                                new_sst_id,
+                                Arc::new(SsTable::create_meta_only(
+                                    new_sst_id,
+                                    input.table_size(),
+                                    input.first_key().clone(),
+                                    input.last_key().clone(),
+                                )),
+                            );
+                        }
+                        print!("L{} {:?} ", run_id, files);
+                    }
+                    println!("-> {:?}", sst_ids);
+                    max_space_bytes = max_space_bytes.max(
+                        storage
+                            .file_list
+                            .keys()
+                            .map(|id| storage.snapshot.sstables[id].table_size())
+                            .sum::<u64>(),
+                    );
+                    let (mut snapshot, del) =
+                        controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
+                    for id in &del {
+                        snapshot.sstables.remove(id);
+                    }
+                    storage.snapshot = snapshot;
+                    storage.remove(&del);
+                    println!("--- After Compaction ---");
+                    if size_only {
+                        storage.dump_tier_sizes();
+                    } else if dump_real_id {
+                        storage.dump_real_id(false, false);
+                    } else {
+                        storage.dump_original_id(false, false);
+                    }
+                    num_compactions += 1;
+                    if num_compactions >= level_size_multiplier * max_levels * 2 {
+                        panic!("compaction does not converge?");
+                    }
+                }
+                if num_compactions == 0 {
+                    println!("no compaction triggered");
+                } else {
+                    println!("{num_compactions} compaction triggered in this iteration");
+                }
+                max_space_bytes = max_space_bytes.max(
+                    storage
+                        .file_list
+                        .keys()
+                        .map(|id| storage.snapshot.sstables[id].table_size())
+                        .sum::<u64>(),
+                );
+                println!("--- Statistics ---");
+                println!(
+                    "Write Amplification (bytes): {:.3}x",
+                    written_bytes as f64 / flushed_bytes as f64
+                );
+                println!(
+                    "Maximum Space Usage (bytes): {:.3}x",
+                    max_space_bytes as f64 / flushed_bytes as f64
+                );
+                println!("Read Amplification: {}x", storage.snapshot.levels.len());
+                println!();
+            }
+        }
+        Args::Trace {
+            compaction,
+            trace,
+            num_ops,
+            num_keys,
+            zipf_theta,
+            value_size,
+            delete_percent,
+            seed,
+            memtable_size_mb,
+            sst_size_mb,
+            level0_file_num_compaction_trigger,
+            max_levels,
+            size_ratio_percent,
+            level_size_multiplier,
+            base_level_size_mb,
+            #[cfg(feature = "mvcc")]
+            sst_selection,
+            num_tiers,
+            max_size_amplification_percent,
+            size_ratio,
+            min_merge_width,
+            max_merge_width,
+            report_every,
+            format,
+            output,
+            verbose,
+        } => {
+            let controller = match compaction {
+                TraceCompaction::Simple => TraceController::Simple(
+                    SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+                        size_ratio_percent,
+                        level0_file_num_compaction_trigger,
+                        max_levels,
+                    }),
+                ),
+                #[cfg(feature = "mvcc")]
+                TraceCompaction::Leveled => {
+                    let sst_selection = match sst_selection {
+                        TraceSstSelection::Oldest => SstSelection::Oldest,
+                        TraceSstSelection::MinOverlap => SstSelection::MinOverlap,
+                        TraceSstSelection::RoundRobin => SstSelection::RoundRobin,
+                    };
+                    TraceController::Leveled(LeveledCompactionController::with_sst_selection(
+                        LeveledCompactionOptions {
+                            level0_file_num_compaction_trigger,
+                            level_size_multiplier,
+                            max_levels,
+                            base_level_size_mb,
+                        },
+                        sst_selection.build(),
+                    ))
+                }
+                #[cfg(not(feature = "mvcc"))]
+                TraceCompaction::Leveled => TraceController::Leveled(
+                    LeveledCompactionController::new(LeveledCompactionOptions {
+                        level0_file_num_compaction_trigger,
+                        level_size_multiplier,
+                        max_levels,
+                        base_level_size_mb,
+                    }),
+                ),
+                TraceCompaction::Tiered => TraceController::Tiered(
+                    TieredCompactionController::new(TieredCompactionOptions {
+                        num_tiers,
+                        max_size_amplification_percent,
+                        size_ratio,
+                        min_merge_width,
+                        max_merge_width,
+                    }),
+                ),
+                #[cfg(feature = "mvcc")]
+                TraceCompaction::LazyLeveled => TraceController::LazyLeveled(
+                    LazyLeveledCompactionController::new(LazyLeveledCompactionOptions {
+                        level_size_multiplier,
+                        max_levels,
+                        base_level_size_mb,
+                    }),
+                ),
+            };
+            let mut storage =
+                TraceStorage::new(controller, memtable_size_mb << 20, sst_size_mb << 20);
+            if storage.controller.flush_to_l0() {
+                for i in 0..max_levels {
+                    storage.snapshot.levels.push((i + 1, Vec::new()));
+                }
+            }
+            let writer = ReportWriter::new(format, output.as_ref())?;
+            match trace {
+                Some(path) => {
+                    run_trace(storage, read_trace(&path)?, report_every, writer, verbose)?
+                }
+                None => run_trace(
+                    storage,
+                    generate_zipfian_workload(
+                        num_ops,
+                        num_keys,
+                        zipf_theta,
+                        value_size,
+                        delete_percent,
+                        seed,
+                    ),
+                    report_every,
+                    writer,
+                    verbose,
+                )?,
+            }
+        }
     }
+    Ok(())
 }
@@ -1,3 +1,7 @@
+// The commands and options that only mini-lsm-mvcc has are behind its `mvcc` feature, which the other crates sharing
+// this file through symlinks do not declare.
+#![allow(unexpected_cfgs)]
+
 mod wrapper;
 
 use rustyline::DefaultEditor;
@@ -10,8 +14,16 @@ use mini_lsm_wrapper::compact::{
     CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
     TieredCompactionOptions,
 };
+#[cfg(feature = "mvcc")]
+use mini_lsm_wrapper::compact::{
+    FifoCompactionOptions, LazyLeveledCompactionOptions, SstSelection,
+};
 use mini_lsm_wrapper::iterators::StorageIterator;
 use mini_lsm_wrapper::lsm_storage::{LsmStorageOptions, MiniLsm};
+#[cfg(feature = "mvcc")]
+use mini_lsm_wrapper::rate_limiter::RateLimiterOptions;
+#[cfg(feature = "mvcc")]
+use mini_lsm_wrapper::wal::WalRecoveryMode;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -20,6 +32,10 @@ enum CompactionStrategy {
     Simple,
     Leveled,
     Tiered,
+    #[cfg(feature = "mvcc")]
+    Fifo,
+    #[cfg(feature = "mvcc")]
+    LazyLeveled,
     None,
 }
 
@@ -32,8 +48,14 @@ struct Args {
     compaction: CompactionStrategy,
     #[arg(long)]
     enable_wal: bool,
+    #[cfg(feature = "mvcc")]
+    #[arg(long)]
+    wal_dir: Option<PathBuf>,
     #[arg(long)]
     serializable: bool,
+    #[cfg(feature = "mvcc")]
+    #[arg(long)]
+    rate_limit: Option<usize>,
 }
 
 struct ReplHandler {
@@ -121,6 +143,21 @@ impl ReplHandler {
                 self.lsm.force_full_compaction()?;
                 println!("full compaction success");
             }
+            #[cfg(feature = "mvcc")]
+            Command::Stats => {
+                println!("{}", self.lsm.compaction_stats());
+                println!("{:?}", self.lsm.background_scheduler_state());
+            }
+            #[cfg(feature = "mvcc")]
+            Command::Pause => {
+                self.lsm.pause_background_work();
+                println!("background work paused");
+            }
+            #[cfg(feature = "mvcc")]
+            Command::Continue => {
+                self.lsm.continue_background_work()?;
+                println!("background work continued");
+            }
             Command::Quit | Command::Close => {
                 self.lsm.close()?;
                 std::process::exit(0);
@@ -153,6 +190,12 @@ enum Command {
     Dump,
     Flush,
     FullCompaction,
+    #[cfg(feature = "mvcc")]
+    Stats,
+    #[cfg(feature = "mvcc")]
+    Pause,
+    #[cfg(feature = "mvcc")]
+    Continue,
     Quit,
     Close,
 }
@@ -231,6 +274,16 @@ impl Command {
             ))(i)
         };
 
+        #[cfg(feature = "mvcc")]
+        let command = |i| {
+            alt((
+                command,
+                map(tag_no_case("stats"), |_| Command::Stats),
+                map(tag_no_case("pause"), |_| Command::Pause),
+                map(tag_no_case("continue"), |_| Command::Continue),
+            ))(i)
+        };
+
         command(input)
             .map(|(_, c)| c)
             .map_err(|e| anyhow::anyhow!("{}", e))
@@ -314,41 +367,68 @@ impl ReplBuilder {
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let lsm = MiniLsm::open(
-        args.path,
-        LsmStorageOptions {
-            block_size: 4096,
-            target_sst_size: 2 << 20, // 2MB
-            num_memtable_limit: 3,
-            compaction_options: match args.compaction {
-                CompactionStrategy::None => CompactionOptions::NoCompaction,
-                CompactionStrategy::Simple => {
-                    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
-                        size_ratio_percent: 200,
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                    })
-                }
-                CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
-                    num_tiers: 3,
-                    max_size_amplification_percent: 200,
-                    size_ratio: 1,
-                    min_merge_width: 2,
-                    max_merge_width: None,
-                }),
-                CompactionStrategy::Leveled => {
-                    CompactionOptions::Leveled(LeveledCompactionOptions {
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                        base_level_size_mb: 128,
-                        level_size_multiplier: 2,
-                    })
-                }
-            },
-            enable_wal: args.enable_wal,
-            serializable: args.serializable,
-        },
-    )?;
+    let compaction_options = match args.compaction {
+        CompactionStrategy::None => CompactionOptions::NoCompaction,
+        CompactionStrategy::Simple => CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+        }),
+        CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        }),
+        CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 128,
+            level_size_multiplier: 2,
+        }),
+        #[cfg(feature = "mvcc")]
+        CompactionStrategy::Fifo => CompactionOptions::Fifo(FifoCompactionOptions {
+            max_total_size_mb: 1024,
+            ttl_secs: None,
+        }),
+        #[cfg(feature = "mvcc")]
+        CompactionStrategy::LazyLeveled => {
+            CompactionOptions::LazyLeveled(LazyLeveledCompactionOptions {
+                level_size_multiplier: 4,
+                max_levels: 4,
+                base_level_size_mb: 4,
+            })
+        }
+    };
+    let options = LsmStorageOptions {
+        block_size: 4096,
+        target_sst_size: 2 << 20, // 2MB
+        num_memtable_limit: 3,
+        enable_wal: args.enable_wal,
+        serializable: args.serializable,
+        ..LsmStorageOptions::default_for_week2_test(compaction_options)
+    };
+    #[cfg(feature = "mvcc")]
+    let options = LsmStorageOptions {
+        wal_dir: args.wal_dir,
+        wal_segment_size: 8 << 20, // 8MB
+        wal_recovery_mode: WalRecoveryMode::TolerateCorruptedTailRecords,
+        change_feed_retention: 1024,
+        max_manifest_size: 1 << 20, // 1MB
+        recovery_threads: 8,
+        max_subcompactions: 4,
+        max_background_compactions: 4,
+        max_background_flushes: 2,
+        rate_limiter: args.rate_limit.map(|bytes_per_sec| RateLimiterOptions {
+            bytes_per_sec,
+            auto_tune: false,
+        }),
+        leveled_sst_selection: SstSelection::Oldest,
+        max_grandparent_overlap_bytes: Some(20 << 20), // 20MB
+        ..options
+    };
+    let lsm = MiniLsm::open(args.path, options)?;
 
     let repl = ReplBuilder::new()
         .app_name("mini-lsm-cli")